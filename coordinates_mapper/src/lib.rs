@@ -1,13 +1,17 @@
 // Calculations are based on https://docs.wradlib.org/en/stable/notebooks/radolan/radolan_grid.html as well as trial and error. Damn, geometry is hard!
 
-const RADIUS_OF_EARTH: f64 = 6370.040;
-const LONGITUDE_OF_PROJECTION_ORIGIN: f64 = 10.;
-const LATITUDE_OF_TRUE_SCALE: f64 = 60.;
-const OFFSET_X: f64 = 542.962_166_921_856_4;
-const OFFSET_Y: f64 = -3_609.144_724_265_575;
+/// In km, the earth is assumed to be a sphere
+pub const RADIUS_OF_EARTH: f64 = 6370.040;
+pub const LONGITUDE_OF_PROJECTION_ORIGIN: f64 = 10.;
+pub const LATITUDE_OF_TRUE_SCALE: f64 = 60.;
+/// Position of the north pole in [`StereographicCoordinates`]. One unit of these coordinates is
+/// 1 km, x grows to the east and y to the south.
+pub const OFFSET_X: f64 = 542.962_166_921_856_4;
+pub const OFFSET_Y: f64 = -3_609.144_724_265_575;
 
 /// Coordinates in Latitude and Longitude
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GeographicCoordinates {
     /// N/S
     pub latitude: f64,
@@ -17,6 +21,7 @@ pub struct GeographicCoordinates {
 
 /// Coordinates on the weather map of DWD
 #[derive(Debug, Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StereographicCoordinates {
     pub x: f64,
     pub y: f64,
@@ -52,11 +57,11 @@ impl From<StereographicCoordinates> for GeographicCoordinates {
                 + LONGITUDE_OF_PROJECTION_ORIGIN,
 
             latitude: ((RADIUS_OF_EARTH.powi(2)
-                * (1. + LONGITUDE_OF_PROJECTION_ORIGIN.to_radians().sin()).powi(2)
-                + ((other.x - OFFSET_X).powi(2) + (other.y - OFFSET_Y).powi(2)))
+                * (1. + LATITUDE_OF_TRUE_SCALE.to_radians().sin()).powi(2)
+                - ((other.x - OFFSET_X).powi(2) + (other.y - OFFSET_Y).powi(2)))
                 / (RADIUS_OF_EARTH.powi(2)
-                    * (1. + LONGITUDE_OF_PROJECTION_ORIGIN.to_radians().sin()).powi(2)
-                    - ((other.x - OFFSET_X).powi(2) + (other.y - OFFSET_Y).powi(2))))
+                    * (1. + LATITUDE_OF_TRUE_SCALE.to_radians().sin()).powi(2)
+                    + ((other.x - OFFSET_X).powi(2) + (other.y - OFFSET_Y).powi(2))))
             .asin()
             .to_degrees(),
         }
@@ -70,8 +75,8 @@ mod test {
     macro_rules! assert_float_eq {
         // Explicit epsilon, fail.
         ($a:expr, $b:expr) => {{
-            let (a, b) = ($a, $b);
-            if (a - b).abs() > 1.0e-4 {
+            let (a, b): (f64, f64) = ($a, $b);
+            if a.is_nan() || b.is_nan() || (a - b).abs() > 1.0e-4 {
                 panic!(
                     "assert_float_eq failed comparing {} to {}: {} != {}",
                     stringify!($a),
@@ -115,4 +120,16 @@ mod test {
             assert_float_eq!(converted_geo_coords.latitude, geo_coords.latitude);
         }
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let geo_coords: GeographicCoordinates =
+            serde_json::from_str(r#"{"latitude": 51.0, "longitude": 9.0}"#).unwrap();
+        let stereo_coords = StereographicCoordinates::from(geo_coords);
+        let read_back: StereographicCoordinates =
+            serde_json::from_str(&serde_json::to_string(&stereo_coords).unwrap()).unwrap();
+        assert_float_eq!(read_back.x, 469.5);
+        assert_float_eq!(read_back.y, 599.5);
+    }
 }
@@ -0,0 +1,471 @@
+//! Lazy combinators for [`RainRadarValues`], available via [`RainRadarValuesExt`]. Every adapter
+//! implements [`RainRadarValues`] itself, so they can be chained and then e.g. compressed.
+
+use crate::{CrossIteratorExt, GridInformation, RainRadarValues, TimeInformation};
+
+/// Maps single values, see [`RainRadarValuesExt::map_values`]. Implemented for all closures.
+pub trait ValueMapping {
+    fn map_value(&self, value: Option<u16>) -> Option<u16>;
+}
+
+impl<F: Fn(Option<u16>) -> Option<u16>> ValueMapping for F {
+    fn map_value(&self, value: Option<u16>) -> Option<u16> {
+        self(value)
+    }
+}
+
+/// Replaces missing values by a fixed value, see [`RainRadarValuesExt::fill_missing`]
+#[derive(Debug, Clone, Copy)]
+pub struct FillMissing(pub u16);
+
+impl ValueMapping for FillMissing {
+    fn map_value(&self, value: Option<u16>) -> Option<u16> {
+        Some(value.unwrap_or(self.0))
+    }
+}
+
+/// 1 for values greater than or equal to the threshold, otherwise 0, see
+/// [`RainRadarValuesExt::threshold`]
+#[derive(Debug, Clone, Copy)]
+pub struct Threshold(pub u16);
+
+impl ValueMapping for Threshold {
+    fn map_value(&self, value: Option<u16>) -> Option<u16> {
+        value.map(|value| (value >= self.0) as u16)
+    }
+}
+
+pub trait RainRadarValuesExt: RainRadarValues + Sized {
+    /// Applies a function to every value (including missing ones)
+    fn map_values<M: ValueMapping>(self, mapping: M) -> MapValues<Self, M> {
+        MapValues {
+            source: self,
+            mapping,
+        }
+    }
+
+    /// Makes all values missing for which `keep(x, y)` is false
+    fn mask<M: Fn(usize, usize) -> bool>(self, keep: M) -> Mask<Self, M> {
+        Mask { source: self, keep }
+    }
+
+    /// Moves all values by `shift` in time (so the value at t is available at t + `shift`).
+    /// `shift` has to be a multiple of the interval of the time slots.
+    fn shift_time(self, shift: chrono::Duration) -> TimeShift<Self> {
+        let interval = self.time_information().interval;
+        assert_eq!(
+            shift.num_milliseconds() % interval.num_milliseconds(),
+            0,
+            "Time shift {shift} is no multiple of the interval {interval}"
+        );
+        TimeShift {
+            source: self,
+            shift,
+        }
+    }
+
+    /// Combines the values of two sources with the same grid. Only the time slots available in
+    /// both of them are available.
+    fn zip_with<O: RainRadarValues, F: Fn(Option<u16>, Option<u16>) -> Option<u16>>(
+        self,
+        other: O,
+        combine: F,
+    ) -> ZipWith<Self, O, F> {
+        let grid_information = self.grid_information();
+        let other_grid_information = other.grid_information();
+        assert!(
+            grid_information.width == other_grid_information.width
+                && grid_information.height == other_grid_information.height
+                && self.grid_offset() == other.grid_offset()
+                && self.cell_size() == other.cell_size(),
+            "Only values with the same grid can be combined"
+        );
+        let time_information = self.time_information();
+        let other_time_information = other.time_information();
+        let first_time = time_information
+            .first_time
+            .max(other_time_information.first_time);
+        assert_eq!(
+            time_information.interval, other_time_information.interval,
+            "Intervals of the values to combine differ"
+        );
+        let interval = time_information.interval;
+        let end_time = |time_information: &TimeInformation| {
+            time_information.first_time + interval * time_information.available_time_slots as i32
+        };
+        let end_time = end_time(&time_information).min(end_time(&other_time_information));
+        assert_eq!(
+            (time_information.first_time - other_time_information.first_time).num_milliseconds()
+                % interval.num_milliseconds(),
+            0,
+            "Time slots of the values to combine are not aligned"
+        );
+        ZipWith {
+            first: self,
+            second: other,
+            combine,
+            time_information: TimeInformation {
+                first_time,
+                available_time_slots: ((end_time - first_time).num_milliseconds()
+                    / interval.num_milliseconds())
+                .max(0) as u32,
+                interval,
+            },
+        }
+    }
+
+    /// Maximum of the values of two sources, missing only if both values are missing (see
+    /// [`Self::zip_with`])
+    #[allow(clippy::type_complexity)]
+    fn max_with<O: RainRadarValues>(
+        self,
+        other: O,
+    ) -> ZipWith<Self, O, fn(Option<u16>, Option<u16>) -> Option<u16>> {
+        self.zip_with(other, |first, second| first.max(second))
+    }
+
+    /// Replaces missing values by `value`
+    fn fill_missing(self, value: u16) -> MapValues<Self, FillMissing> {
+        self.map_values(FillMissing(value))
+    }
+
+    /// 1 where values reach `threshold`, 0 where they don't, missing values stay missing
+    fn threshold(self, threshold: u16) -> MapValues<Self, Threshold> {
+        self.map_values(Threshold(threshold))
+    }
+}
+
+impl<T: RainRadarValues> RainRadarValuesExt for T {}
+
+/// See [`RainRadarValuesExt::map_values`]
+pub struct MapValues<S: RainRadarValues, M: ValueMapping> {
+    source: S,
+    mapping: M,
+}
+
+pub struct MapValuesIterator<'a, I: std::iter::Iterator<Item = Option<u16>>, M: ValueMapping> {
+    inner: I,
+    mapping: &'a M,
+}
+
+impl<'a, I: std::iter::Iterator<Item = Option<u16>>, M: ValueMapping> std::iter::Iterator
+    for MapValuesIterator<'a, I, M>
+{
+    type Item = Option<u16>;
+
+    fn next(&mut self) -> Option<Option<u16>> {
+        self.inner.next().map(|value| self.mapping.map_value(value))
+    }
+}
+
+impl<S: RainRadarValues, M: ValueMapping> RainRadarValues for MapValues<S, M> {
+    type Iter<'a, X: crate::Range, Y: crate::Range>
+        = MapValuesIterator<'a, S::Iter<'a, X, Y>, M>
+    where
+        Self: 'a;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        MapValuesIterator {
+            inner: self.source.for_area(time, x, y),
+            mapping: &self.mapping,
+        }
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        self.source.time_information()
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        self.source.grid_information()
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        self.source.grid_offset()
+    }
+
+    fn cell_size(&self) -> usize {
+        self.source.cell_size()
+    }
+}
+
+/// See [`RainRadarValuesExt::mask`]
+pub struct Mask<S: RainRadarValues, M: Fn(usize, usize) -> bool> {
+    source: S,
+    keep: M,
+}
+
+pub struct MaskIterator<
+    'a,
+    I: std::iter::Iterator<Item = Option<u16>>,
+    X: crate::Range,
+    Y: crate::Range,
+    M: Fn(usize, usize) -> bool,
+> {
+    inner: I,
+    positions: crate::CrossProduct<X, Y>,
+    keep: &'a M,
+}
+
+impl<
+        'a,
+        I: std::iter::Iterator<Item = Option<u16>>,
+        X: crate::Range,
+        Y: crate::Range,
+        M: Fn(usize, usize) -> bool,
+    > std::iter::Iterator for MaskIterator<'a, I, X, Y, M>
+{
+    type Item = Option<u16>;
+
+    fn next(&mut self) -> Option<Option<u16>> {
+        let value = self.inner.next()?;
+        let (x, y) = self
+            .positions
+            .next()
+            .expect("Source returned more values than requested");
+        Some(if (self.keep)(x, y) { value } else { None })
+    }
+}
+
+impl<S: RainRadarValues, M: Fn(usize, usize) -> bool> RainRadarValues for Mask<S, M> {
+    type Iter<'a, X: crate::Range, Y: crate::Range>
+        = MaskIterator<'a, S::Iter<'a, X, Y>, X, Y, M>
+    where
+        Self: 'a;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        MaskIterator {
+            inner: self.source.for_area(time, x.clone(), y.clone()),
+            positions: x.cross_product(y),
+            keep: &self.keep,
+        }
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        self.source.time_information()
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        self.source.grid_information()
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        self.source.grid_offset()
+    }
+
+    fn cell_size(&self) -> usize {
+        self.source.cell_size()
+    }
+}
+
+/// See [`RainRadarValuesExt::shift_time`]
+pub struct TimeShift<S: RainRadarValues> {
+    source: S,
+    shift: chrono::Duration,
+}
+
+impl<S: RainRadarValues> RainRadarValues for TimeShift<S> {
+    type Iter<'a, X: crate::Range, Y: crate::Range>
+        = S::Iter<'a, X, Y>
+    where
+        Self: 'a;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        self.source.for_area(time - self.shift, x, y)
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        let time_information = self.source.time_information();
+        TimeInformation {
+            first_time: time_information.first_time + self.shift,
+            available_time_slots: time_information.available_time_slots,
+            interval: time_information.interval,
+        }
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        self.source.grid_information()
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        self.source.grid_offset()
+    }
+
+    fn cell_size(&self) -> usize {
+        self.source.cell_size()
+    }
+}
+
+/// See [`RainRadarValuesExt::zip_with`]
+pub struct ZipWith<
+    A: RainRadarValues,
+    B: RainRadarValues,
+    F: Fn(Option<u16>, Option<u16>) -> Option<u16>,
+> {
+    first: A,
+    second: B,
+    combine: F,
+    time_information: TimeInformation,
+}
+
+pub struct ZipWithIterator<
+    'a,
+    I: std::iter::Iterator<Item = Option<u16>>,
+    J: std::iter::Iterator<Item = Option<u16>>,
+    F: Fn(Option<u16>, Option<u16>) -> Option<u16>,
+> {
+    first: I,
+    second: J,
+    combine: &'a F,
+}
+
+impl<
+        'a,
+        I: std::iter::Iterator<Item = Option<u16>>,
+        J: std::iter::Iterator<Item = Option<u16>>,
+        F: Fn(Option<u16>, Option<u16>) -> Option<u16>,
+    > std::iter::Iterator for ZipWithIterator<'a, I, J, F>
+{
+    type Item = Option<u16>;
+
+    fn next(&mut self) -> Option<Option<u16>> {
+        match (self.first.next(), self.second.next()) {
+            (Some(first), Some(second)) => Some((self.combine)(first, second)),
+            (None, None) => None,
+            _ => panic!("Sources returned a different number of values"),
+        }
+    }
+}
+
+impl<A: RainRadarValues, B: RainRadarValues, F: Fn(Option<u16>, Option<u16>) -> Option<u16>>
+    RainRadarValues for ZipWith<A, B, F>
+{
+    type Iter<'a, X: crate::Range, Y: crate::Range>
+        = ZipWithIterator<'a, A::Iter<'a, X, Y>, B::Iter<'a, X, Y>, F>
+    where
+        Self: 'a;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        ZipWithIterator {
+            first: self.first.for_area(time, x.clone(), y.clone()),
+            second: self.second.for_area(time, x, y),
+            combine: &self.combine,
+        }
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        self.time_information
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        self.first.grid_information()
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        self.first.grid_offset()
+    }
+
+    fn cell_size(&self) -> usize {
+        self.first.cell_size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_values::TestRainRadarValues;
+    use crate::CompressedRainRadarValues;
+
+    #[test]
+    fn test_adapters() {
+        let first = TestRainRadarValues::with_size(13, 420, 300, 3);
+        let second = TestRainRadarValues::with_size(14, 420, 300, 3)
+            .with_first_time(first.time_information().first_time + chrono::Duration::minutes(5));
+        let first_time = first.time_information().first_time;
+        let time = first_time + chrono::Duration::minutes(5);
+        let doubled = (&first).map_values(|value: Option<u16>| value.map(|value| value * 2));
+        assert!(doubled.for_area(time, 0..420, 0..300).eq(first
+            .for_area(time, 0..420, 0..300)
+            .map(|value| value.map(|value| value * 2))));
+
+        let masked = (&first).mask(|x, y| x >= 200 && y < 150);
+        for ((x, y), (value, masked_value)) in (10..410).cross_product(20..280).zip(
+            first
+                .for_area(time, 10..410, 20..280)
+                .zip(masked.for_area(time, 10..410, 20..280)),
+        ) {
+            assert_eq!(masked_value, if x >= 200 && y < 150 { value } else { None });
+        }
+
+        let shifted = (&first).shift_time(chrono::Duration::minutes(10));
+        assert_eq!(
+            shifted.time_information().first_time,
+            first_time + chrono::Duration::minutes(10)
+        );
+        assert!(shifted
+            .for_area(first_time + chrono::Duration::minutes(15), 0..420, 0..300)
+            .eq(first.for_area(time, 0..420, 0..300)));
+
+        // the top left corner of the test values is missing
+        assert!(first
+            .for_area(time, 0..420, 0..300)
+            .any(|value| value.is_none()));
+
+        // only 12:10 and 12:15 are available in both
+        let max = (&first).max_with(&second);
+        assert_eq!(
+            max.available_times().collect::<Vec<_>>(),
+            [time, time + chrono::Duration::minutes(5)]
+        );
+        for ((first_value, second_value), max_value) in first
+            .for_area(time, 0..420, 0..300)
+            .zip(second.for_area(time, 0..420, 0..300))
+            .zip(max.for_area(time, 0..420, 0..300))
+        {
+            assert_eq!(max_value, first_value.max(second_value));
+        }
+
+        let filled = (&first).fill_missing(0);
+        assert!(filled
+            .for_area(time, 0..420, 0..300)
+            .all(|value| value.is_some()));
+
+        // adapters can be chained and compressed
+        let chained = first
+            .mask(|x, _| x < 400)
+            .shift_time(chrono::Duration::minutes(5))
+            .threshold(50);
+        let compressed = CompressedRainRadarValues::from_rain_radar_values(&chained);
+        crate::test_values::assert_same_values(&chained, &compressed);
+        let later = time + chrono::Duration::minutes(5);
+        assert!(chained
+            .for_area(later, 0..420, 0..300)
+            .flatten()
+            .all(|value| value <= 1));
+        assert!(chained
+            .for_area(later, 0..420, 0..300)
+            .any(|value| value == Some(1)));
+        assert!(chained
+            .for_area(later, 400..420, 0..300)
+            .all(|value| value.is_none()));
+    }
+}
@@ -0,0 +1,428 @@
+//! Animated loops of rendered time slots as GIF, APNG or (with the `webp` feature) WebP.
+
+use crate::render::{Image, RenderOptions};
+use crate::RainRadarValues;
+use anyhow::{bail, ensure, Context, Result};
+
+#[derive(Debug, Clone)]
+pub struct AnimationOptions {
+    /// The area, scale and palette of the frames. The timestamp is drawn into each frame by
+    /// default.
+    pub render: RenderOptions,
+    pub frame_delay: std::time::Duration,
+    /// Delay of the last frame, so the end of the loop is noticeable
+    pub last_frame_delay: std::time::Duration,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            render: RenderOptions {
+                timestamp: true,
+                ..Default::default()
+            },
+            frame_delay: std::time::Duration::from_millis(500),
+            last_frame_delay: std::time::Duration::from_secs(2),
+        }
+    }
+}
+
+/// Rendered frames, ordered by time, that are repeated endlessly
+pub struct Animation {
+    frames: Vec<(chrono::NaiveDateTime, Image)>,
+    frame_delay: std::time::Duration,
+    last_frame_delay: std::time::Duration,
+}
+
+impl Animation {
+    /// All time slots of `forecast`, preceded by the first time slot (lead time 0) of each of the
+    /// `observed` values that is older than the forecast (e.g. from previous downloads)
+    pub fn from_rain_radar_values<'a, T: RainRadarValues, O: RainRadarValues + 'a>(
+        forecast: &T,
+        observed: impl IntoIterator<Item = &'a O>,
+        options: &AnimationOptions,
+    ) -> Result<Self> {
+        let forecast_start = forecast.time_information().first_time;
+        let mut observed: Vec<(chrono::NaiveDateTime, &O)> = observed
+            .into_iter()
+            .map(|values| (values.time_information().first_time, values))
+            .filter(|(time, _)| *time < forecast_start)
+            .collect();
+        observed.sort_by_key(|(time, _)| *time);
+        observed.dedup_by_key(|(time, _)| *time);
+
+        // checked before rendering, which panics for areas outside of the grid
+        let grid_information = forecast.grid_information();
+        let grid_offset = forecast.grid_offset();
+        if let Some((x, y)) = &options.render.area {
+            ensure!(
+                x.start < x.end
+                    && y.start < y.end
+                    && x.end <= grid_information.width
+                    && y.end <= grid_information.height,
+                "Area {x:?}, {y:?} is not within the {}x{} grid of the forecast",
+                grid_information.width,
+                grid_information.height
+            );
+        }
+        for (time, values) in &observed {
+            ensure!(
+                values.grid_information() == grid_information
+                    && values.grid_offset() == grid_offset,
+                "Observed values of {time} cover a different area than the forecast"
+            );
+        }
+
+        let mut frames = Vec::new();
+        for (time, values) in observed {
+            frames.push((
+                time,
+                Image::from_rain_radar_values(values, time, &options.render),
+            ));
+        }
+        for time in forecast.available_times() {
+            frames.push((
+                time,
+                Image::from_rain_radar_values(forecast, time, &options.render),
+            ));
+        }
+        ensure!(!frames.is_empty(), "No time slots to animate");
+        Ok(Self {
+            frames,
+            frame_delay: options.frame_delay,
+            last_frame_delay: options.last_frame_delay,
+        })
+    }
+
+    pub fn times(&self) -> impl std::iter::Iterator<Item = chrono::NaiveDateTime> + '_ {
+        self.frames.iter().map(|(time, _)| *time)
+    }
+
+    pub fn frames(&self) -> impl std::iter::Iterator<Item = &Image> {
+        self.frames.iter().map(|(_, image)| image)
+    }
+
+    fn delay(&self, index: usize) -> std::time::Duration {
+        if index + 1 == self.frames.len() {
+            self.last_frame_delay
+        } else {
+            self.frame_delay
+        }
+    }
+
+    fn size(&self) -> (usize, usize) {
+        let image = &self.frames[0].1;
+        (image.width(), image.height())
+    }
+
+    /// GIF only knows fully transparent and opaque pixels: Colours with an alpha value of 0 are
+    /// transparent, all others opaque. At most 255 different colours are supported, which is
+    /// plenty for the palettes of [`crate::render`].
+    pub fn write_gif<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        let (width, height) = self.size();
+        let width: u16 = width.try_into().context("Animation is too wide for GIF")?;
+        let height: u16 = height.try_into().context("Animation is too high for GIF")?;
+
+        // index 0 is transparent
+        let mut colours: Vec<[u8; 3]> = vec![[0, 0, 0]];
+        let mut indices = std::collections::HashMap::new();
+        let mut frames = Vec::with_capacity(self.frames.len());
+        for image in self.frames() {
+            let mut buffer = Vec::with_capacity(image.pixels().len());
+            for &[r, g, b, a] in image.pixels() {
+                if a == 0 {
+                    buffer.push(0);
+                    continue;
+                }
+                let index = match indices.get(&[r, g, b]) {
+                    Some(&index) => index,
+                    None => {
+                        ensure!(
+                            colours.len() < 256,
+                            "Animation has more than 255 colours, which is not supported by GIF"
+                        );
+                        colours.push([r, g, b]);
+                        let index = (colours.len() - 1) as u8;
+                        indices.insert([r, g, b], index);
+                        index
+                    }
+                };
+                buffer.push(index);
+            }
+            frames.push(buffer);
+        }
+
+        let mut encoder = gif::Encoder::new(writer, width, height, colours.as_flattened())
+            .context("Failed writing GIF header")?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .context("Failed writing GIF header")?;
+        for (index, buffer) in frames.into_iter().enumerate() {
+            let delay: u16 = (self.delay(index).as_millis() / 10)
+                .try_into()
+                .context("Frame delay is too long for GIF")?;
+            encoder
+                .write_frame(&gif::Frame {
+                    width,
+                    height,
+                    delay,
+                    dispose: gif::DisposalMethod::Background,
+                    transparent: Some(0),
+                    buffer: buffer.into(),
+                    ..Default::default()
+                })
+                .context("Failed writing GIF frame")?;
+        }
+        Ok(())
+    }
+
+    pub fn write_apng<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        let (width, height) = self.size();
+        let mut encoder = png::Encoder::new(
+            writer,
+            width.try_into().context("Animation is too wide")?,
+            height.try_into().context("Animation is too high")?,
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        // 0 plays the animation endlessly
+        encoder
+            .set_animated(self.frames.len().try_into()?, 0)
+            .context("Failed setting up APNG")?;
+        encoder
+            .set_dispose_op(png::DisposeOp::Background)
+            .context("Failed setting up APNG")?;
+        let mut writer = encoder
+            .write_header()
+            .context("Failed writing APNG header")?;
+        for (index, image) in self.frames().enumerate() {
+            let delay: u16 = self
+                .delay(index)
+                .as_millis()
+                .try_into()
+                .context("Frame delay is too long for APNG")?;
+            writer
+                .set_frame_delay(delay, 1000)
+                .context("Failed setting frame delay")?;
+            writer
+                .write_image_data(&image.bytes())
+                .context("Failed writing APNG frame")?;
+        }
+        writer.finish().context("Failed finishing APNG")
+    }
+
+    /// Lossless animated WebP. Every frame is encoded on its own and covers the whole canvas.
+    #[cfg(feature = "webp")]
+    pub fn write_webp<W: std::io::Write>(&self, mut writer: W) -> Result<()> {
+        let (width, height) = self.size();
+        // canvas and frame sizes are stored as 24 bit values minus 1
+        ensure!(
+            width <= 1 << 24 && height <= 1 << 24,
+            "Animation is too large for WebP"
+        );
+        let u24 = |value: usize| (value as u32).to_le_bytes()[..3].to_vec();
+
+        // frames are encoded as still images, whose VP8L chunk is taken over into an ANMF chunk
+        let mut frames = Vec::new();
+        for (index, image) in self.frames().enumerate() {
+            let mut still = Vec::new();
+            image_webp::WebPEncoder::new(&mut still)
+                .encode(
+                    &image.bytes(),
+                    width as u32,
+                    height as u32,
+                    image_webp::ColorType::Rgba8,
+                )
+                .context("Failed encoding WebP frame")?;
+            ensure!(
+                still.get(12..16) == Some(b"VP8L"),
+                "Unexpected WebP frame from encoder"
+            );
+            let delay = self.delay(index).as_millis();
+            ensure!(delay < 1 << 24, "Frame delay is too long for WebP");
+            // x and y offset, width - 1, height - 1, duration in ms and flags (no blending, so
+            // transparent pixels don't show the previous frame)
+            let mut frame = [u24(0), u24(0), u24(width - 1), u24(height - 1)].concat();
+            frame.extend_from_slice(&u24(delay as usize));
+            frame.push(0b10);
+            frame.extend_from_slice(&still[12..]);
+            frames.push(frame);
+        }
+
+        let mut chunks = Vec::new();
+        let mut chunk = |name: &[u8; 4], data: &[u8]| {
+            chunks.extend_from_slice(name);
+            chunks.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            chunks.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                chunks.push(0);
+            }
+        };
+        // flags (animation and alpha), reserved, canvas width - 1 and height - 1
+        let vp8x = [vec![0b1_0010, 0, 0, 0], u24(width - 1), u24(height - 1)].concat();
+        chunk(b"VP8X", &vp8x);
+        // transparent background, 0 plays the animation endlessly
+        chunk(b"ANIM", &[0, 0, 0, 0, 0, 0]);
+        for frame in &frames {
+            chunk(b"ANMF", frame);
+        }
+
+        let size: u32 = (4 + chunks.len())
+            .try_into()
+            .context("Animation is too large for WebP")?;
+        writer
+            .write_all(b"RIFF")
+            .and_then(|_| writer.write_all(&size.to_le_bytes()))
+            .and_then(|_| writer.write_all(b"WEBP"))
+            .and_then(|_| writer.write_all(&chunks))
+            .context("Failed writing WebP")
+    }
+
+    /// Chooses the format by the extension of `path`: `gif`, `png`/`apng` for APNG or `webp`
+    pub fn save<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        let write: fn(&Self, std::io::BufWriter<std::fs::File>) -> Result<()> =
+            match extension.as_deref() {
+                Some("gif") => |animation, writer| animation.write_gif(writer),
+                Some("png" | "apng") => |animation, writer| animation.write_apng(writer),
+                #[cfg(feature = "webp")]
+                Some("webp") => |animation, writer| animation.write_webp(writer),
+                #[cfg(not(feature = "webp"))]
+                Some("webp") => bail!("Animated WebP requires the webp feature"),
+                _ => bail!(
+                    "Unknown animation format of {} (expected .gif, .png or .webp)",
+                    path.display()
+                ),
+            };
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed creating {}", path.display()))?;
+        write(self, std::io::BufWriter::new(file))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_values::TestRainRadarValues;
+
+    #[test]
+    fn test_animation() -> Result<()> {
+        let forecast = TestRainRadarValues::with_size(20, 420, 300, 25);
+        let forecast_start = forecast.time_information().first_time;
+        let observed: Vec<TestRainRadarValues> = [-10, -5, -10, 0, 5]
+            .into_iter()
+            .map(|minutes| {
+                TestRainRadarValues::with_size(21, 420, 300, 25)
+                    .with_first_time(forecast_start + chrono::Duration::minutes(minutes))
+            })
+            .collect();
+        let options = AnimationOptions {
+            render: RenderOptions {
+                area: Some((300..420, 200..300)),
+                ..AnimationOptions::default().render
+            },
+            frame_delay: std::time::Duration::from_millis(200),
+            ..Default::default()
+        };
+        let animation = Animation::from_rain_radar_values(&forecast, &observed, &options)?;
+
+        // observed frames once each and only before the forecast
+        let times: Vec<_> = animation.times().collect();
+        assert_eq!(times.len(), 2 + 25);
+        assert_eq!(times[0], forecast_start - chrono::Duration::minutes(10));
+        assert_eq!(times[1], forecast_start - chrono::Duration::minutes(5));
+        assert!(times[2..].iter().copied().eq(forecast.available_times()));
+        assert_eq!(
+            animation.frames().next().unwrap(),
+            &Image::from_rain_radar_values(&observed[0], times[0], &options.render)
+        );
+        // the timestamp makes every frame different
+        assert!(animation
+            .frames()
+            .zip(animation.frames().skip(1))
+            .all(|(a, b)| a != b));
+
+        let mut gif = Vec::new();
+        animation.write_gif(&mut gif)?;
+        let mut decoder = gif::DecodeOptions::new();
+        decoder.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = decoder.read_info(std::io::Cursor::new(gif))?;
+        let mut gif_frames = 0;
+        while let Some(frame) = decoder.read_next_frame()? {
+            let expected = animation.frames().nth(gif_frames).unwrap();
+            assert_eq!(frame.delay, if gif_frames == 26 { 200 } else { 20 });
+            assert_eq!(
+                (frame.width as usize, frame.height as usize),
+                (expected.width(), expected.height())
+            );
+            // transparent pixels are decoded as transparent black
+            assert!(frame
+                .buffer
+                .chunks(4)
+                .zip(expected.pixels())
+                .all(|(actual, expected)| actual[3] == expected[3]
+                    && (expected[3] == 0 || actual == expected)));
+            gif_frames += 1;
+        }
+        assert_eq!(gif_frames, 27);
+
+        let mut apng = Vec::new();
+        animation.write_apng(&mut apng)?;
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(apng)).read_info()?;
+        let animation_control = decoder.info().animation_control.unwrap();
+        assert_eq!(
+            (animation_control.num_frames, animation_control.num_plays),
+            (27, 0)
+        );
+        let mut buffer = vec![0; decoder.output_buffer_size()];
+        for (index, expected) in animation.frames().enumerate() {
+            decoder.next_frame(&mut buffer)?;
+            assert_eq!(buffer, expected.bytes());
+            let frame_control = decoder.info().frame_control.unwrap();
+            assert_eq!(
+                frame_control.delay_num,
+                if index == 26 { 2000 } else { 200 }
+            );
+        }
+
+        #[cfg(feature = "webp")]
+        {
+            let mut webp = Vec::new();
+            animation.write_webp(&mut webp)?;
+            let mut decoder = image_webp::WebPDecoder::new(std::io::Cursor::new(webp))?;
+            assert!(decoder.is_animated() && decoder.has_alpha());
+            assert_eq!(decoder.num_frames(), 27);
+            assert_eq!(decoder.loop_count(), image_webp::LoopCount::Forever);
+            let mut buffer = vec![0; decoder.output_buffer_size().unwrap()];
+            for (index, expected) in animation.frames().enumerate() {
+                let delay = decoder.read_frame(&mut buffer)?;
+                assert_eq!(delay, if index == 26 { 2000 } else { 200 });
+                assert_eq!(buffer, expected.bytes());
+            }
+        }
+        #[cfg(not(feature = "webp"))]
+        assert!(animation.save("radar.webp").is_err());
+
+        // the observed values have to cover the area of the forecast
+        let small = [TestRainRadarValues::with_size(22, 200, 200, 1)
+            .with_first_time(forecast_start - chrono::Duration::minutes(5))];
+        assert!(Animation::from_rain_radar_values(&forecast, &small, &options).is_err());
+        let shifted = TestRainRadarValues::with_size(22, 520, 300, 1)
+            .with_first_time(forecast_start - chrono::Duration::minutes(5));
+        let shifted = [crate::Crop::new(&shifted, 100..520, 0..300)];
+        assert!(Animation::from_rain_radar_values(&forecast, &shifted, &options).is_err());
+        let outside = AnimationOptions {
+            render: RenderOptions {
+                area: Some((300..500, 200..300)),
+                ..options.render.clone()
+            },
+            ..options.clone()
+        };
+        assert!(Animation::from_rain_radar_values(&forecast, &observed, &outside).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,533 @@
+use crate::{CompressedRainRadarValues, RainRadarValues};
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// Format of an archive file:
+// bytes 0 - 7: magic bytes "DWDRRARC"
+// bytes 8 - 11: u32 – format version, always 1
+// bytes 12 - 15: reserved, always 0
+// byte 16 and onwards: records, one per forecast, each consisting of
+//   - bytes 0 - 7: UNIX timestamp for the base time of the forecast
+//   - bytes 8 - 11: u32 – number of time slots in the forecast
+//   - bytes 12 - 15: u32 – seconds between two time slots
+//   - bytes 16 - 23: u64 – length of the forecast data
+//   - bytes 24 - 31: reserved, always 0 (unlike in the index)
+//   - byte 32 and onwards: the forecast as returned by `CompressedRainRadarValues::data`
+// after the last record: the index, for every record its header with bytes 24 - 31 replaced by
+//   the u64 position of the record, followed by the u64 position of the index and the magic
+//   bytes "DWDRRIDX".
+// A record is appended by removing the index, writing the record and then writing a new index, so
+// the index is only missing if writing was interrupted. In that case, the records are read one by
+// one when opening the file, an incomplete record at the end is removed and the index is written
+// again.
+const MAGIC: &[u8; 8] = b"DWDRRARC";
+const INDEX_MAGIC: &[u8; 8] = b"DWDRRIDX";
+const VERSION: u32 = 1;
+const FILE_HEADER_SIZE: u64 = 16;
+const RECORD_HEADER_SIZE: u64 = 32;
+const INDEX_TRAILER_SIZE: u64 = 16;
+
+/// A forecast stored in an [`Archive`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveEntry {
+    /// Time the forecast was issued at (its first time slot)
+    pub base_time: chrono::NaiveDateTime,
+    pub time_slots: u32,
+    pub interval: chrono::Duration,
+    data_offset: u64,
+    data_length: u64,
+}
+
+impl ArchiveEntry {
+    /// Time after the last time slot of the forecast
+    fn end_time(&self) -> chrono::NaiveDateTime {
+        self.base_time + self.interval * self.time_slots as i32
+    }
+
+    fn record_position(&self) -> u64 {
+        self.data_offset - RECORD_HEADER_SIZE
+    }
+
+    fn record_end(&self) -> u64 {
+        self.data_offset + self.data_length
+    }
+
+    fn record_header(&self) -> [u8; RECORD_HEADER_SIZE as usize] {
+        let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+        header[0..8].copy_from_slice(&self.base_time.and_utc().timestamp().to_le_bytes());
+        header[8..12].copy_from_slice(&self.time_slots.to_le_bytes());
+        header[12..16].copy_from_slice(&(self.interval.num_seconds() as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&self.data_length.to_le_bytes());
+        header
+    }
+
+    /// Parses the header of the record at `position`
+    fn from_record_header(header: &[u8], position: u64) -> Result<Self> {
+        let entry = ArchiveEntry {
+            base_time: chrono::DateTime::from_timestamp(
+                i64::from_le_bytes(header[0..8].try_into().unwrap()),
+                0,
+            )
+            .with_context(|| format!("Invalid base time in record header at {position}"))?
+            .naive_utc(),
+            time_slots: u32::from_le_bytes(header[8..12].try_into().unwrap()),
+            interval: chrono::Duration::seconds(u32::from_le_bytes(
+                header[12..16].try_into().unwrap(),
+            ) as i64),
+            data_offset: position + RECORD_HEADER_SIZE,
+            data_length: u64::from_le_bytes(header[16..24].try_into().unwrap()),
+        };
+        ensure!(
+            entry.interval > chrono::Duration::zero(),
+            "Forecast issued at {} has no interval between its time slots",
+            entry.base_time
+        );
+        Ok(entry)
+    }
+}
+
+/// File containing many [`CompressedRainRadarValues`], indexed by the time they were issued at
+pub struct Archive {
+    file: std::fs::File,
+    index: BTreeMap<chrono::NaiveDateTime, ArchiveEntry>,
+    /// Longest time covered by a single forecast, limits the search for covering forecasts
+    max_duration: chrono::Duration,
+    /// End of the last record, where the index starts
+    records_end: u64,
+}
+
+impl Archive {
+    /// Creates a new, empty archive (fails if the file already exists)
+    pub fn create<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .context("Could not create archive file")?;
+        let mut header = Vec::with_capacity(FILE_HEADER_SIZE as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        file.write_all(&header)
+            .context("Failed writing archive header")?;
+        let mut result = Self {
+            file,
+            index: BTreeMap::new(),
+            max_duration: chrono::Duration::zero(),
+            records_end: FILE_HEADER_SIZE,
+        };
+        result.write_index()?;
+        Ok(result)
+    }
+
+    /// Opens an existing archive for reading and appending. If the index is missing because
+    /// appending a forecast was interrupted, the forecasts are read one by one and an incomplete
+    /// forecast at the end is removed.
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .context("Could not open archive file")?;
+        let file_length = file
+            .metadata()
+            .context("Could not read archive metadata")?
+            .len();
+
+        let mut header = [0u8; FILE_HEADER_SIZE as usize];
+        file.read_exact(&mut header)
+            .context("Failed reading archive header")?;
+        ensure!(
+            header[0..8] == *MAGIC,
+            "Not an archive file (found magic bytes {:?})",
+            &header[0..8]
+        );
+        let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+        ensure!(
+            version == VERSION,
+            "Archive format version {version} not supported (expected version {VERSION})"
+        );
+
+        let mut result = Self {
+            file,
+            index: BTreeMap::new(),
+            max_duration: chrono::Duration::zero(),
+            records_end: FILE_HEADER_SIZE,
+        };
+        match result.read_index(file_length)? {
+            Some((entries, records_end)) => {
+                for entry in entries {
+                    result.insert_into_index(entry)?;
+                }
+                result.records_end = records_end;
+            }
+            None => {
+                result.scan_records(file_length)?;
+                result.write_index()?;
+            }
+        }
+        Ok(result)
+    }
+
+    /// The entries of the index and its position, None if there is no complete index
+    fn read_index(&mut self, file_length: u64) -> Result<Option<(Vec<ArchiveEntry>, u64)>> {
+        if file_length < FILE_HEADER_SIZE + INDEX_TRAILER_SIZE {
+            return Ok(None);
+        }
+        let mut trailer = [0u8; INDEX_TRAILER_SIZE as usize];
+        self.file
+            .seek(SeekFrom::Start(file_length - INDEX_TRAILER_SIZE))
+            .and_then(|_| self.file.read_exact(&mut trailer))
+            .context("Failed reading the end of the archive index")?;
+        let index_position = u64::from_le_bytes(trailer[0..8].try_into().unwrap());
+        let index_end = file_length - INDEX_TRAILER_SIZE;
+        if trailer[8..16] != *INDEX_MAGIC
+            || !(FILE_HEADER_SIZE..=index_end).contains(&index_position)
+            || !(index_end - index_position).is_multiple_of(RECORD_HEADER_SIZE)
+        {
+            return Ok(None);
+        }
+
+        let mut index = vec![0u8; (index_end - index_position) as usize];
+        self.file
+            .seek(SeekFrom::Start(index_position))
+            .and_then(|_| self.file.read_exact(&mut index))
+            .context("Failed reading the archive index")?;
+        let mut entries = Vec::with_capacity(index.len() / RECORD_HEADER_SIZE as usize);
+        for header in index.chunks_exact(RECORD_HEADER_SIZE as usize) {
+            let position = u64::from_le_bytes(header[24..32].try_into().unwrap());
+            let entry = ArchiveEntry::from_record_header(header, position)?;
+            ensure!(
+                position >= FILE_HEADER_SIZE
+                    && entry
+                        .data_offset
+                        .checked_add(entry.data_length)
+                        .is_some_and(|end| end <= index_position),
+                "Index entry of the forecast issued at {} is out of range",
+                entry.base_time
+            );
+            entries.push(entry);
+        }
+        Ok(Some((entries, index_position)))
+    }
+
+    /// Reads the record headers one by one and removes an incomplete record at the end
+    fn scan_records(&mut self, file_length: u64) -> Result<()> {
+        let mut position = FILE_HEADER_SIZE;
+        while file_length - position >= RECORD_HEADER_SIZE {
+            let mut record_header = [0u8; RECORD_HEADER_SIZE as usize];
+            self.file
+                .seek(SeekFrom::Start(position))
+                .and_then(|_| self.file.read_exact(&mut record_header))
+                .with_context(|| format!("Failed reading record header at {position}"))?;
+            // the remains of an index, whose entries contain the position of their record
+            if record_header[24..32] != [0; 8] {
+                break;
+            }
+            let entry = ArchiveEntry::from_record_header(&record_header, position)?;
+            if file_length - entry.data_offset < entry.data_length {
+                break;
+            }
+            position = entry.record_end();
+            self.insert_into_index(entry)?;
+        }
+        self.file
+            .set_len(position)
+            .context("Failed removing the incomplete end of the archive")?;
+        self.records_end = position;
+        Ok(())
+    }
+
+    /// Writes the index after the last record
+    fn write_index(&mut self) -> Result<()> {
+        let mut index = Vec::with_capacity(
+            self.index.len() * RECORD_HEADER_SIZE as usize + INDEX_TRAILER_SIZE as usize,
+        );
+        for entry in self.index.values() {
+            let mut header = entry.record_header();
+            header[24..32].copy_from_slice(&entry.record_position().to_le_bytes());
+            index.extend_from_slice(&header);
+        }
+        index.extend_from_slice(&self.records_end.to_le_bytes());
+        index.extend_from_slice(INDEX_MAGIC);
+        self.file
+            .seek(SeekFrom::Start(self.records_end))
+            .and_then(|_| self.file.write_all(&index))
+            .and_then(|()| self.file.set_len(self.records_end + index.len() as u64))
+            .and_then(|()| self.file.sync_data())
+            .context("Failed writing the archive index")
+    }
+
+    fn insert_into_index(&mut self, entry: ArchiveEntry) -> Result<()> {
+        if self.index.contains_key(&entry.base_time) {
+            bail!(
+                "Archive already contains a forecast issued at {}",
+                entry.base_time
+            );
+        }
+        self.max_duration = self.max_duration.max(entry.end_time() - entry.base_time);
+        self.index.insert(entry.base_time, entry);
+        Ok(())
+    }
+
+    /// Appends a forecast to the archive (fails if there already is a forecast issued at the same
+    /// time)
+    pub fn append(&mut self, values: &CompressedRainRadarValues) -> Result<()> {
+        let time_information = values.time_information();
+        ensure!(
+            !self.index.contains_key(&time_information.first_time),
+            "Archive already contains a forecast issued at {}",
+            time_information.first_time
+        );
+        let interval_seconds = time_information.interval.num_seconds();
+        ensure!(
+            interval_seconds > 0
+                && interval_seconds <= u32::MAX as i64
+                && chrono::Duration::seconds(interval_seconds) == time_information.interval,
+            "Interval {} can't be stored in the archive (only whole seconds are supported)",
+            time_information.interval
+        );
+        let data = values.data();
+        let entry = ArchiveEntry {
+            base_time: time_information.first_time,
+            time_slots: time_information.available_time_slots,
+            interval: time_information.interval,
+            data_offset: self.records_end + RECORD_HEADER_SIZE,
+            data_length: data.len() as u64,
+        };
+
+        // the record replaces the index, which is written again afterwards
+        let written = self
+            .file
+            .set_len(self.records_end)
+            .and_then(|()| self.file.seek(SeekFrom::Start(self.records_end)))
+            .and_then(|_| self.file.write_all(&entry.record_header()))
+            .and_then(|()| self.file.write_all(data))
+            .context("Failed appending forecast to the archive");
+        if written.is_err() {
+            // keep the archive usable without the forecast if possible (e.g. after the disk ran
+            // full), otherwise the index is restored by the next `open`
+            let _ = self.write_index();
+            return written;
+        }
+        self.records_end = entry.record_end();
+        self.insert_into_index(entry)?;
+        self.write_index()
+    }
+
+    /// All forecasts in the archive, ordered by the time they were issued at
+    pub fn entries(&self) -> impl Iterator<Item = &ArchiveEntry> {
+        self.index.values()
+    }
+
+    /// Loads a forecast of this archive
+    pub fn load(&mut self, entry: &ArchiveEntry) -> Result<CompressedRainRadarValues> {
+        self.file
+            .seek(SeekFrom::Start(entry.record_position()))
+            .context("Failed seeking to the forecast")?;
+        let mut record_header = [0u8; RECORD_HEADER_SIZE as usize];
+        self.file
+            .read_exact(&mut record_header)
+            .context("Failed reading the record header")?;
+        ensure!(
+            record_header == entry.record_header(),
+            "Record header of the forecast issued at {} does not match the index",
+            entry.base_time
+        );
+        let mut data = vec![0u8; entry.data_length as usize];
+        self.file
+            .read_exact(&mut data)
+            .context("Failed reading the forecast")?;
+        let values = CompressedRainRadarValues::from_data(&data)
+            .with_context(|| format!("Invalid forecast issued at {}", entry.base_time))?;
+        let time_information = values.time_information();
+        ensure!(
+            time_information.first_time == entry.base_time
+                && time_information.available_time_slots == entry.time_slots
+                && time_information.interval == entry.interval,
+            "Forecast issued at {} does not match its record header",
+            entry.base_time
+        );
+        Ok(values)
+    }
+
+    /// The forecast issued at `time`, if there is one
+    pub fn forecast_issued_at(
+        &mut self,
+        time: chrono::NaiveDateTime,
+    ) -> Result<Option<CompressedRainRadarValues>> {
+        match self.index.get(&time).copied() {
+            Some(entry) => self.load(&entry).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// All forecasts that contain a time slot for `time`, ordered by the time they were issued at
+    pub fn forecasts_covering(
+        &mut self,
+        time: chrono::NaiveDateTime,
+    ) -> Result<Vec<CompressedRainRadarValues>> {
+        let entries: Vec<ArchiveEntry> = self
+            .index
+            .range((time - self.max_duration)..=time)
+            .map(|(_, entry)| *entry)
+            .filter(|entry| {
+                time < entry.end_time()
+                    && (time - entry.base_time).num_seconds() % entry.interval.num_seconds() == 0
+            })
+            .collect();
+        entries.iter().map(|entry| self.load(entry)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_values::TestRainRadarValues;
+
+    fn temporary_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "rain_radar_values_archive_{name}_{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn test_archive() -> Result<()> {
+        let path = temporary_path("test_archive");
+        let first_time = chrono::NaiveDate::from_ymd_opt(2022, 5, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let forecasts: Vec<(TestRainRadarValues, CompressedRainRadarValues)> = (0..4)
+            .map(|index| {
+                let test_values = TestRainRadarValues::with_size(index, 120, 130, 3)
+                    .with_first_time(first_time + chrono::Duration::minutes(5 * index as i64));
+                let compressed = CompressedRainRadarValues::from_rain_radar_values(&test_values);
+                (test_values, compressed)
+            })
+            .collect();
+
+        let mut archive = Archive::create(&path)?;
+        assert!(Archive::create(&path).is_err());
+        // append out of order, the index is sorted anyway
+        archive.append(&forecasts[1].1)?;
+        archive.append(&forecasts[0].1)?;
+        archive.append(&forecasts[2].1)?;
+        assert!(archive.append(&forecasts[2].1).is_err());
+        drop(archive);
+
+        let mut archive = Archive::open(&path)?;
+        archive.append(&forecasts[3].1)?;
+        for archive in [archive, Archive::open(&path)?].iter_mut() {
+            let base_times: Vec<chrono::NaiveDateTime> =
+                archive.entries().map(|entry| entry.base_time).collect();
+            assert_eq!(
+                base_times,
+                forecasts
+                    .iter()
+                    .map(|(test_values, _)| test_values.time_information().first_time)
+                    .collect::<Vec<_>>()
+            );
+
+            for (test_values, _) in &forecasts {
+                let forecast = archive
+                    .forecast_issued_at(test_values.time_information().first_time)?
+                    .expect("Forecast not found");
+                crate::test_values::assert_same_values(test_values, &forecast);
+            }
+            assert!(archive
+                .forecast_issued_at(first_time + chrono::Duration::minutes(1))?
+                .is_none());
+
+            // forecasts 0 - 2 cover 12:10, forecast 3 starts at 12:15
+            let covering =
+                archive.forecasts_covering(first_time + chrono::Duration::minutes(10))?;
+            assert_eq!(covering.len(), 3);
+            for ((test_values, _), forecast) in forecasts.iter().zip(&covering) {
+                crate::test_values::assert_same_values(test_values, forecast);
+            }
+            let covering =
+                archive.forecasts_covering(first_time + chrono::Duration::minutes(25))?;
+            assert_eq!(covering.len(), 1);
+            assert_eq!(
+                covering[0].time_information().first_time,
+                forecasts[3].0.time_information().first_time
+            );
+            assert!(archive
+                .forecasts_covering(first_time + chrono::Duration::minutes(30))?
+                .is_empty());
+            assert!(archive
+                .forecasts_covering(first_time + chrono::Duration::minutes(11))?
+                .is_empty());
+        }
+
+        let complete_length = std::fs::metadata(&path)?.len();
+        let load_all = |archive: &mut Archive| -> Result<()> {
+            let entries: Vec<ArchiveEntry> = archive.entries().copied().collect();
+            assert_eq!(entries.len(), forecasts.len());
+            for ((test_values, _), entry) in forecasts.iter().zip(&entries) {
+                crate::test_values::assert_same_values(test_values, &archive.load(entry)?);
+            }
+            Ok(())
+        };
+
+        // an interrupted append leaves an incomplete record without index, which is removed
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        let records_end = complete_length - INDEX_TRAILER_SIZE - 4 * RECORD_HEADER_SIZE;
+        file.set_len(records_end)?;
+        file.seek(SeekFrom::Start(records_end))?;
+        let next_forecast = CompressedRainRadarValues::from_rain_radar_values(
+            &TestRainRadarValues::with_size(4, 120, 130, 3)
+                .with_first_time(first_time + chrono::Duration::minutes(20)),
+        );
+        let record = ArchiveEntry {
+            base_time: first_time + chrono::Duration::minutes(20),
+            time_slots: 3,
+            interval: chrono::Duration::minutes(5),
+            data_offset: records_end + RECORD_HEADER_SIZE,
+            data_length: next_forecast.data().len() as u64,
+        };
+        file.write_all(&record.record_header())?;
+        file.write_all(&next_forecast.data()[..100])?;
+        drop(file);
+        let mut archive = Archive::open(&path)?;
+        load_all(&mut archive)?;
+        assert_eq!(std::fs::metadata(&path)?.len(), complete_length);
+        // appending works again afterwards
+        archive.append(&next_forecast)?;
+        assert_eq!(Archive::open(&path)?.entries().count(), 5);
+        drop(archive);
+
+        // without the index (e.g. cut off), the records are read one by one
+        let length = std::fs::metadata(&path)?.len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(length - 1)?;
+        let archive = Archive::open(&path)?;
+        assert_eq!(archive.entries().count(), 5);
+        assert_eq!(std::fs::metadata(&path)?.len(), length);
+        drop(archive);
+
+        // the index is used when opening, a damaged record is only noticed when loading it
+        let mut file = std::fs::OpenOptions::new().write(true).open(&path)?;
+        file.seek(SeekFrom::Start(FILE_HEADER_SIZE + 8))?;
+        file.write_all(&7u32.to_le_bytes())?;
+        drop(file);
+        let mut archive = Archive::open(&path)?;
+        let entries: Vec<ArchiveEntry> = archive.entries().copied().collect();
+        assert_eq!(entries.len(), 5);
+        // the first record is forecast 1, appended first
+        let error = archive.load(&entries[1]).err().unwrap();
+        assert!(error.to_string().contains("does not match the index"));
+        assert!(archive.load(&entries[0]).is_ok());
+
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
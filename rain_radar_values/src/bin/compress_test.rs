@@ -0,0 +1,314 @@
+use std::time::{Duration, Instant};
+
+use rain_radar_values::{
+    CompressedRainRadarValues, Compression, DWDRainRadarValues, EncoderOptions, Quantisation,
+    RainRadarValues,
+};
+
+/// The encodings that are compared
+fn configurations() -> Vec<(&'static str, EncoderOptions)> {
+    vec![
+        ("plain", EncoderOptions::default()),
+        (
+            "deduplicated",
+            EncoderOptions {
+                deduplicate_blocks: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "lzma",
+            EncoderOptions {
+                compression: Compression::Lzma(6),
+                ..Default::default()
+            },
+        ),
+        (
+            "lzma+dedup+delta",
+            EncoderOptions {
+                compression: Compression::Lzma(6),
+                deduplicate_blocks: true,
+                temporal_delta: true,
+                ..Default::default()
+            },
+        ),
+        (
+            "lzma+dedup+delta, block size 50",
+            EncoderOptions {
+                compression: Compression::Lzma(6),
+                deduplicate_blocks: true,
+                temporal_delta: true,
+                block_size: 50,
+                ..Default::default()
+            },
+        ),
+        (
+            "lzma+dedup+delta, step 5 (lossy)",
+            EncoderOptions {
+                compression: Compression::Lzma(6),
+                deduplicate_blocks: true,
+                temporal_delta: true,
+                quantisation: Quantisation::Step(5),
+                ..Default::default()
+            },
+        ),
+    ]
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Verification {
+    Lossless,
+    /// Values differ, but at most by the maximum quantisation error stored in the file
+    WithinQuantisationError(u16),
+    Failed(String),
+}
+
+#[derive(Debug, serde::Serialize)]
+struct FileResult {
+    file: std::path::PathBuf,
+    configuration: &'static str,
+    original_size: u64,
+    compressed_size: usize,
+    number_of_values: u64,
+    #[serde(rename = "encode_seconds", serialize_with = "seconds")]
+    encode_duration: Duration,
+    #[serde(rename = "decode_seconds", serialize_with = "seconds")]
+    decode_duration: Duration,
+    verification: Verification,
+}
+
+fn test_file(file_path: &std::path::Path) -> Vec<FileResult> {
+    let original_size = std::fs::metadata(file_path)
+        .unwrap_or_else(|err| panic!("Failed reading metadata of {file_path:?}: {err}"))
+        .len();
+    let values = DWDRainRadarValues::from_file(file_path)
+        .unwrap_or_else(|err| panic!("Failed loading {file_path:?}: {err}"));
+    let grid_information = values.grid_information();
+    let number_of_values = values.time_information().available_time_slots as u64
+        * (grid_information.width * grid_information.height) as u64;
+
+    configurations()
+        .into_iter()
+        .map(|(configuration, options)| {
+            let start = Instant::now();
+            let compressed =
+                CompressedRainRadarValues::from_rain_radar_values_with_options(&values, &options);
+            let encode_duration = start.elapsed();
+
+            // decoding includes parsing the header and reading back every value
+            let start = Instant::now();
+            let decoded = CompressedRainRadarValues::from_data(compressed.data())
+                .unwrap_or_else(|err| panic!("Failed decoding {file_path:?}: {err}"));
+            let decoded_values: Vec<Option<u16>> = decoded
+                .available_times()
+                .flat_map(|time| {
+                    decoded.for_area(time, 0..grid_information.width, 0..grid_information.height)
+                })
+                .collect();
+            let decode_duration = start.elapsed();
+
+            let original_values = values.available_times().flat_map(|time| {
+                values.for_area(time, 0..grid_information.width, 0..grid_information.height)
+            });
+            let verification = verify(original_values, decoded_values, &decoded);
+
+            FileResult {
+                file: file_path.to_owned(),
+                configuration,
+                original_size,
+                compressed_size: compressed.data().len(),
+                number_of_values,
+                encode_duration,
+                decode_duration,
+                verification,
+            }
+        })
+        .collect()
+}
+
+fn verify(
+    original_values: impl Iterator<Item = Option<u16>>,
+    decoded_values: Vec<Option<u16>>,
+    decoded: &CompressedRainRadarValues,
+) -> Verification {
+    let allowed_error = decoded.max_quantisation_error();
+    let mut max_error = 0;
+    let mut number_of_values = 0;
+    for (index, (original, decoded)) in original_values.zip(&decoded_values).enumerate() {
+        number_of_values += 1;
+        match (original, *decoded) {
+            (None, None) => {}
+            (Some(original), Some(decoded)) => {
+                let error = original.abs_diff(decoded);
+                if error > allowed_error {
+                    return Verification::Failed(format!(
+                        "value {index}: {original} decoded as {decoded}"
+                    ));
+                }
+                max_error = u16::max(max_error, error);
+            }
+            (original, decoded) => {
+                return Verification::Failed(format!(
+                    "value {index}: {original:?} decoded as {decoded:?}"
+                ))
+            }
+        }
+    }
+    if number_of_values != decoded_values.len() {
+        Verification::Failed(format!(
+            "{} values decoded instead of {number_of_values}",
+            decoded_values.len()
+        ))
+    } else if max_error == 0 {
+        Verification::Lossless
+    } else {
+        Verification::WithinQuantisationError(max_error)
+    }
+}
+
+/// Sum of the results of all files for one configuration
+#[derive(Debug, Default, serde::Serialize)]
+struct ConfigurationSummary {
+    files: usize,
+    original_size: u64,
+    compressed_size: u64,
+    number_of_values: u64,
+    #[serde(rename = "encode_seconds", serialize_with = "seconds")]
+    encode_duration: Duration,
+    #[serde(rename = "decode_seconds", serialize_with = "seconds")]
+    decode_duration: Duration,
+    max_error: u16,
+    failures: Vec<String>,
+}
+
+impl ConfigurationSummary {
+    fn add(&mut self, result: &FileResult) {
+        self.files += 1;
+        self.original_size += result.original_size;
+        self.compressed_size += result.compressed_size as u64;
+        self.number_of_values += result.number_of_values;
+        self.encode_duration += result.encode_duration;
+        self.decode_duration += result.decode_duration;
+        match &result.verification {
+            Verification::Lossless => {}
+            Verification::WithinQuantisationError(error) => {
+                self.max_error = u16::max(self.max_error, *error)
+            }
+            Verification::Failed(reason) => self
+                .failures
+                .push(format!("{}: {reason}", result.file.display())),
+        }
+    }
+
+    fn ratio(&self) -> f64 {
+        self.compressed_size as f64 / self.original_size as f64
+    }
+
+    /// Million values per second
+    fn throughput(&self, duration: Duration) -> f64 {
+        self.number_of_values as f64 / duration.as_secs_f64() / 1_000_000.
+    }
+
+    fn verification(&self) -> String {
+        if !self.failures.is_empty() {
+            format!("FAILED ({} files)", self.failures.len())
+        } else if self.max_error == 0 {
+            "lossless".to_owned()
+        } else {
+            format!("lossy, max error {}", self.max_error)
+        }
+    }
+}
+
+/// Durations are written as seconds
+fn seconds<S: serde::Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// A summary with the values derived from it, as written to the JSON file
+#[derive(serde::Serialize)]
+struct SummaryReport<'a> {
+    configuration: &'static str,
+    #[serde(flatten)]
+    summary: &'a ConfigurationSummary,
+    // NaN (e.g. without any files) is written as null
+    ratio: f64,
+    encode_million_values_per_second: f64,
+    decode_million_values_per_second: f64,
+    lossless: bool,
+}
+
+#[derive(serde::Serialize)]
+struct Report<'a> {
+    summaries: Vec<SummaryReport<'a>>,
+    files: &'a [FileResult],
+}
+
+fn main() {
+    // not written to DWD_DOWNLOADER_TARGET_DIRECTORY, as every entry there is expected to be a directory of downloads
+    let json_path: std::path::PathBuf = std::env::args_os()
+        .nth(1)
+        .unwrap_or_else(|| "compress_test.json".into())
+        .into();
+
+    // Files are tested one after another, so the measured throughput isn't distorted by other
+    // encoders and decoders running at the same time
+    let results: Vec<FileResult> = rain_radar_values::local_file_analysis::selected_files()
+        .into_iter()
+        .flat_map(|file_path| test_file(file_path))
+        .collect();
+
+    let mut summaries: Vec<(&'static str, ConfigurationSummary)> = configurations()
+        .into_iter()
+        .map(|(configuration, _)| (configuration, ConfigurationSummary::default()))
+        .collect();
+    for result in &results {
+        summaries
+            .iter_mut()
+            .find(|(configuration, _)| *configuration == result.configuration)
+            .expect("Unknown configuration")
+            .1
+            .add(result);
+    }
+
+    println!(
+        "{:<34} {:>6} {:>14} {:>14} {:>8} {:>12} {:>12}  verification",
+        "configuration", "files", "tar.bz2 bytes", "bytes", "ratio", "encode MV/s", "decode MV/s"
+    );
+    for (configuration, summary) in &summaries {
+        println!(
+            "{:<34} {:>6} {:>14} {:>14} {:>8.3} {:>12.2} {:>12.2}  {}",
+            configuration,
+            summary.files,
+            summary.original_size,
+            summary.compressed_size,
+            summary.ratio(),
+            summary.throughput(summary.encode_duration),
+            summary.throughput(summary.decode_duration),
+            summary.verification(),
+        );
+        for failure in &summary.failures {
+            println!("    {failure}");
+        }
+    }
+
+    let report = Report {
+        summaries: summaries
+            .iter()
+            .map(|(configuration, summary)| SummaryReport {
+                configuration,
+                summary,
+                ratio: summary.ratio(),
+                encode_million_values_per_second: summary.throughput(summary.encode_duration),
+                decode_million_values_per_second: summary.throughput(summary.decode_duration),
+                lossless: summary.failures.is_empty() && summary.max_error == 0,
+            })
+            .collect(),
+        files: &results,
+    };
+    let json = serde_json::to_string(&report).expect("Failed serialising results");
+    std::fs::write(&json_path, json + "\n")
+        .unwrap_or_else(|err| panic!("Failed writing {json_path:?}: {err}"));
+    println!("Machine-readable results written to {json_path:?}");
+}
@@ -32,7 +32,7 @@ fn main() {
                     .strip_prefix(&target_directory)
                     .expect("Failed stripping prefix"),
             );
-            let values = rain_radar_values::DWDRainRadarValues::from_file(&file_path)
+            let values = rain_radar_values::DWDRainRadarValues::from_file(file_path)
                 .unwrap_or_else(|err| panic!("Failed loading {file_path:?}: {err}"));
             values.to_bmp(output_dir);
 
@@ -41,18 +41,19 @@ fn main() -> Result<()> {
         let begin_crawling_at_time = now - chrono::Duration::hours(48); // determined by gut feeling
 
         // round down to nearest 15 minutes
-        let midnight = NaiveTime::from_hms(0, 0, 0);
+        let midnight = NaiveTime::MIN;
         let time_since_day_began = begin_crawling_at_time
             .time()
             .signed_duration_since(midnight);
         let hours = time_since_day_began.num_hours() as u32;
         let minutes = (time_since_day_began.num_minutes() / 15 * 15) as u32 - hours * 60;
-        let rounded_down_time = NaiveTime::from_hms(hours, minutes, 0);
+        let rounded_down_time = NaiveTime::from_hms_opt(hours, minutes, 0)
+            .expect("Rounded down time is invalid (this should be impossible)");
 
-        let begin_crawling_at_time_rounded = chrono::DateTime::<Utc>::from_utc(
-            NaiveDateTime::new(begin_crawling_at_time.date().naive_utc(), rounded_down_time),
-            *begin_crawling_at_time.offset(),
-        );
+        let begin_crawling_at_time_rounded = Utc.from_utc_datetime(&NaiveDateTime::new(
+            begin_crawling_at_time.date_naive(),
+            rounded_down_time,
+        ));
 
         for date_time in std::iter::successors(Some(begin_crawling_at_time_rounded), |last_time| {
             let next_time = *last_time + chrono::Duration::minutes(15);
@@ -97,7 +98,7 @@ fn main() -> Result<()> {
                             .parent()
                             .expect("Could not get parent directory of output file (this should be impossible)");
                         if !parent_directory.is_dir() {
-                            std::fs::create_dir(&parent_directory).with_context(|| {
+                            std::fs::create_dir(parent_directory).with_context(|| {
                                 anyhow!("Failed creating directory {parent_directory:?}")
                             })?;
                         }
@@ -1,154 +1,1569 @@
-use crate::{CrossIteratorExt, RainRadarValues, TimeInformation};
+use crate::{CrossIteratorExt, GridInformation, RainRadarValues, TimeInformation};
+use anyhow::{bail, ensure, Context, Result};
 
-mod aligned_alloc {
-    // from https://stackoverflow.com/a/69544158/4674154
-    use std::alloc::*;
-    use std::ptr::NonNull;
-    pub struct AlignedAlloc<const N: usize>;
-    unsafe impl<const N: usize> Allocator for AlignedAlloc<N> {
-        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-            Global.allocate(layout.align_to(N).unwrap())
+mod aligned_bytes {
+    /// Bytes backed by a `[u16]` allocation, so that u16 values at even positions can be read in
+    /// place
+    pub struct AlignedBytes {
+        words: Box<[u16]>,
+        len: usize,
+    }
+
+    impl AlignedBytes {
+        pub fn from_bytes(bytes: &[u8]) -> Self {
+            let mut result = Self {
+                words: vec![0; bytes.len().div_ceil(2)].into_boxed_slice(),
+                len: bytes.len(),
+            };
+            result.copy_from_slice(bytes);
+            result
+        }
+    }
+
+    impl std::ops::Deref for AlignedBytes {
+        type Target = [u8];
+
+        fn deref(&self) -> &[u8] {
+            // this should be sound: every u16 consists of two initialised bytes and len is at most
+            // twice the number of u16
+            unsafe { std::slice::from_raw_parts(self.words.as_ptr() as *const u8, self.len) }
+        }
+    }
+
+    impl std::ops::DerefMut for AlignedBytes {
+        fn deref_mut(&mut self) -> &mut [u8] {
+            unsafe { std::slice::from_raw_parts_mut(self.words.as_mut_ptr() as *mut u8, self.len) }
+        }
+    }
+}
+use aligned_bytes::AlignedBytes;
+
+/// Second compression stage that is applied to the stored value blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Compression {
+    /// Blocks are stored as they are, so values can be read without decoding anything
+    None,
+    /// Every block is compressed on its own with lzma using the given preset (0 - 9, optionally
+    /// combined with `lzma::EXTREME_PRESET`), so single blocks can still be decoded independently
+    Lzma(u32),
+}
+
+impl Compression {
+    fn id(&self) -> u8 {
+        match self {
+            Compression::None => 0,
+            Compression::Lzma(_) => 1,
+        }
+    }
+}
+
+/// Lossy mapping of the values before they are stored, so that more blocks fit into 8 bit and
+/// compress better
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Quantisation {
+    /// Values are stored exactly
+    None,
+    /// Values are rounded to the nearest multiple of the step
+    Step(u16),
+    /// Values are rounded to intensity classes that grow logarithmically, with the given number of
+    /// classes per doubling of the value (0 keeps its own class). At most
+    /// [`MAX_CLASSES_PER_DOUBLING`], so all classes stay below the marker for missing values.
+    Logarithmic { classes_per_doubling: u16 },
+}
+
+/// Largest number of classes per doubling for [`Quantisation::Logarithmic`]: Values below
+/// 2^16 are in at most 16 * classes per doubling + 1 classes, which have to be less than u16::MAX
+pub const MAX_CLASSES_PER_DOUBLING: u16 = (u16::MAX - 2) / 16;
+
+impl Quantisation {
+    fn id(&self) -> u8 {
+        match self {
+            Quantisation::None => 0,
+            Quantisation::Step(_) => 1,
+            Quantisation::Logarithmic { .. } => 2,
+        }
+    }
+
+    fn parameter(&self) -> u16 {
+        match self {
+            Quantisation::None => 0,
+            Quantisation::Step(step) => *step,
+            Quantisation::Logarithmic {
+                classes_per_doubling,
+            } => *classes_per_doubling,
+        }
+    }
+
+    fn from_id_and_parameter(id: u8, parameter: u16) -> Result<Self> {
+        let quantisation = match id {
+            0 => Quantisation::None,
+            1 => Quantisation::Step(parameter),
+            2 => Quantisation::Logarithmic {
+                classes_per_doubling: parameter,
+            },
+            id => bail!("Unknown quantisation {id}"),
+        };
+        quantisation.validate()?;
+        Ok(quantisation)
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(
+            *self == Quantisation::None || self.parameter() > 0,
+            "Quantisation parameter must not be 0"
+        );
+        if let Quantisation::Logarithmic {
+            classes_per_doubling,
+        } = self
+        {
+            ensure!(
+                *classes_per_doubling <= MAX_CLASSES_PER_DOUBLING,
+                "{classes_per_doubling} classes per doubling exceed the maximum of \
+                 {MAX_CLASSES_PER_DOUBLING}"
+            );
+        }
+        Ok(())
+    }
+
+    /// What is stored instead of `value`
+    fn quantise(&self, value: u16) -> u16 {
+        match self {
+            Quantisation::None => value,
+            Quantisation::Step(step) => {
+                let step = *step as u32;
+                ((value as u32 + step / 2) / step) as u16
+            }
+            Quantisation::Logarithmic {
+                classes_per_doubling,
+            } => {
+                if value == 0 {
+                    0
+                } else {
+                    1 + (*classes_per_doubling as f64 * (value as f64).log2()).round() as u16
+                }
+            }
+        }
+    }
+
+    /// Inverse of [`Self::quantise`], as far as possible
+    fn dequantise(&self, value: u16) -> u16 {
+        match self {
+            Quantisation::None => value,
+            Quantisation::Step(step) => {
+                // u16::MAX marks missing values
+                u32::min(value as u32 * *step as u32, u16::MAX as u32 - 1) as u16
+            }
+            Quantisation::Logarithmic {
+                classes_per_doubling,
+            } => {
+                if value == 0 {
+                    0
+                } else {
+                    f64::min(
+                        2f64.powf((value - 1) as f64 / *classes_per_doubling as f64)
+                            .round(),
+                        (u16::MAX - 1) as f64,
+                    ) as u16
+                }
+            }
+        }
+    }
+}
+
+/// Options for [`CompressedRainRadarValues::from_rain_radar_values_with_options`]
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EncoderOptions {
+    pub compression: Compression,
+    /// Store identical blocks (e.g. the same area in consecutive time slots) only once and let all
+    /// of their offsets point to it
+    pub deduplicate_blocks: bool,
+    /// Store a block as difference to the block at the same position in the previous time slot if
+    /// that is smaller. Only possible with compression, as the differences only pay off after
+    /// entropy coding. Chains of such blocks are limited to [`MAX_DELTA_CHAIN_LENGTH`], so
+    /// reading a block never decompresses more than that many further blocks.
+    pub temporal_delta: bool,
+    /// Edge length of the square blocks the grid is divided into
+    pub block_size: usize,
+    /// Only encode this many time slots from the beginning (all available ones if None)
+    pub time_slots: Option<u32>,
+    /// Lossy quantisation of the values, the introduced error is available via
+    /// [`CompressedRainRadarValues::max_quantisation_error`]
+    pub quantisation: Quantisation,
+    /// Store a [`BlockSummary`] for every block, so
+    /// [`CompressedRainRadarValues::max_in_area`] only needs to read the values of blocks that
+    /// are partially inside of the area
+    pub block_summaries: bool,
+}
+
+impl Default for EncoderOptions {
+    fn default() -> Self {
+        Self {
+            compression: Compression::None,
+            deduplicate_blocks: false,
+            temporal_delta: false,
+            block_size: 100,
+            time_slots: None,
+            quantisation: Quantisation::None,
+            block_summaries: false,
+        }
+    }
+}
+
+const MAGIC: &[u8; 8] = b"DWDRRCMP";
+const VERSION: u32 = 1;
+const HEADER_SIZE: usize = 80;
+
+const FLAG_DELTA_REFERENCES: u8 = 1 << 0;
+const FLAG_32_BIT_OFFSETS: u8 = 1 << 1;
+const FLAG_BLOCK_SUMMARIES: u8 = 1 << 2;
+
+/// Maximum number of delta references that have to be followed to decode a block, a block that
+/// would exceed it is stored on its own instead
+pub const MAX_DELTA_CHAIN_LENGTH: usize = 8;
+
+/// Size of a stored [`BlockSummary`]
+const BLOCK_SUMMARY_SIZE: usize = 16;
+
+/// Block offsets are kept as u32 in memory, regardless of how they are stored
+const OFFSET_ALL_MISSING: u32 = 0xFFFF_FFFF;
+const OFFSET_ALL_ZERO: u32 = 0x7FFF_FFFF;
+const OFFSET_16_BIT: u32 = 1 << 31;
+
+/// Dimensions of the stored grid and how it is divided into blocks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Layout {
+    block_size: usize,
+    width: usize,
+    height: usize,
+    time_slots: usize,
+    /// Time between two time slots
+    interval: chrono::Duration,
+}
+
+impl Layout {
+    fn blocks_x(&self) -> usize {
+        self.width.div_ceil(self.block_size)
+    }
+
+    fn blocks_y(&self) -> usize {
+        self.height.div_ceil(self.block_size)
+    }
+
+    fn blocks_per_time_slot(&self) -> usize {
+        self.blocks_x() * self.blocks_y()
+    }
+
+    fn number_of_blocks(&self) -> usize {
+        self.time_slots * self.blocks_per_time_slot()
+    }
+
+    fn values_per_block(&self) -> usize {
+        self.block_size * self.block_size
+    }
+
+    /// Distance between two stored blocks without compression. Always even, so 16 bit blocks
+    /// stay aligned.
+    fn step_size(&self) -> usize {
+        self.values_per_block() + self.values_per_block() % 2
+    }
+
+    /// Index into the block offsets
+    fn block_index(&self, time_slot: usize, x_block: usize, y_block: usize) -> usize {
+        (time_slot * self.blocks_x() + x_block) * self.blocks_y() + y_block
+    }
+
+    /// Whether the value with the given index inside a block is part of the grid (blocks at the
+    /// right and bottom edge may extend beyond it)
+    fn is_in_grid(&self, x_block: usize, y_block: usize, index: usize) -> bool {
+        x_block * self.block_size + index % self.block_size < self.width
+            && y_block * self.block_size + index / self.block_size < self.height
+    }
+}
+
+/// Location of the values of a block, as stored in the block offsets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BlockLocation {
+    AllMissing,
+    AllZero,
+    Stored { is_16_bit: bool, offset: usize },
+}
+
+impl BlockLocation {
+    fn from_u32(offset: u32) -> Self {
+        match offset {
+            OFFSET_ALL_MISSING => BlockLocation::AllMissing,
+            OFFSET_ALL_ZERO => BlockLocation::AllZero,
+            offset => BlockLocation::Stored {
+                is_16_bit: offset & OFFSET_16_BIT != 0,
+                offset: (offset & !OFFSET_16_BIT) as usize,
+            },
         }
-        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-            Global.deallocate(ptr, layout.align_to(N).unwrap())
+    }
+
+    fn from_u16(offset: u16) -> Self {
+        match offset {
+            0xFFFF => BlockLocation::AllMissing,
+            0x7FFF => BlockLocation::AllZero,
+            offset => BlockLocation::Stored {
+                is_16_bit: offset & 0x8000 != 0,
+                offset: (offset & 0x7FFF) as usize,
+            },
         }
     }
 }
-use aligned_alloc::AlignedAlloc;
+
+/// Kind of values in a block, see [`CompressedRainRadarValues::blocks`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlockClassification {
+    /// All values are missing
+    Missing,
+    /// All values are 0
+    Dry,
+    /// Stored values fit into 8 bit
+    U8,
+    /// Stored values need 16 bit
+    U16,
+}
+
+/// Values of a block in row-major order, as they are stored (so still quantised if the values were
+/// encoded with [`Quantisation`]). Blocks are always square, so blocks at the right and bottom
+/// edge of the grid contain values outside of it, which are missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockValues<'a> {
+    Missing,
+    Dry,
+    /// u8::MAX marks a missing value. Borrowed from the data unless the blocks are compressed.
+    U8(std::borrow::Cow<'a, [u8]>),
+    /// u16::MAX marks a missing value. Borrowed from the data unless the blocks are compressed.
+    U16(std::borrow::Cow<'a, [u16]>),
+}
+
+/// Metadata of [`CompressedRainRadarValues`], see [`CompressedRainRadarValues::header`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Header {
+    pub time_information: TimeInformation,
+    pub grid_information: GridInformation,
+    pub grid_offset: (usize, usize),
+    pub cell_size: usize,
+    pub block_size: usize,
+    /// Whether the blocks are compressed with lzma (the preset is not stored)
+    pub is_compressed: bool,
+    pub quantisation: Quantisation,
+    pub max_quantisation_error: u16,
+    pub has_block_summaries: bool,
+}
+
+/// Summary of the values of a block (as read back, so after quantisation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockSummary {
+    /// None iff all values are missing
+    pub max: Option<u16>,
+    pub sum: u64,
+    /// Number of values greater than 0
+    pub wet_values: u32,
+}
+
+impl BlockSummary {
+    fn from_values(values: &[Option<u16>], quantisation: &Quantisation) -> Self {
+        let mut summary = BlockSummary::default();
+        for value in values.iter().flatten() {
+            let value = quantisation.dequantise(*value);
+            summary.max = Some(summary.max.map_or(value, |max| u16::max(max, value)));
+            summary.sum += value as u64;
+            if value > 0 {
+                summary.wet_values += 1;
+            }
+        }
+        summary
+    }
+
+    fn to_bytes(self) -> [u8; BLOCK_SUMMARY_SIZE] {
+        let mut bytes = [0u8; BLOCK_SUMMARY_SIZE];
+        // values are never u16::MAX, as that marks missing values
+        bytes[0..2].copy_from_slice(&self.max.unwrap_or(u16::MAX).to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.wet_values.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.sum.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Self {
+        BlockSummary {
+            max: match u16::from_le_bytes([bytes[0], bytes[1]]) {
+                u16::MAX => None,
+                max => Some(max),
+            },
+            wet_values: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            sum: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+        }
+    }
+}
+
+/// A block of values at one time slot
+#[derive(Debug, Clone)]
+pub struct Block<'a> {
+    pub time_slot: usize,
+    pub time: chrono::NaiveDateTime,
+    /// Area of the grid the block covers (smaller than the block size at the right and bottom edge)
+    pub x: std::ops::Range<usize>,
+    pub y: std::ops::Range<usize>,
+    pub values: BlockValues<'a>,
+    /// Only available if the values were encoded with [`EncoderOptions::block_summaries`]
+    pub summary: Option<BlockSummary>,
+    block_size: usize,
+    quantisation: Quantisation,
+}
+
+impl<'a> Block<'a> {
+    pub fn classification(&self) -> BlockClassification {
+        match self.values {
+            BlockValues::Missing => BlockClassification::Missing,
+            BlockValues::Dry => BlockClassification::Dry,
+            BlockValues::U8(_) => BlockClassification::U8,
+            BlockValues::U16(_) => BlockClassification::U16,
+        }
+    }
+
+    /// Value at a position of the grid inside of this block
+    pub fn value(&self, x: usize, y: usize) -> Option<u16> {
+        assert!(self.x.contains(&x) && self.y.contains(&y));
+        let index = (y - self.y.start) * self.block_size + (x - self.x.start);
+        let value = match &self.values {
+            BlockValues::Missing => None,
+            BlockValues::Dry => Some(0),
+            BlockValues::U8(values) => match values[index] {
+                u8::MAX => None,
+                value => Some(value as u16),
+            },
+            BlockValues::U16(values) => match values[index] {
+                u16::MAX => None,
+                value => Some(value),
+            },
+        };
+        value.map(|value| self.quantisation.dequantise(value))
+    }
+
+    /// Values of the area covered by the block, in the same order as [`RainRadarValues::for_area`]
+    pub fn for_area(&self) -> impl std::iter::Iterator<Item = Option<u16>> + '_ {
+        self.x
+            .clone()
+            .cross_product(self.y.clone())
+            .map(|(x, y)| self.value(x, y))
+    }
+}
+
+/// Number of decoded blocks an iterator keeps around if blocks are compressed, in addition to a
+/// full row of blocks (so iterating over the whole area decodes every block only once)
+const DECODED_BLOCKS_CACHE_RESERVE: usize = 4;
 
 pub struct CompressedRainRadarValues {
     // format specification: everything is little endian.
-    // bytes 0 - 7: UNIX timestamp for base time
-    // bytes 8 - 6607: [[[u16; 12]; 11]; 25] –
-    //   - location of 100x100 value blocks
-    //   - 0xFFFF => values are all nonexistant
-    //   - 0x7FFF => values are all 0
-    //   - all other values: Highest bit: values are 16 bit iff 1, 8 bit iff 0. Lowest 15 bits: Offset beginning from byte 6608 in 10000 byte steps.
-    // byte 6608 and onwards: The real values in blocks of 100x100, either u8 or u16 (see above), if u8::MAX/u16::MAX: value missing
-    data: Box<[u8], AlignedAlloc<2>>,
+    // bytes 0 - 7: magic bytes "DWDRRCMP"
+    // bytes 8 - 11: u32 – format version, always 1
+    // bytes 12 - 15: reserved, always 0
+    // bytes 16 - 23: UNIX timestamp for base time
+    // byte 24: compression of the stored blocks: 0 => none, 1 => lzma (every block on its own)
+    // byte 25: flags:
+    //   - bit 0 => list of delta references present (only with lzma)
+    //   - bit 1 => block offsets are u32 instead of u16
+    //   - bit 2 => block summaries are present at the end of the data
+    //   - all other bits are 0
+    // bytes 26 - 27: u16 – edge length of the square value blocks
+    // bytes 28 - 31: u32 – number of stored blocks
+    // bytes 32 - 35: u32 – width of the grid
+    // bytes 36 - 39: u32 – height of the grid
+    // bytes 40 - 43: u32 – number of time slots
+    // byte 44: quantisation of the values: 0 => none, 1 => rounded to multiples of a step, 2 => logarithmic classes
+    // byte 45: reserved, always 0
+    // bytes 46 - 47: u16 – parameter of the quantisation (step or classes per doubling of the value, 0 without quantisation)
+    // bytes 48 - 49: u16 – maximum absolute difference between an original value and the value read back
+    // bytes 50 - 51: u16 – edge length of a value in cells of the full DWD grid (see `RainRadarValues::cell_size`)
+    // bytes 52 - 55: u32 – x coordinate of the value at (0, 0) in the full DWD grid (see `RainRadarValues::grid_offset`), a
+    //   multiple of the cell size
+    // bytes 56 - 59: u32 – y coordinate of the value at (0, 0) in the full DWD grid
+    // bytes 60 - 63: u32 – seconds between two time slots
+    // bytes 64 - 79: reserved, always 0
+    // byte 80 and onwards: [[[u16 or u32; blocks in y direction]; blocks in x direction]; time slots] –
+    //   - location of the value blocks. Blocks at the right and bottom edge extend beyond the grid if its size is no multiple of
+    //     the block size, their values outside of the grid are stored as missing.
+    //   - 0xFFFF/0xFFFFFFFF => values are all nonexistant
+    //   - 0x7FFF/0x7FFFFFFF => values are all 0
+    //   - all other values: Highest bit: values are 16 bit iff 1, 8 bit iff 0. Lower bits: Offset of the stored block (see below).
+    //     Several locations may share the same offset if their values are identical.
+    // afterwards:
+    //   - without compression: The real (quantised) values in row-major blocks, either u8 or u16 (see above), if u8::MAX/u16::MAX: value missing.
+    //     The offset counts in steps of (block size)² bytes, rounded up to the next even number, so a 16 bit block takes up two
+    //     steps.
+    //   - with lzma: [u32; number of stored blocks + 1] – start of every compressed block, counted from the end of this list (the last
+    //     entry is the end of the last block). If the delta references flag is set, [u32; number of stored blocks] follows – for every
+    //     block either u32::MAX or the offset of an earlier stored block it is relative to. Afterwards the compressed blocks, each of
+    //     which decompresses to the same bytes as an uncompressed block would be stored (without rounding up) – or, if it has a delta
+    //     reference, to the wrapping differences between those bytes and the ones of the referenced block (converted to the same
+    //     width, u16::MAX turning into u8::MAX). At most `MAX_DELTA_CHAIN_LENGTH` references have to be followed from any block
+    //     to a block without reference. The offset is the index of the block in this list.
+    // if the block summaries flag is set, finally [[[summary; blocks in y direction]; blocks in x direction]; time slots], each
+    //   summary consisting of u16 maximum value (u16::MAX if all values are missing), 2 reserved bytes, u32 number of values
+    //   greater than 0, u64 sum of all values. Values are counted after quantisation.
+    data: AlignedBytes,
+    layout: Layout,
+    quantisation: Quantisation,
+}
+
+enum EncodedBlock {
+    AllMissing,
+    AllZero,
+    Stored {
+        is_16_bit: bool,
+        /// values as they would be stored without compression, used to find identical blocks
+        plain_bytes: Vec<u8>,
+        /// values as they are stored on their own
+        bytes: Vec<u8>,
+        /// values relative to the block at the same position in the previous time slot, if that is
+        /// smaller. Only used if the chain of references doesn't get too long.
+        delta_bytes: Option<Vec<u8>>,
+    },
 }
 
 impl CompressedRainRadarValues {
     pub fn from_rain_radar_values<T: super::RainRadarValues>(from: &T) -> Self {
+        Self::from_rain_radar_values_with_options(from, &EncoderOptions::default())
+    }
+
+    pub fn from_rain_radar_values_with_options<T: super::RainRadarValues>(
+        from: &T,
+        options: &EncoderOptions,
+    ) -> Self {
+        let first_time = from.time_information().first_time;
+        let layout = Self::layout(from, options);
+
+        let encoded_blocks = Self::block_indices(&layout).map(|(time_offset, x_block, y_block)| {
+            Self::encode_block(
+                from,
+                first_time,
+                &layout,
+                time_offset,
+                x_block,
+                y_block,
+                options,
+            )
+        });
+        let mut result = Self::assemble_with_metadata(first_time, layout, encoded_blocks, options);
+        result.set_location(from);
+        result
+    }
+
+    /// Same as [`Self::from_rain_radar_values_with_options`] (including the resulting data), but
+    /// the blocks are encoded concurrently
+    #[cfg(feature = "rayon")]
+    pub fn from_rain_radar_values_parallel<T: super::RainRadarValues + Sync>(
+        from: &T,
+        options: &EncoderOptions,
+    ) -> Self {
+        use rayon::prelude::*;
+
+        let first_time = from.time_information().first_time;
+        let layout = Self::layout(from, options);
+
+        // collecting into a Vec keeps the order of the blocks, so they are assembled exactly like
+        // in the sequential encoder
+        let encoded_blocks: Vec<(EncodedBlock, u16, BlockSummary)> = Self::block_indices(&layout)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|(time_offset, x_block, y_block)| {
+                Self::encode_block(
+                    from,
+                    first_time,
+                    &layout,
+                    time_offset,
+                    x_block,
+                    y_block,
+                    options,
+                )
+            })
+            .collect();
+        let mut result =
+            Self::assemble_with_metadata(first_time, layout, encoded_blocks.into_iter(), options);
+        result.set_location(from);
+        result
+    }
+
+    /// Stores the grid offset and cell size of `from`
+    fn set_location<T: super::RainRadarValues>(&mut self, from: &T) {
+        let (x_offset, y_offset) = from.grid_offset();
+        let cell_size: u16 = from
+            .cell_size()
+            .try_into()
+            .expect("Cell size does not fit into u16");
+        self.data[50..52].copy_from_slice(&cell_size.to_le_bytes());
+        let x_offset: u32 = x_offset
+            .try_into()
+            .expect("Grid offset does not fit into u32");
+        let y_offset: u32 = y_offset
+            .try_into()
+            .expect("Grid offset does not fit into u32");
+        self.data[52..56].copy_from_slice(&x_offset.to_le_bytes());
+        self.data[56..60].copy_from_slice(&y_offset.to_le_bytes());
+    }
+
+    /// (time slot, x block, y block) of all blocks, in the order they are stored in
+    fn block_indices(layout: &Layout) -> impl std::iter::Iterator<Item = (usize, usize, usize)> {
+        let blocks_x = layout.blocks_x();
+        let blocks_y = layout.blocks_y();
+        (0..layout.time_slots).flat_map(move |time_offset| {
+            (0..blocks_x).flat_map(move |x_block| {
+                (0..blocks_y).map(move |y_block| (time_offset, x_block, y_block))
+            })
+        })
+    }
+
+    /// Assembles the blocks and adds the maximum quantisation error and the block summaries
+    fn assemble_with_metadata(
+        first_time: chrono::NaiveDateTime,
+        layout: Layout,
+        encoded_blocks: impl std::iter::Iterator<Item = (EncodedBlock, u16, BlockSummary)>,
+        options: &EncoderOptions,
+    ) -> Self {
+        let mut max_error = 0;
+        let mut summaries = Vec::new();
+        let encoded_blocks =
+            encoded_blocks.map(|(encoded_block, max_quantisation_error, summary)| {
+                max_error = u16::max(max_error, max_quantisation_error);
+                if options.block_summaries {
+                    summaries.push(summary);
+                }
+                encoded_block
+            });
+        let mut result = Self::assemble(first_time, layout, encoded_blocks, options);
+        result.data[48..50].copy_from_slice(&max_error.to_le_bytes());
+        if options.block_summaries {
+            result.data[25] |= FLAG_BLOCK_SUMMARIES;
+            let mut data =
+                Vec::with_capacity(result.data.len() + summaries.len() * BLOCK_SUMMARY_SIZE);
+            data.extend_from_slice(&result.data);
+            for summary in summaries {
+                data.extend_from_slice(&summary.to_bytes());
+            }
+            result.data = AlignedBytes::from_bytes(&data);
+        }
+        result
+    }
+
+    fn layout<T: super::RainRadarValues>(from: &T, options: &EncoderOptions) -> Layout {
         let time_information = from.time_information();
-        assert_eq!(time_information.available_time_slots, 25);
+        let grid_information = from.grid_information();
+        assert!(
+            !options.temporal_delta || options.compression != Compression::None,
+            "Temporal delta encoding requires compression"
+        );
+        if let Err(error) = options.quantisation.validate() {
+            panic!("{error}");
+        }
+        assert!(
+            options.block_size > 0 && options.block_size <= u16::MAX as usize,
+            "Block size {} is not supported",
+            options.block_size
+        );
+        assert!(grid_information.width > 0 && grid_information.height > 0);
+        let time_slots = match options.time_slots {
+            Some(time_slots) => {
+                assert!(time_slots <= time_information.available_time_slots);
+                time_slots
+            }
+            None => time_information.available_time_slots,
+        };
+        let interval = time_information.interval;
+        assert!(
+            interval > chrono::Duration::zero()
+                && chrono::Duration::seconds(interval.num_seconds()) == interval
+                && interval.num_seconds() <= u32::MAX as i64,
+            "Interval {interval} is not a positive number of whole seconds"
+        );
+        Layout {
+            block_size: options.block_size,
+            width: grid_information.width,
+            height: grid_information.height,
+            time_slots: time_slots as usize,
+            interval,
+        }
+    }
+
+    /// Quantised values of a block in row-major order (values outside of the grid are missing) and
+    /// the maximum error introduced by the quantisation
+    fn values_in_block<T: super::RainRadarValues>(
+        from: &T,
+        first_time: chrono::NaiveDateTime,
+        layout: &Layout,
+        time_offset: usize,
+        x_block: usize,
+        y_block: usize,
+        quantisation: &Quantisation,
+    ) -> (Vec<Option<u16>>, u16) {
+        let time = first_time + layout.interval * time_offset as i32;
+        let x_start = x_block * layout.block_size;
+        let x_end = usize::min(x_start + layout.block_size, layout.width);
+        let y_start = y_block * layout.block_size;
+        let y_end = usize::min(y_start + layout.block_size, layout.height);
+
+        let mut values_in_block = vec![None; layout.values_per_block()];
+        let mut max_quantisation_error = 0;
+        for ((x, y), value) in (x_start..x_end)
+            .cross_product(y_start..y_end)
+            .zip(from.for_area(time, x_start..x_end, y_start..y_end))
+        {
+            let value = value.map(|value| {
+                let quantised_value = quantisation.quantise(value);
+                let error = value.abs_diff(quantisation.dequantise(quantised_value));
+                max_quantisation_error = u16::max(max_quantisation_error, error);
+                quantised_value
+            });
+            values_in_block[(y - y_start) * layout.block_size + (x - x_start)] = value;
+        }
+        (values_in_block, max_quantisation_error)
+    }
+
+    /// Returns None if the block does not need to be stored, otherwise whether it needs 16 bit
+    fn needs_16_bit(
+        values_in_block: &[Option<u16>],
+        layout: &Layout,
+        x_block: usize,
+        y_block: usize,
+    ) -> Option<bool> {
+        if values_in_block.iter().all(|value| value.is_none())
+            || values_in_block.iter().enumerate().all(|(index, value)| {
+                *value == Some(0) || !layout.is_in_grid(x_block, y_block, index)
+            })
+        {
+            None
+        } else {
+            Some(values_in_block.iter().any(|value| value.unwrap_or(0) > 254))
+        }
+    }
+
+    fn to_bytes(values_in_block: &[Option<u16>], is_16_bit: bool) -> Vec<u8> {
+        if is_16_bit {
+            values_in_block
+                .iter()
+                .map(|value| value.unwrap_or(u16::MAX))
+                .flat_map(|value| value.to_le_bytes().into_iter())
+                .collect::<Vec<u8>>()
+        } else {
+            values_in_block
+                .iter()
+                .map(|value| value.map(|value| value as u8))
+                .map(|value| value.unwrap_or(u8::MAX))
+                .collect::<Vec<u8>>()
+        }
+    }
 
-        let first_time = (time_information.first_time.timestamp() as u64).to_le_bytes();
+    /// Encodes a block and returns the maximum error introduced by the quantisation and the summary
+    /// of its values
+    fn encode_block<T: super::RainRadarValues>(
+        from: &T,
+        first_time: chrono::NaiveDateTime,
+        layout: &Layout,
+        time_offset: usize,
+        x_block: usize,
+        y_block: usize,
+        options: &EncoderOptions,
+    ) -> (EncodedBlock, u16, BlockSummary) {
+        let (values_in_block, max_quantisation_error) = Self::values_in_block(
+            from,
+            first_time,
+            layout,
+            time_offset,
+            x_block,
+            y_block,
+            &options.quantisation,
+        );
+        let summary = BlockSummary::from_values(&values_in_block, &options.quantisation);
+        let encoded_block = Self::encode_values(
+            from,
+            first_time,
+            layout,
+            time_offset,
+            x_block,
+            y_block,
+            values_in_block,
+            options,
+        );
+        (encoded_block, max_quantisation_error, summary)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn encode_values<T: super::RainRadarValues>(
+        from: &T,
+        first_time: chrono::NaiveDateTime,
+        layout: &Layout,
+        time_offset: usize,
+        x_block: usize,
+        y_block: usize,
+        values_in_block: Vec<Option<u16>>,
+        options: &EncoderOptions,
+    ) -> EncodedBlock {
+        let is_16_bit = match Self::needs_16_bit(&values_in_block, layout, x_block, y_block) {
+            Some(is_16_bit) => is_16_bit,
+            None if values_in_block.iter().all(|value| value.is_none()) => {
+                return EncodedBlock::AllMissing
+            }
+            None => return EncodedBlock::AllZero,
+        };
+        let plain_bytes = Self::to_bytes(&values_in_block, is_16_bit);
+
+        let preset = match options.compression {
+            Compression::None => {
+                return EncodedBlock::Stored {
+                    is_16_bit,
+                    bytes: plain_bytes.clone(),
+                    plain_bytes,
+                    delta_bytes: None,
+                }
+            }
+            Compression::Lzma(preset) => preset,
+        };
+        let compress =
+            |bytes: &[u8]| lzma::compress(bytes, preset).expect("lzma compression failed");
+
+        let bytes = compress(&plain_bytes);
 
-        let mut offsets: Vec<u8> = Vec::with_capacity(6600);
-        let mut current_offset_from_start: u16 = 0;
+        let mut delta_bytes = None;
+        if options.temporal_delta && time_offset > 0 {
+            let (previous_values_in_block, _) = Self::values_in_block(
+                from,
+                first_time,
+                layout,
+                time_offset - 1,
+                x_block,
+                y_block,
+                &options.quantisation,
+            );
+            // the previous block can only be referenced if it is stored
+            if Self::needs_16_bit(&previous_values_in_block, layout, x_block, y_block).is_some() {
+                let reference_bytes = Self::to_bytes(&previous_values_in_block, is_16_bit);
+                let delta = compress(&delta_encode(&plain_bytes, &reference_bytes, is_16_bit));
+                if delta.len() < bytes.len() {
+                    delta_bytes = Some(delta);
+                }
+            }
+        }
+
+        EncodedBlock::Stored {
+            is_16_bit,
+            plain_bytes,
+            bytes,
+            delta_bytes,
+        }
+    }
+
+    /// Puts the encoded blocks (ordered by time, x and y) together
+    fn assemble(
+        first_time: chrono::NaiveDateTime,
+        layout: Layout,
+        encoded_blocks: impl std::iter::Iterator<Item = EncodedBlock>,
+        options: &EncoderOptions,
+    ) -> Self {
+        let first_time = (first_time.and_utc().timestamp() as u64).to_le_bytes();
+
+        let mut offsets: Vec<u32> = Vec::with_capacity(layout.number_of_blocks());
+        let mut next_offset: u32 = 0;
+        let mut block_starts: Vec<u32> = vec![0];
+        let mut delta_references: Vec<u32> = Vec::new();
+        // number of references to follow from every stored block
+        let mut chain_lengths: Vec<usize> = Vec::new();
+        // the length of the plain bytes already tells whether a block is 16 bit
+        let mut known_blocks: std::collections::HashMap<Vec<u8>, u32> =
+            std::collections::HashMap::new();
         let mut values_vec: Vec<u8> = Vec::with_capacity(100000);
 
-        for time_offset in 0..25 {
-            let time =
-                time_information.first_time + chrono::Duration::minutes(time_offset as i64 * 5);
-            for x in 0..11 {
-                for y in 0..12 {
-                    let values_in_block = from
-                        .for_area(time, (x * 100)..((x + 1) * 100), (y * 100)..((y + 1) * 100))
-                        .collect::<Vec<Option<u16>>>();
-                    assert_eq!(values_in_block.len(), 10000);
-
-                    let offset: u16 = if values_in_block.iter().all(|value| *value == None) {
-                        0xFFFF
-                    } else if values_in_block.iter().all(|value| *value == Some(0)) {
-                        0x7FFF
-                    } else if values_in_block.iter().any(|value| value.unwrap_or(0) > 254) {
-                        assert!(current_offset_from_start < 0x7FFF);
-                        let offset = current_offset_from_start | (1 << 15);
-                        current_offset_from_start += 2;
-                        let values_as_bytes_iter = values_in_block
-                            .iter()
-                            .map(|value| value.unwrap_or(u16::MAX))
-                            .flat_map(|value| value.to_le_bytes().into_iter());
-                        debug_assert!(values_as_bytes_iter.clone().count() == 20000);
-                        values_vec.extend(values_as_bytes_iter);
-                        offset
+        for encoded_block in encoded_blocks {
+            let offset: u32 = match encoded_block {
+                EncodedBlock::AllMissing => OFFSET_ALL_MISSING,
+                EncodedBlock::AllZero => OFFSET_ALL_ZERO,
+                EncodedBlock::Stored {
+                    is_16_bit,
+                    plain_bytes,
+                    bytes,
+                    delta_bytes,
+                } => {
+                    let known_offset = if options.deduplicate_blocks {
+                        known_blocks.get(&plain_bytes).copied()
                     } else {
-                        let offset = current_offset_from_start;
-                        current_offset_from_start += 1;
-                        let values_as_bytes_iter = values_in_block
-                            .into_iter()
-                            .map(|value| value.map(|value| value as u8))
-                            .map(|value| value.unwrap_or(u8::MAX))
-                            .flat_map(|value| value.to_le_bytes().into_iter());
-                        debug_assert!(values_as_bytes_iter.clone().count() == 10000);
-                        values_vec.extend(values_as_bytes_iter);
-                        offset
+                        None
                     };
-                    offsets.extend(offset.to_le_bytes().into_iter());
+                    match known_offset {
+                        Some(offset) => offset,
+                        None => {
+                            // same position in the previous time slot
+                            let reference = delta_bytes.is_some().then(|| {
+                                match BlockLocation::from_u32(
+                                    offsets[offsets.len() - layout.blocks_per_time_slot()],
+                                ) {
+                                    BlockLocation::Stored { offset, .. } => offset,
+                                    _ => panic!("Delta reference is not stored"),
+                                }
+                            });
+                            let (bytes, reference) = match (delta_bytes, reference) {
+                                (Some(delta_bytes), Some(reference))
+                                    if chain_lengths[reference] < MAX_DELTA_CHAIN_LENGTH =>
+                                {
+                                    (delta_bytes, Some(reference))
+                                }
+                                _ => (bytes, None),
+                            };
+                            chain_lengths.push(
+                                reference.map_or(0, |reference| chain_lengths[reference] + 1),
+                            );
+
+                            let offset = next_offset;
+                            next_offset += match options.compression {
+                                Compression::None if is_16_bit => 2,
+                                _ => 1,
+                            };
+                            assert!(next_offset <= OFFSET_ALL_ZERO);
+                            values_vec.extend_from_slice(&bytes);
+                            if options.compression == Compression::None {
+                                // keep following blocks aligned
+                                values_vec
+                                    .resize(next_offset as usize * layout.step_size(), u8::MAX);
+                            }
+                            block_starts.push(
+                                values_vec
+                                    .len()
+                                    .try_into()
+                                    .expect("Compressed blocks are bigger than 4 GiB"),
+                            );
+                            delta_references
+                                .push(reference.map_or(u32::MAX, |reference| reference as u32));
+                            let offset = if is_16_bit {
+                                offset | OFFSET_16_BIT
+                            } else {
+                                offset
+                            };
+                            if options.deduplicate_blocks {
+                                known_blocks.insert(plain_bytes, offset);
+                            }
+                            offset
+                        }
+                    }
                 }
-            }
+            };
+            offsets.push(offset);
         }
 
-        assert_eq!(offsets.len(), 6600);
-        assert_eq!(values_vec.len(), current_offset_from_start as usize * 10000);
+        assert_eq!(offsets.len(), layout.number_of_blocks());
+        let number_of_stored_blocks = (block_starts.len() - 1) as u32;
+        let has_32_bit_offsets = next_offset > 0x7FFF;
+
+        let mut flags = 0;
+        if options.temporal_delta {
+            flags |= FLAG_DELTA_REFERENCES;
+        }
+        if has_32_bit_offsets {
+            flags |= FLAG_32_BIT_OFFSETS;
+        }
 
-        let mut data = Vec::new_in(AlignedAlloc::<2>);
+        let mut data = Vec::new();
 
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&VERSION.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
         data.extend_from_slice(&first_time);
-        data.extend_from_slice(&offsets);
+        data.extend_from_slice(&[options.compression.id(), flags]);
+        data.extend_from_slice(&(layout.block_size as u16).to_le_bytes());
+        data.extend_from_slice(&number_of_stored_blocks.to_le_bytes());
+        for dimension in [layout.width, layout.height, layout.time_slots] {
+            let dimension: u32 = dimension
+                .try_into()
+                .expect("Grid dimension does not fit into u32");
+            data.extend_from_slice(&dimension.to_le_bytes());
+        }
+        data.extend_from_slice(&[options.quantisation.id(), 0]);
+        data.extend_from_slice(&options.quantisation.parameter().to_le_bytes());
+        // maximum quantisation error, filled in by the caller
+        data.extend_from_slice(&[0; 2]);
+        // reserved and grid offset, which is filled in by the caller
+        data.extend_from_slice(&[0; 10]);
+        data.extend_from_slice(&(layout.interval.num_seconds() as u32).to_le_bytes());
+        data.extend_from_slice(&[0; 16]);
+        assert_eq!(data.len(), HEADER_SIZE);
+
+        for offset in offsets {
+            if has_32_bit_offsets {
+                data.extend_from_slice(&offset.to_le_bytes());
+            } else {
+                let offset: u16 = match offset {
+                    OFFSET_ALL_MISSING => 0xFFFF,
+                    OFFSET_ALL_ZERO => 0x7FFF,
+                    offset => ((offset & OFFSET_16_BIT) >> 16) as u16 | offset as u16,
+                };
+                data.extend_from_slice(&offset.to_le_bytes());
+            }
+        }
+        match options.compression {
+            Compression::None => {
+                assert_eq!(values_vec.len(), next_offset as usize * layout.step_size());
+            }
+            Compression::Lzma(_) => {
+                for block_start in block_starts {
+                    data.extend_from_slice(&block_start.to_le_bytes());
+                }
+                if options.temporal_delta {
+                    for delta_reference in delta_references {
+                        data.extend_from_slice(&delta_reference.to_le_bytes());
+                    }
+                }
+            }
+        }
         data.extend_from_slice(&values_vec);
 
-        let data = data.into_boxed_slice();
+        Self {
+            data: AlignedBytes::from_bytes(&data),
+            layout,
+            quantisation: options.quantisation,
+        }
+    }
 
-        assert_eq!(
-            data.len(),
-            6608 + current_offset_from_start as usize * 10000
+    /// Reads values from the bytes returned by [`Self::data`]. The data is validated completely
+    /// (compressed blocks are decompressed once), so reading values afterwards doesn't fail.
+    pub fn from_data(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() >= HEADER_SIZE,
+            "Data is too short ({} bytes) to contain the header",
+            bytes.len()
+        );
+        ensure!(
+            bytes[0..8] == *MAGIC,
+            "Not compressed rain radar values (found magic bytes {:?})",
+            &bytes[0..8]
+        );
+
+        let read_u32 = |position: usize| {
+            u32::from_le_bytes(
+                bytes[position..(position + 4)]
+                    .try_into()
+                    .expect("Could not convert to [u8; 4] (this should not happen"),
+            ) as usize
+        };
+        let version = read_u32(8);
+        ensure!(
+            version == VERSION as usize,
+            "Unknown format version {version}"
+        );
+        let layout = Layout {
+            block_size: u16::from_le_bytes([bytes[26], bytes[27]]) as usize,
+            width: read_u32(32),
+            height: read_u32(36),
+            time_slots: read_u32(40),
+            interval: chrono::Duration::seconds(read_u32(60) as i64),
+        };
+        ensure!(layout.block_size > 0, "Block size is 0");
+        ensure!(layout.interval > chrono::Duration::zero(), "Interval is 0");
+        ensure!(
+            layout.width > 0 && layout.height > 0,
+            "Grid size {}x{} is empty",
+            layout.width,
+            layout.height
+        );
+        let first_time = chrono::DateTime::from_timestamp(
+            i64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            0,
+        )
+        .context("Base time is out of range")?
+        .naive_utc();
+        ensure!(
+            layout
+                .interval
+                .num_seconds()
+                .checked_mul(layout.time_slots as i64)
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|duration| first_time.checked_add_signed(duration))
+                .is_some(),
+            "Time slots exceed the supported time range"
+        );
+
+        let quantisation = Quantisation::from_id_and_parameter(
+            bytes[44],
+            u16::from_le_bytes([bytes[46], bytes[47]]),
+        )?;
+
+        let result = Self {
+            data: AlignedBytes::from_bytes(bytes),
+            layout,
+            quantisation,
+        };
+
+        let cell_size = result.cell_size();
+        let (offset_x, offset_y) = result.grid_offset();
+        ensure!(cell_size > 0, "Cell size is 0");
+        ensure!(
+            offset_x.is_multiple_of(cell_size) && offset_y.is_multiple_of(cell_size),
+            "Grid offset ({offset_x}, {offset_y}) is no multiple of the cell size {cell_size}"
+        );
+
+        let is_compressed = match result.data[24] {
+            0 => false,
+            1 => true,
+            compression => bail!("Unknown compression {compression}"),
+        };
+        let flags = result.data[25];
+        ensure!(
+            flags & !(FLAG_DELTA_REFERENCES | FLAG_32_BIT_OFFSETS | FLAG_BLOCK_SUMMARIES) == 0,
+            "Unknown flags {flags:#x}"
+        );
+        ensure!(
+            is_compressed || flags & FLAG_DELTA_REFERENCES == 0,
+            "Delta references are only supported with compression"
+        );
+        let number_of_stored_blocks = result.number_of_stored_blocks();
+
+        ensure!(
+            result.data.len() >= result.values_start() + result.block_summaries_size(),
+            "Data is too short ({} bytes) to contain the block offsets",
+            result.data.len()
         );
 
-        Self { data }
+        if is_compressed {
+            let directory_end = result.compressed_blocks_start();
+            ensure!(
+                result.values_end() >= directory_end,
+                "Data is too short ({} bytes) to contain the list of compressed blocks",
+                result.data.len()
+            );
+            if result.has_delta_references() {
+                let mut chain_lengths = Vec::with_capacity(number_of_stored_blocks);
+                for offset in 0..number_of_stored_blocks {
+                    let chain_length = match result.delta_reference(offset) {
+                        Some(reference) => {
+                            // only references to earlier blocks, so there can't be any cycles
+                            ensure!(
+                                reference < offset,
+                                "Delta reference {reference} of block {offset} is invalid"
+                            );
+                            chain_lengths[reference] + 1
+                        }
+                        None => 0,
+                    };
+                    ensure!(
+                        chain_length <= MAX_DELTA_CHAIN_LENGTH,
+                        "Chain of delta references of block {offset} is too long"
+                    );
+                    chain_lengths.push(chain_length);
+                }
+            }
+            let block_starts = (0..=number_of_stored_blocks)
+                .map(|index| result.compressed_block_start(index))
+                .collect::<Vec<_>>();
+            ensure!(
+                block_starts.windows(2).all(|starts| starts[0] <= starts[1]),
+                "Compressed blocks are not ordered"
+            );
+            ensure!(
+                directory_end + block_starts[number_of_stored_blocks] == result.values_end(),
+                "Length of the compressed blocks does not match the data length"
+            );
+            for offset in 0..number_of_stored_blocks {
+                result.decompress_block(offset)?;
+            }
+        } else {
+            ensure!(
+                (result.values_end() - result.values_start()).is_multiple_of(layout.step_size()),
+                "Length of the uncompressed blocks is not a multiple of the block length"
+            );
+        }
+
+        let available_steps = (result.values_end() - result.values_start()) / layout.step_size();
+        for index in 0..layout.number_of_blocks() {
+            if let BlockLocation::Stored { is_16_bit, offset } = result.block_location(index) {
+                if is_compressed {
+                    ensure!(
+                        offset < number_of_stored_blocks,
+                        "Block offset {offset} is out of range"
+                    );
+                } else {
+                    let steps = if is_16_bit { 2 } else { 1 };
+                    ensure!(
+                        offset + steps <= available_steps,
+                        "Block offset {offset} is out of range"
+                    );
+                }
+            }
+        }
+
+        Ok(result)
     }
 
     fn first_time(&self) -> chrono::NaiveDateTime {
-        chrono::NaiveDateTime::from_timestamp(
+        chrono::DateTime::from_timestamp(
             i64::from_le_bytes(
-                self.data[0..8]
+                self.data[16..24]
                     .try_into()
                     .expect("Could not convert to [u8; 8] (this should not happen"),
             ),
             0,
         )
+        .expect("Base time out of range")
+        .naive_utc()
     }
 
-    fn block_offsets(&self) -> &[[[u16; 12]; 11]; 25] {
-        let block_offsets_byte_area = &self.data[8..6608];
+    /// Metadata stored in the header of the data
+    pub fn header(&self) -> Header {
+        Header {
+            time_information: self.time_information(),
+            grid_information: self.grid_information(),
+            grid_offset: self.grid_offset(),
+            cell_size: self.cell_size(),
+            block_size: self.layout.block_size,
+            is_compressed: self.is_compressed(),
+            quantisation: self.quantisation,
+            max_quantisation_error: self.max_quantisation_error(),
+            has_block_summaries: self.has_block_summaries(),
+        }
+    }
 
-        assert!(block_offsets_byte_area.len() == std::mem::size_of::<[[[u16; 12]; 11]; 25]>());
+    pub fn quantisation(&self) -> Quantisation {
+        self.quantisation
+    }
 
-        unsafe {
-            // this should be sound: the slice has the correct length (as asserted above)
-            &*(block_offsets_byte_area as *const [u8] as *const [[[u16; 12]; 11]; 25])
+    /// Maximum absolute difference between a value of the original values and the one returned
+    /// here (0 without quantisation)
+    pub fn max_quantisation_error(&self) -> u16 {
+        u16::from_le_bytes([self.data[48], self.data[49]])
+    }
+
+    /// Index of the time slot for `time`
+    fn time_slot(&self, time: chrono::NaiveDateTime) -> usize {
+        self.time_information()
+            .time_slot(time)
+            .unwrap_or_else(|| panic!("Illegal time {time}: Not one of the available times"))
+    }
+
+    fn is_compressed(&self) -> bool {
+        self.data[24] != 0
+    }
+
+    fn has_delta_references(&self) -> bool {
+        self.data[25] & FLAG_DELTA_REFERENCES != 0
+    }
+
+    fn has_32_bit_offsets(&self) -> bool {
+        self.data[25] & FLAG_32_BIT_OFFSETS != 0
+    }
+
+    fn has_block_summaries(&self) -> bool {
+        self.data[25] & FLAG_BLOCK_SUMMARIES != 0
+    }
+
+    fn block_summaries_size(&self) -> usize {
+        if self.has_block_summaries() {
+            self.layout.number_of_blocks() * BLOCK_SUMMARY_SIZE
+        } else {
+            0
         }
     }
 
-    fn block_u8(&self, offset: usize) -> &[[u8; 100]; 100] {
-        let offset = 6608 + (offset * 10000);
-        let block_byte_area = &self.data[offset..(offset + 10000)];
+    /// End of the stored (or compressed) blocks
+    fn values_end(&self) -> usize {
+        self.data.len() - self.block_summaries_size()
+    }
 
-        assert!(block_byte_area.len() == std::mem::size_of::<[[u8; 100]; 100]>());
+    fn block_summary(&self, index: usize) -> Option<BlockSummary> {
+        if !self.has_block_summaries() {
+            return None;
+        }
+        let position = self.values_end() + index * BLOCK_SUMMARY_SIZE;
+        Some(BlockSummary::from_bytes(
+            &self.data[position..(position + BLOCK_SUMMARY_SIZE)],
+        ))
+    }
 
-        unsafe {
-            // this should be sound: the slice has the correct length (as asserted above)
-            &*(block_byte_area as *const [u8] as *const [[u8; 100]; 100])
+    /// Maximum value in an area over all time slots in `times` (None if all of these values are
+    /// missing). Blocks that are completely inside of the area are answered from their block
+    /// summaries (if present), only the others are decoded.
+    pub fn max_in_area(
+        &self,
+        times: std::ops::Range<chrono::NaiveDateTime>,
+        x: std::ops::Range<usize>,
+        y: std::ops::Range<usize>,
+    ) -> Option<u16> {
+        let layout = &self.layout;
+        assert!(x.end <= layout.width && y.end <= layout.height);
+        // index of the first time slot at or after `time`
+        let time_slot_after = |time: chrono::NaiveDateTime| -> usize {
+            let seconds = (time - self.first_time()).num_seconds();
+            let interval = layout.interval.num_seconds();
+            usize::min(
+                ((seconds.max(0) + interval - 1) / interval) as usize,
+                layout.time_slots,
+            )
+        };
+        let time_slots = time_slot_after(times.start)..time_slot_after(times.end);
+        if x.is_empty() || y.is_empty() {
+            return None;
+        }
+
+        let mut max: Option<u16> = None;
+        let mut add = |value: u16| max = Some(max.map_or(value, |max| u16::max(max, value)));
+        for time_slot in time_slots {
+            let time = self.first_time() + layout.interval * time_slot as i32;
+            for x_block in (x.start / layout.block_size)..=((x.end - 1) / layout.block_size) {
+                for y_block in (y.start / layout.block_size)..=((y.end - 1) / layout.block_size) {
+                    let index = layout.block_index(time_slot, x_block, y_block);
+                    let block_x = (x_block * layout.block_size)
+                        ..usize::min((x_block + 1) * layout.block_size, layout.width);
+                    let block_y = (y_block * layout.block_size)
+                        ..usize::min((y_block + 1) * layout.block_size, layout.height);
+                    let fully_covered = x.start <= block_x.start
+                        && block_x.end <= x.end
+                        && y.start <= block_y.start
+                        && block_y.end <= y.end;
+
+                    match self.block_location(index) {
+                        BlockLocation::AllMissing => {}
+                        BlockLocation::AllZero => add(self.quantisation.dequantise(0)),
+                        BlockLocation::Stored { .. } => match self.block_summary(index) {
+                            Some(summary) if fully_covered => {
+                                if let Some(block_max) = summary.max {
+                                    add(block_max)
+                                }
+                            }
+                            _ => {
+                                let area_x = usize::max(x.start, block_x.start)
+                                    ..usize::min(x.end, block_x.end);
+                                let area_y = usize::max(y.start, block_y.start)
+                                    ..usize::min(y.end, block_y.end);
+                                for value in self.for_area(time, area_x, area_y).flatten() {
+                                    add(value)
+                                }
+                            }
+                        },
+                    }
+                }
+            }
         }
+        max
     }
 
-    fn block_u16(&self, offset: usize) -> &[[u16; 100]; 100] {
-        let offset = 6608 + (offset * 10000);
-        assert!(offset % 2 == 0); // alignment of the buffer is guaranteed as it is allocated via AlignedAlloc<2>
-        let block_byte_area = &self.data[offset..(offset + 20000)];
+    fn number_of_stored_blocks(&self) -> usize {
+        self.read_u32(28) as usize
+    }
 
-        assert!(block_byte_area.len() == std::mem::size_of::<[[u16; 100]; 100]>());
+    fn values_start(&self) -> usize {
+        let offset_size = if self.has_32_bit_offsets() { 4 } else { 2 };
+        HEADER_SIZE + self.layout.number_of_blocks() * offset_size
+    }
+
+    fn block_location(&self, index: usize) -> BlockLocation {
+        if self.has_32_bit_offsets() {
+            BlockLocation::from_u32(self.read_u32(HEADER_SIZE + index * 4))
+        } else {
+            let position = HEADER_SIZE + index * 2;
+            BlockLocation::from_u16(u16::from_le_bytes([
+                self.data[position],
+                self.data[position + 1],
+            ]))
+        }
+    }
+
+    fn block_u8(&self, offset: usize) -> &[u8] {
+        let start = self.values_start() + offset * self.layout.step_size();
+        &self.data[start..(start + self.layout.values_per_block())]
+    }
+
+    fn block_u16(&self, offset: usize) -> &[u16] {
+        let start = self.values_start() + offset * self.layout.step_size();
+        let block_byte_area = &self.data[start..(start + 2 * self.layout.values_per_block())];
+
+        // alignment of the buffer is guaranteed as it is backed by u16 (see AlignedBytes), the start
+        // of the values and the step size are even
+        assert!((block_byte_area.as_ptr() as usize).is_multiple_of(std::mem::align_of::<u16>()));
 
         unsafe {
-            // this should be sound: the slice has the correct length (as asserted above)
-            &*(block_byte_area as *const [u8] as *const [[u16; 100]; 100])
+            // this should be sound: the slice is aligned and has the correct length
+            std::slice::from_raw_parts(
+                block_byte_area.as_ptr() as *const u16,
+                self.layout.values_per_block(),
+            )
+        }
+    }
+
+    fn read_u32(&self, position: usize) -> u32 {
+        u32::from_le_bytes(
+            self.data[position..(position + 4)]
+                .try_into()
+                .expect("Could not convert to [u8; 4] (this should not happen"),
+        )
+    }
+
+    fn compressed_block_start(&self, offset: usize) -> usize {
+        self.read_u32(self.values_start() + offset * 4) as usize
+    }
+
+    fn delta_reference(&self, offset: usize) -> Option<usize> {
+        if !self.has_delta_references() {
+            return None;
+        }
+        let position = self.values_start() + (self.number_of_stored_blocks() + 1 + offset) * 4;
+        match self.read_u32(position) {
+            u32::MAX => None,
+            reference => Some(reference as usize),
         }
     }
 
-    fn reader(&self) -> impl std::io::Read + '_ {
-        std::io::Cursor::new(&self.data)
+    fn compressed_blocks_start(&self) -> usize {
+        let number_of_stored_blocks = self.number_of_stored_blocks();
+        let delta_references_size = if self.has_delta_references() {
+            number_of_stored_blocks * 4
+        } else {
+            0
+        };
+        self.values_start() + (number_of_stored_blocks + 1) * 4 + delta_references_size
+    }
+
+    /// Decompresses a stored block on its own (without applying its delta reference), returns the
+    /// bytes and whether they are 16 bit values
+    fn decompress_block(&self, offset: usize) -> Result<(Vec<u8>, bool)> {
+        let compressed_blocks_start = self.compressed_blocks_start();
+        let start = compressed_blocks_start + self.compressed_block_start(offset);
+        let end = compressed_blocks_start + self.compressed_block_start(offset + 1);
+        let bytes = lzma::decompress(&self.data[start..end])
+            .with_context(|| format!("Failed decompressing block {offset}"))?;
+        let is_16_bit = match bytes.len() {
+            length if length == self.layout.values_per_block() => false,
+            length if length == 2 * self.layout.values_per_block() => true,
+            length => bail!("Decompressed block {offset} has unexpected length {length}"),
+        };
+        Ok((bytes, is_16_bit))
+    }
+
+    /// Decompresses a stored block (and the blocks it is relative to). Missing values are
+    /// u16::MAX, regardless of the block width.
+    fn decode_compressed_block(&self, offset: usize) -> Box<[u16]> {
+        let reference = self
+            .delta_reference(offset)
+            .map(|reference| self.decode_compressed_block(reference));
+
+        let (mut bytes, is_16_bit) = self
+            .decompress_block(offset)
+            .expect("Compressed block is invalid (this should not happen, see from_data)");
+
+        if let Some(reference) = reference {
+            let reference_values = reference
+                .iter()
+                .map(|value| {
+                    if *value == u16::MAX {
+                        None
+                    } else {
+                        Some(*value)
+                    }
+                })
+                .collect::<Vec<_>>();
+            bytes = delta_decode(
+                &bytes,
+                &Self::to_bytes(&reference_values, is_16_bit),
+                is_16_bit,
+            );
+        }
+
+        if is_16_bit {
+            bytes
+                .chunks_exact(2)
+                .map(|value| u16::from_le_bytes([value[0], value[1]]))
+                .collect()
+        } else {
+            bytes
+                .into_iter()
+                .map(|value| {
+                    if value == u8::MAX {
+                        u16::MAX
+                    } else {
+                        value as u16
+                    }
+                })
+                .collect()
+        }
+    }
+
+    /// All blocks of all time slots, ordered by time slot
+    pub fn blocks(&self) -> Blocks<'_> {
+        Blocks {
+            radar_values: self,
+            indices: 0..self.layout.number_of_blocks(),
+        }
+    }
+
+    /// All blocks of one time slot
+    pub fn blocks_at(&self, time: chrono::NaiveDateTime) -> Blocks<'_> {
+        let time_slot = self.time_slot(time);
+        let blocks_per_time_slot = self.layout.blocks_per_time_slot();
+        Blocks {
+            radar_values: self,
+            indices: (time_slot * blocks_per_time_slot)..((time_slot + 1) * blocks_per_time_slot),
+        }
+    }
+
+    fn block(&self, index: usize) -> Block<'_> {
+        let layout = &self.layout;
+        let y_block = index % layout.blocks_y();
+        let x_block = (index / layout.blocks_y()) % layout.blocks_x();
+        let time_slot = index / layout.blocks_per_time_slot();
+
+        let values = match self.block_location(index) {
+            BlockLocation::AllMissing => BlockValues::Missing,
+            BlockLocation::AllZero => BlockValues::Dry,
+            BlockLocation::Stored { is_16_bit, offset } if self.is_compressed() => {
+                let decoded_block = self.decode_compressed_block(offset);
+                if is_16_bit {
+                    BlockValues::U16(std::borrow::Cow::Owned(decoded_block.into_vec()))
+                } else {
+                    BlockValues::U8(std::borrow::Cow::Owned(
+                        decoded_block
+                            .iter()
+                            .map(|value| {
+                                if *value == u16::MAX {
+                                    u8::MAX
+                                } else {
+                                    *value as u8
+                                }
+                            })
+                            .collect(),
+                    ))
+                }
+            }
+            BlockLocation::Stored {
+                is_16_bit: true,
+                offset,
+            } => {
+                let values = self.block_u16(offset);
+                if cfg!(target_endian = "little") {
+                    BlockValues::U16(std::borrow::Cow::Borrowed(values))
+                } else {
+                    BlockValues::U16(std::borrow::Cow::Owned(
+                        values.iter().map(|value| u16::from_le(*value)).collect(),
+                    ))
+                }
+            }
+            BlockLocation::Stored {
+                is_16_bit: false,
+                offset,
+            } => BlockValues::U8(std::borrow::Cow::Borrowed(self.block_u8(offset))),
+        };
+
+        let x_start = x_block * layout.block_size;
+        let y_start = y_block * layout.block_size;
+        Block {
+            time_slot,
+            time: self.first_time() + self.layout.interval * time_slot as i32,
+            x: x_start..usize::min(x_start + layout.block_size, layout.width),
+            y: y_start..usize::min(y_start + layout.block_size, layout.height),
+            values,
+            summary: self.block_summary(index),
+            block_size: layout.block_size,
+            quantisation: self.quantisation,
+        }
     }
 
     pub fn data(&self) -> &[u8] {
@@ -156,51 +1571,151 @@ impl CompressedRainRadarValues {
     }
 }
 
+/// Wrapping differences between the stored bytes of two blocks with the same width
+fn delta_encode(bytes: &[u8], reference_bytes: &[u8], is_16_bit: bool) -> Vec<u8> {
+    if is_16_bit {
+        bytes
+            .chunks_exact(2)
+            .zip(reference_bytes.chunks_exact(2))
+            .flat_map(|(value, reference)| {
+                u16::from_le_bytes([value[0], value[1]])
+                    .wrapping_sub(u16::from_le_bytes([reference[0], reference[1]]))
+                    .to_le_bytes()
+                    .into_iter()
+            })
+            .collect()
+    } else {
+        bytes
+            .iter()
+            .zip(reference_bytes)
+            .map(|(value, reference)| value.wrapping_sub(*reference))
+            .collect()
+    }
+}
+
+/// Inverse of [`delta_encode`]
+fn delta_decode(delta_bytes: &[u8], reference_bytes: &[u8], is_16_bit: bool) -> Vec<u8> {
+    if is_16_bit {
+        delta_bytes
+            .chunks_exact(2)
+            .zip(reference_bytes.chunks_exact(2))
+            .flat_map(|(delta, reference)| {
+                u16::from_le_bytes([delta[0], delta[1]])
+                    .wrapping_add(u16::from_le_bytes([reference[0], reference[1]]))
+                    .to_le_bytes()
+                    .into_iter()
+            })
+            .collect()
+    } else {
+        delta_bytes
+            .iter()
+            .zip(reference_bytes)
+            .map(|(delta, reference)| delta.wrapping_add(*reference))
+            .collect()
+    }
+}
+
+/// Iterator over the blocks of [`CompressedRainRadarValues`], see
+/// [`CompressedRainRadarValues::blocks`]
+pub struct Blocks<'a> {
+    radar_values: &'a CompressedRainRadarValues,
+    indices: std::ops::Range<usize>,
+}
+
+impl<'a> std::iter::Iterator for Blocks<'a> {
+    type Item = Block<'a>;
+
+    fn next(&mut self) -> Option<Block<'a>> {
+        self.indices
+            .next()
+            .map(|index| self.radar_values.block(index))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.indices.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Blocks<'a> {}
+
 pub struct Iterator<'a, X: super::Range, Y: super::Range> {
     radar_values: &'a CompressedRainRadarValues,
     prediction_index: usize,
     current_index_iter: super::CrossProduct<X, Y>,
+    decoded_blocks: Vec<(usize, Box<[u16]>)>,
+}
+
+impl<'a, X: super::Range, Y: super::Range> Iterator<'a, X, Y> {
+    fn decoded_block(&mut self, offset: usize) -> &[u16] {
+        let position = match self
+            .decoded_blocks
+            .iter()
+            .position(|(decoded_offset, _)| *decoded_offset == offset)
+        {
+            Some(position) => position,
+            None => {
+                let decoded_block = self.radar_values.decode_compressed_block(offset);
+                if self.decoded_blocks.len()
+                    == self.radar_values.layout.blocks_x() + DECODED_BLOCKS_CACHE_RESERVE
+                {
+                    self.decoded_blocks.remove(0);
+                }
+                self.decoded_blocks.push((offset, decoded_block));
+                self.decoded_blocks.len() - 1
+            }
+        };
+        &self.decoded_blocks[position].1
+    }
 }
 
 impl<'a, X: super::Range, Y: super::Range> std::iter::Iterator for Iterator<'a, X, Y> {
     type Item = Option<u16>;
 
     fn next(&mut self) -> Option<Option<u16>> {
-        (&mut self.current_index_iter)
-            .map(|(x, y)| -> Option<u16> {
-                let x_block = x / 100;
-                let y_block = y / 100;
-                assert!(x_block < 11);
-                assert!(y_block < 12);
-
-                let offsets = self.radar_values.block_offsets();
-                let offset = u16::from_le(offsets[self.prediction_index][x_block][y_block]);
-
-                let highest_bit_set = (offset & 0x8000) != 0;
-
-                let (is_16_bit, offset) = match (highest_bit_set, offset) {
-                    (_, 0xFFFF) => return None,
-                    (_, 0x7FFF) => return Some(0),
-                    (is_16_bit, offset) => (is_16_bit, (offset & 0x7FFF) as usize),
-                };
+        let (x, y) = self.current_index_iter.next()?;
 
-                if is_16_bit {
-                    let value = self.radar_values.block_u16(offset)[y % 100][x % 100];
-                    if value == u16::MAX {
-                        None
-                    } else {
-                        Some(value)
-                    }
-                } else {
-                    let value = self.radar_values.block_u8(offset)[y % 100][x % 100];
-                    if value == u8::MAX {
-                        None
-                    } else {
-                        Some(value as u16)
-                    }
-                }
-            })
-            .next()
+        let layout = &self.radar_values.layout;
+        assert!(x < layout.width);
+        assert!(y < layout.height);
+        let x_block = x / layout.block_size;
+        let y_block = y / layout.block_size;
+        let index_in_block = (y % layout.block_size) * layout.block_size + x % layout.block_size;
+
+        let (is_16_bit, offset) = match self.radar_values.block_location(layout.block_index(
+            self.prediction_index,
+            x_block,
+            y_block,
+        )) {
+            BlockLocation::AllMissing => return Some(None),
+            BlockLocation::AllZero => {
+                return Some(Some(self.radar_values.quantisation.dequantise(0)))
+            }
+            BlockLocation::Stored { is_16_bit, offset } => (is_16_bit, offset),
+        };
+
+        let value = if self.radar_values.is_compressed() {
+            let value = self.decoded_block(offset)[index_in_block];
+            if value == u16::MAX {
+                None
+            } else {
+                Some(value)
+            }
+        } else if is_16_bit {
+            let value = u16::from_le(self.radar_values.block_u16(offset)[index_in_block]);
+            if value == u16::MAX {
+                None
+            } else {
+                Some(value)
+            }
+        } else {
+            let value = self.radar_values.block_u8(offset)[index_in_block];
+            if value == u8::MAX {
+                None
+            } else {
+                Some(value as u16)
+            }
+        };
+        Some(value.map(|value| self.radar_values.quantisation.dequantise(value)))
     }
 }
 
@@ -212,30 +1727,37 @@ impl RainRadarValues for CompressedRainRadarValues {
         time: chrono::naive::NaiveDateTime,
         x: X,
         y: Y,
-    ) -> Iterator<X, Y> {
-        let duration = time - self.first_time();
-        let prediction_index: usize = (duration.num_minutes() / 5)
-            .try_into()
-            .expect("prediction_index is not usize");
-        assert_eq!(
-            chrono::Duration::minutes(prediction_index as i64 * 5),
-            duration,
-            "Illegal duration: Not multiple of 5 minutes"
-        );
-        assert!(prediction_index < 25);
+    ) -> Iterator<'_, X, Y> {
         Iterator {
             radar_values: self,
-            prediction_index,
+            prediction_index: self.time_slot(time),
             current_index_iter: x.cross_product(y),
+            decoded_blocks: Vec::new(),
         }
     }
 
     fn time_information(&self) -> crate::TimeInformation {
         TimeInformation {
             first_time: self.first_time(),
-            available_time_slots: 25,
+            available_time_slots: self.layout.time_slots as u32,
+            interval: self.layout.interval,
         }
     }
+
+    fn grid_information(&self) -> GridInformation {
+        GridInformation {
+            width: self.layout.width,
+            height: self.layout.height,
+        }
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        (self.read_u32(52) as usize, self.read_u32(56) as usize)
+    }
+
+    fn cell_size(&self) -> usize {
+        u16::from_le_bytes([self.data[50], self.data[51]]) as usize
+    }
 }
 
 #[cfg(test)]
@@ -259,7 +1781,7 @@ mod test {
                 dbg!(compressed_rain_radar_values.data.len());
 
                 std::io::copy(
-                    &mut compressed_rain_radar_values.reader(),
+                    &mut compressed_rain_radar_values.data(),
                     &mut std::fs::File::create("/tmp/compressed_data").unwrap(),
                 )
                 .unwrap();
@@ -293,4 +1815,494 @@ mod test {
             })
             .collect()
     }
+
+    #[test]
+    fn test_round_trip() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::new(1);
+
+        let uncompressed = CompressedRainRadarValues::from_rain_radar_values(&test_values);
+        crate::test_values::assert_same_values(&test_values, &uncompressed);
+
+        let lzma = CompressedRainRadarValues::from_rain_radar_values_with_options(
+            &test_values,
+            &EncoderOptions {
+                compression: Compression::Lzma(6),
+                ..Default::default()
+            },
+        );
+        crate::test_values::assert_same_values(&test_values, &lzma);
+        assert!(lzma.data().len() < uncompressed.data().len());
+
+        for compressed_rain_radar_values in [uncompressed, lzma] {
+            let read_back =
+                CompressedRainRadarValues::from_data(compressed_rain_radar_values.data())?;
+            assert_eq!(read_back.data(), compressed_rain_radar_values.data());
+            crate::test_values::assert_same_values(&test_values, &read_back);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_interval() -> Result<()> {
+        let hourly = crate::test_values::TestRainRadarValues::with_size(3, 400, 300, 4)
+            .with_interval(chrono::Duration::hours(1));
+        let compressed = CompressedRainRadarValues::from_rain_radar_values(&hourly);
+        let read_back = CompressedRainRadarValues::from_data(compressed.data())?;
+        assert_eq!(
+            read_back.time_information().interval,
+            chrono::Duration::hours(1)
+        );
+        crate::test_values::assert_same_values(&hourly, &read_back);
+        let first_time = hourly.time_information().first_time;
+        assert_eq!(
+            read_back.blocks().map(|block| block.time).max(),
+            Some(first_time + chrono::Duration::hours(3))
+        );
+        assert_eq!(
+            read_back.max_in_area(
+                (first_time + chrono::Duration::minutes(5))
+                    ..(first_time + chrono::Duration::hours(2)),
+                0..400,
+                0..300
+            ),
+            hourly
+                .for_area(first_time + chrono::Duration::hours(1), 0..400, 0..300)
+                .flatten()
+                .max()
+        );
+
+        let mut data = compressed.data().to_vec();
+        data[60..64].copy_from_slice(&[0; 4]);
+        assert!(CompressedRainRadarValues::from_data(&data).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_data_rejects_truncated_data() {
+        let test_values = crate::test_values::TestRainRadarValues::new(2);
+        for compression in [Compression::None, Compression::Lzma(0)] {
+            let compressed_rain_radar_values =
+                CompressedRainRadarValues::from_rain_radar_values_with_options(
+                    &test_values,
+                    &EncoderOptions {
+                        compression,
+                        ..Default::default()
+                    },
+                );
+            let data = compressed_rain_radar_values.data();
+            assert!(CompressedRainRadarValues::from_data(&data[..data.len() - 2]).is_err());
+
+            let mut out_of_range = data.to_vec();
+            out_of_range[16..24].copy_from_slice(&i64::MAX.to_le_bytes());
+            assert!(CompressedRainRadarValues::from_data(&out_of_range).is_err());
+        }
+    }
+
+    #[test]
+    fn test_from_data_rejects_unknown_formats_and_corrupt_blocks() {
+        let test_values = crate::test_values::TestRainRadarValues::new(2);
+        let compressed = CompressedRainRadarValues::from_rain_radar_values_with_options(
+            &test_values,
+            &EncoderOptions {
+                compression: Compression::Lzma(0),
+                ..Default::default()
+            },
+        );
+        let data = compressed.data();
+        assert!(CompressedRainRadarValues::from_data(data).is_ok());
+
+        let mut wrong_magic = data.to_vec();
+        wrong_magic[0..8].copy_from_slice(b"DWDRRARC");
+        let error = CompressedRainRadarValues::from_data(&wrong_magic)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("magic bytes"));
+        let mut unknown_version = data.to_vec();
+        unknown_version[8..12].copy_from_slice(&2u32.to_le_bytes());
+        let error = CompressedRainRadarValues::from_data(&unknown_version)
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("Unknown format version 2"));
+
+        // corrupt blocks are found before any values are read
+        let mut corrupt = data.to_vec();
+        let position = corrupt.len() - 20;
+        corrupt[position] ^= 0xFF;
+        assert!(CompressedRainRadarValues::from_data(&corrupt).is_err());
+    }
+
+    #[test]
+    fn test_deduplication_and_temporal_delta() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(3, 550, 600, 25);
+
+        let uncompressed = CompressedRainRadarValues::from_rain_radar_values(&test_values);
+        let deduplicated = CompressedRainRadarValues::from_rain_radar_values_with_options(
+            &test_values,
+            &EncoderOptions {
+                deduplicate_blocks: true,
+                ..Default::default()
+            },
+        );
+        crate::test_values::assert_same_values(&test_values, &deduplicated);
+        // the missing area in the top left corner is the same in every time slot
+        assert!(deduplicated.data().len() < uncompressed.data().len());
+
+        let lzma_options = EncoderOptions {
+            compression: Compression::Lzma(6),
+            deduplicate_blocks: true,
+            ..Default::default()
+        };
+        let lzma = CompressedRainRadarValues::from_rain_radar_values_with_options(
+            &test_values,
+            &lzma_options,
+        );
+        let delta = CompressedRainRadarValues::from_rain_radar_values_with_options(
+            &test_values,
+            &EncoderOptions {
+                temporal_delta: true,
+                ..lzma_options
+            },
+        );
+        assert!(delta.data().len() <= lzma.data().len());
+        let delta = CompressedRainRadarValues::from_data(delta.data())?;
+        let last_time = test_values.available_times().next_back().unwrap();
+        crate::test_values::assert_same_values_at(&test_values, &delta, last_time);
+
+        // with 25 time slots, chains would get longer than the maximum without blocks that are
+        // stored on their own
+        let chain_length = |mut offset: usize| {
+            let mut length = 0;
+            while let Some(reference) = delta.delta_reference(offset) {
+                offset = reference;
+                length += 1;
+            }
+            length
+        };
+        let chain_lengths: Vec<usize> = (0..delta.number_of_stored_blocks())
+            .map(chain_length)
+            .collect();
+        assert_eq!(
+            chain_lengths.iter().max(),
+            Some(&MAX_DELTA_CHAIN_LENGTH),
+            "Test values should produce long chains"
+        );
+
+        // random access into a block that is most likely relative to earlier time slots
+        assert_eq!(
+            delta
+                .for_area(last_time, 250..270, 300..310)
+                .collect::<Vec<_>>(),
+            test_values
+                .for_area(last_time, 250..270, 300..310)
+                .collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_block_size_and_grid_size() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(4, 333, 250, 3);
+        for (compression, block_size) in [
+            (Compression::None, 64),
+            (Compression::None, 37),
+            (Compression::Lzma(0), 37),
+        ] {
+            let compressed_rain_radar_values =
+                CompressedRainRadarValues::from_rain_radar_values_with_options(
+                    &test_values,
+                    &EncoderOptions {
+                        compression,
+                        block_size,
+                        ..Default::default()
+                    },
+                );
+            let read_back =
+                CompressedRainRadarValues::from_data(compressed_rain_radar_values.data())?;
+            crate::test_values::assert_same_values(&test_values, &read_back);
+        }
+
+        let first_time_slot_only = CompressedRainRadarValues::from_rain_radar_values_with_options(
+            &test_values,
+            &EncoderOptions {
+                time_slots: Some(1),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            first_time_slot_only.time_information().available_time_slots,
+            1
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_32_bit_offsets() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(5, 600, 600, 2);
+        let compressed_rain_radar_values =
+            CompressedRainRadarValues::from_rain_radar_values_with_options(
+                &test_values,
+                &EncoderOptions {
+                    block_size: 2,
+                    ..Default::default()
+                },
+            );
+        assert!(compressed_rain_radar_values.has_32_bit_offsets());
+        let read_back = CompressedRainRadarValues::from_data(compressed_rain_radar_values.data())?;
+        crate::test_values::assert_same_values(&test_values, &read_back);
+        Ok(())
+    }
+
+    #[test]
+    fn test_quantisation() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(6, 550, 600, 2);
+        let lossless = CompressedRainRadarValues::from_rain_radar_values(&test_values);
+        assert_eq!(lossless.max_quantisation_error(), 0);
+
+        for (quantisation, allowed_error) in [
+            (Quantisation::Step(10), 5),
+            (
+                Quantisation::Logarithmic {
+                    classes_per_doubling: 8,
+                },
+                17,
+            ),
+        ] {
+            let quantised = CompressedRainRadarValues::from_rain_radar_values_with_options(
+                &test_values,
+                &EncoderOptions {
+                    quantisation,
+                    ..Default::default()
+                },
+            );
+            assert!(quantised.data().len() < lossless.data().len());
+            let quantised = CompressedRainRadarValues::from_data(quantised.data())?;
+            assert_eq!(quantised.quantisation(), quantisation);
+            let max_quantisation_error = quantised.max_quantisation_error();
+            assert!(max_quantisation_error > 0 && max_quantisation_error <= allowed_error);
+
+            let mut actual_max_error = 0;
+            for time in test_values.available_times() {
+                for (expected, actual) in test_values
+                    .for_area(time, 0..550, 0..600)
+                    .zip(quantised.for_area(time, 0..550, 0..600))
+                {
+                    match (expected, actual) {
+                        (Some(expected), Some(actual)) => {
+                            actual_max_error = u16::max(actual_max_error, expected.abs_diff(actual))
+                        }
+                        (None, None) => {}
+                        _ => panic!("{expected:?} and {actual:?} differ in being missing"),
+                    }
+                }
+            }
+            assert_eq!(actual_max_error, max_quantisation_error);
+        }
+
+        // the largest value must not end up in the class that marks missing values
+        let finest = Quantisation::Logarithmic {
+            classes_per_doubling: MAX_CLASSES_PER_DOUBLING,
+        };
+        assert!(finest.quantise(u16::MAX - 1) < u16::MAX);
+        assert_eq!(
+            finest.dequantise(finest.quantise(u16::MAX - 1)),
+            u16::MAX - 1
+        );
+        assert!(Quantisation::from_id_and_parameter(2, MAX_CLASSES_PER_DOUBLING).is_ok());
+        assert!(Quantisation::from_id_and_parameter(2, MAX_CLASSES_PER_DOUBLING + 1).is_err());
+        assert!(Quantisation::from_id_and_parameter(2, u16::MAX).is_err());
+        let mut data = lossless.data().to_vec();
+        data[44] = 2;
+        data[46..48].copy_from_slice(&5000u16.to_le_bytes());
+        assert!(CompressedRainRadarValues::from_data(&data).is_err());
+        let too_fine = std::panic::catch_unwind(|| {
+            CompressedRainRadarValues::from_rain_radar_values_with_options(
+                &test_values,
+                &EncoderOptions {
+                    quantisation: Quantisation::Logarithmic {
+                        classes_per_doubling: 5000,
+                    },
+                    ..Default::default()
+                },
+            )
+        });
+        assert!(too_fine.is_err());
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_encoding() {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(7, 350, 400, 4);
+        for options in [
+            EncoderOptions::default(),
+            EncoderOptions {
+                compression: Compression::Lzma(1),
+                deduplicate_blocks: true,
+                temporal_delta: true,
+                block_size: 64,
+                quantisation: Quantisation::Step(4),
+                ..Default::default()
+            },
+        ] {
+            let sequential = CompressedRainRadarValues::from_rain_radar_values_with_options(
+                &test_values,
+                &options,
+            );
+            let parallel =
+                CompressedRainRadarValues::from_rain_radar_values_parallel(&test_values, &options);
+            assert_eq!(sequential.data(), parallel.data());
+        }
+    }
+
+    #[test]
+    fn test_blocks() {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(8, 550, 620, 3);
+        for compression in [Compression::None, Compression::Lzma(1)] {
+            let compressed = CompressedRainRadarValues::from_rain_radar_values_with_options(
+                &test_values,
+                &EncoderOptions {
+                    compression,
+                    ..Default::default()
+                },
+            );
+            assert_eq!(compressed.blocks().len(), 3 * 6 * 7);
+
+            let mut classifications = Vec::new();
+            for block in compressed.blocks() {
+                assert!(block.for_area().eq(test_values.for_area(
+                    block.time,
+                    block.x.clone(),
+                    block.y.clone()
+                )));
+                if compression == Compression::None {
+                    assert!(matches!(
+                        block.values,
+                        BlockValues::Missing
+                            | BlockValues::Dry
+                            | BlockValues::U8(std::borrow::Cow::Borrowed(_))
+                            | BlockValues::U16(std::borrow::Cow::Borrowed(_))
+                    ));
+                }
+                classifications.push(block.classification());
+            }
+            // the top left block is inside of the missing triangle
+            assert_eq!(classifications[0], BlockClassification::Missing);
+            for classification in [
+                BlockClassification::Dry,
+                BlockClassification::U8,
+                BlockClassification::U16,
+            ] {
+                assert!(classifications.contains(&classification));
+            }
+
+            let time = test_values.available_times().nth(2).unwrap();
+            let blocks_at: Vec<Block> = compressed.blocks_at(time).collect();
+            assert_eq!(blocks_at.len(), 6 * 7);
+            assert!(blocks_at.iter().all(|block| block.time_slot == 2));
+            assert_eq!(blocks_at[6 * 7 - 1].x, 500..550);
+            assert_eq!(blocks_at[6 * 7 - 1].y, 600..620);
+        }
+    }
+
+    #[test]
+    fn test_block_summaries() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(9, 450, 380, 14);
+        let expected_max = |times: std::ops::Range<chrono::NaiveDateTime>,
+                            x: std::ops::Range<usize>,
+                            y: std::ops::Range<usize>| {
+            test_values
+                .available_times()
+                .filter(|time| times.contains(time))
+                .flat_map(|time| test_values.for_area(time, x.clone(), y.clone()))
+                .flatten()
+                .max()
+        };
+
+        for (compression, quantisation) in [
+            (Compression::None, Quantisation::None),
+            (Compression::Lzma(1), Quantisation::Step(7)),
+        ] {
+            let options = EncoderOptions {
+                compression,
+                quantisation,
+                block_summaries: true,
+                ..Default::default()
+            };
+            let with_summaries = CompressedRainRadarValues::from_rain_radar_values_with_options(
+                &test_values,
+                &options,
+            );
+            let without_summaries = CompressedRainRadarValues::from_rain_radar_values_with_options(
+                &test_values,
+                &EncoderOptions {
+                    block_summaries: false,
+                    ..options
+                },
+            );
+            assert_eq!(
+                with_summaries.data().len(),
+                without_summaries.data().len() + 14 * 5 * 4 * BLOCK_SUMMARY_SIZE
+            );
+            let with_summaries = CompressedRainRadarValues::from_data(with_summaries.data())?;
+            crate::test_values::assert_same_values_at(
+                &without_summaries,
+                &with_summaries,
+                test_values.available_times().nth(13).unwrap(),
+            );
+
+            for block in with_summaries.blocks() {
+                let summary = block.summary.expect("Block summary missing");
+                let values: Vec<u16> = block.for_area().flatten().collect();
+                assert_eq!(summary.max, values.iter().copied().max());
+                assert_eq!(
+                    summary.sum,
+                    values.iter().map(|value| *value as u64).sum::<u64>()
+                );
+                assert_eq!(
+                    summary.wet_values as usize,
+                    values.iter().filter(|value| **value > 0).count()
+                );
+            }
+            assert!(without_summaries
+                .blocks()
+                .all(|block| block.summary.is_none()));
+
+            let first_time = test_values.time_information().first_time;
+            let next_hour = first_time..(first_time + chrono::Duration::hours(1));
+            for (times, x, y) in [
+                (next_hour.clone(), 0..450, 0..380),
+                (next_hour.clone(), 100..300, 200..300),
+                (next_hour.clone(), 130..260, 17..350),
+                (next_hour, 0..50, 0..50),
+                (
+                    (first_time - chrono::Duration::minutes(12))
+                        ..(first_time + chrono::Duration::minutes(3)),
+                    300..450,
+                    100..380,
+                ),
+                (
+                    (first_time + chrono::Duration::seconds(330))
+                        ..(first_time + chrono::Duration::seconds(630)),
+                    0..450,
+                    0..380,
+                ),
+                (
+                    (first_time + chrono::Duration::minutes(67))
+                        ..(first_time + chrono::Duration::hours(3)),
+                    0..450,
+                    0..380,
+                ),
+            ] {
+                // the quantisation error changes values, but not which value is the largest
+                let expected = expected_max(times.clone(), x.clone(), y.clone())
+                    .map(|max| quantisation.dequantise(quantisation.quantise(max)));
+                assert_eq!(
+                    with_summaries.max_in_area(times.clone(), x.clone(), y.clone()),
+                    expected
+                );
+                assert_eq!(without_summaries.max_in_area(times, x, y), expected);
+            }
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,372 @@
+//! Outlines of rain areas (isohyets) as GeoJSON for web maps. The outlines are found with marching
+//! squares between the centers of the cells, interpolated linearly between their values, and
+//! projected to latitude and longitude with [`coordinates_mapper`].
+
+use crate::RainRadarValues;
+use anyhow::{ensure, Result};
+use coordinates_mapper::{GeographicCoordinates, StereographicCoordinates};
+use std::collections::HashMap;
+
+/// A closed ring of (x, y) positions, the first position is repeated at the end
+pub type Ring = Vec<(f64, f64)>;
+
+/// Areas at a time slot where the values reach a threshold
+#[derive(Debug, Clone, PartialEq)]
+pub struct Contour {
+    pub time: chrono::NaiveDateTime,
+    pub threshold: u16,
+    /// Every polygon is an outer ring (counterclockwise) followed by its holes (clockwise), as
+    /// (longitude, latitude) like in GeoJSON
+    pub polygons: Vec<Vec<Ring>>,
+}
+
+impl Contour {
+    /// Contour of the values at `time`. Missing values count as dry.
+    pub fn from_rain_radar_values<T: RainRadarValues + ?Sized>(
+        values: &T,
+        time: chrono::NaiveDateTime,
+        threshold: u16,
+    ) -> Self {
+        assert!(
+            threshold > 0,
+            "Threshold must be positive, dry cells would count as rain"
+        );
+        let grid_information = values.grid_information();
+        let grid: Vec<f64> = values
+            .for_area(time, 0..grid_information.width, 0..grid_information.height)
+            .map(|value| value.unwrap_or(0) as f64)
+            .collect();
+        let (offset_x, offset_y) = values.grid_offset();
+        let cell_size = values.cell_size() as f64;
+        // rings are in coordinates of the values, with the center of the value (x, y) at (x, y)
+        let to_geographic = |(x, y): (f64, f64)| {
+            let geographic: GeographicCoordinates = StereographicCoordinates {
+                x: offset_x as f64 + (x + 0.5) * cell_size,
+                y: offset_y as f64 + (y + 0.5) * cell_size,
+            }
+            .into();
+            (geographic.longitude, geographic.latitude)
+        };
+        let polygons = polygons(
+            &grid,
+            grid_information.width,
+            grid_information.height,
+            threshold as f64,
+        )
+        .into_iter()
+        .map(|polygon| {
+            polygon
+                .into_iter()
+                .map(|ring| ring.into_iter().map(to_geographic).collect())
+                .collect()
+        })
+        .collect();
+        Self {
+            time,
+            threshold,
+            polygons,
+        }
+    }
+
+    /// A GeoJSON `Feature` with a `MultiPolygon` geometry (without polygons if no value reaches
+    /// the threshold). Coordinates are rounded to 5 decimals (about 1 m).
+    pub fn to_geojson(&self, interval: chrono::Duration) -> serde_json::Value {
+        let round = |value: f64| (value * 1e5).round() / 1e5;
+        let coordinates: Vec<Vec<Vec<[f64; 2]>>> = self
+            .polygons
+            .iter()
+            .map(|polygon| {
+                polygon
+                    .iter()
+                    .map(|ring| ring.iter().map(|&(x, y)| [round(x), round(y)]).collect())
+                    .collect()
+            })
+            .collect();
+        serde_json::json!({
+            "type": "Feature",
+            "geometry": {
+                "type": "MultiPolygon",
+                "coordinates": coordinates,
+            },
+            "properties": {
+                "time": self.time.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+                "threshold": self.threshold,
+                // values are 1/100 mm per time slot
+                "threshold_mm_per_hour":
+                    self.threshold as f64 / 100. * 3600. / interval.num_seconds() as f64,
+            },
+        })
+    }
+}
+
+/// A GeoJSON `FeatureCollection` with a feature (see [`Contour::to_geojson`]) for every available
+/// time and every threshold
+pub fn geojson<T: RainRadarValues + ?Sized>(
+    values: &T,
+    thresholds: &[u16],
+) -> Result<serde_json::Value> {
+    ensure!(
+        thresholds.iter().all(|&threshold| threshold > 0),
+        "Thresholds must be positive"
+    );
+    let interval = values.time_information().interval;
+    let mut features = Vec::new();
+    for time in values.available_times() {
+        for &threshold in thresholds {
+            features.push(
+                Contour::from_rain_radar_values(values, time, threshold).to_geojson(interval),
+            );
+        }
+    }
+    Ok(serde_json::json!({
+        "type": "FeatureCollection",
+        "features": features,
+    }))
+}
+
+/// Position of a crossing of the contour with the line between two neighbouring centers: the
+/// line to the right (`false`) or below (`true`) the center (x, y) of the grid padded by one dry
+/// value on each side
+type Crossing = (usize, usize, bool);
+
+/// Rings of all areas of `grid` with at least `threshold`, grouped to polygons (outer ring
+/// followed by its holes)
+fn polygons(grid: &[f64], width: usize, height: usize, threshold: f64) -> Vec<Vec<Ring>> {
+    let value = |x: usize, y: usize| {
+        if x == 0 || y == 0 || x > width || y > height {
+            0.
+        } else {
+            grid[(y - 1) * width + (x - 1)]
+        }
+    };
+
+    // every crossing starts exactly one segment, all segments have the area to their left
+    // (in a coordinate system with y growing downwards)
+    let mut next: HashMap<Crossing, Crossing> = HashMap::new();
+    let mut positions: HashMap<Crossing, (f64, f64)> = HashMap::new();
+    for y in 0..=height {
+        for x in 0..=width {
+            // clockwise, edge k goes from corner k to corner k + 1
+            let corners = [(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)];
+            let values = corners.map(|(x, y)| value(x, y));
+            let inside = values.map(|value| value >= threshold);
+            if inside.iter().all(|&inside| inside) || inside.iter().all(|&inside| !inside) {
+                continue;
+            }
+            let crossing = |edge: usize| -> Crossing {
+                match edge {
+                    0 => (x, y, false),
+                    1 => (x + 1, y, true),
+                    2 => (x, y + 1, false),
+                    _ => (x, y, true),
+                }
+            };
+            let entries: Vec<usize> = (0..4)
+                .filter(|&edge| !inside[edge] && inside[(edge + 1) % 4])
+                .collect();
+            let exits: Vec<usize> = (0..4)
+                .filter(|&edge| inside[edge] && !inside[(edge + 1) % 4])
+                .collect();
+            for &entry in &entries {
+                let exit = if entries.len() == 1 {
+                    exits[0]
+                } else if values.iter().sum::<f64>() / 4. >= threshold {
+                    // saddle with the inside connected through the center
+                    (entry + 3) % 4
+                } else {
+                    (entry + 1) % 4
+                };
+                debug_assert!(exits.contains(&exit));
+                next.insert(crossing(entry), crossing(exit));
+            }
+            for edge in entries.into_iter().chain(exits) {
+                let (from, to) = (corners[edge], corners[(edge + 1) % 4]);
+                let fraction = ((threshold - values[edge])
+                    / (values[(edge + 1) % 4] - values[edge]))
+                    .clamp(0., 1.);
+                // without padding
+                let position = |from: usize, to: usize| {
+                    from as f64 + (to as f64 - from as f64) * fraction - 1.
+                };
+                positions.insert(
+                    crossing(edge),
+                    (position(from.0, to.0), position(from.1, to.1)),
+                );
+            }
+        }
+    }
+
+    let mut outer_rings = Vec::new();
+    let mut holes = Vec::new();
+    while let Some(&start) = next.keys().next() {
+        let mut ring = vec![positions[&start]];
+        let mut crossing = next.remove(&start).unwrap();
+        while crossing != start {
+            ring.push(positions[&crossing]);
+            crossing = next
+                .remove(&crossing)
+                .expect("Contour is not closed (this shouldn't happen)");
+        }
+        ring.push(ring[0]);
+        // outer rings have the area to their left, which is clockwise with y growing downwards
+        if signed_area(&ring) < 0. {
+            outer_rings.push(ring);
+        } else {
+            holes.push(ring);
+        }
+    }
+
+    let mut polygons: Vec<Vec<Ring>> = outer_rings.into_iter().map(|ring| vec![ring]).collect();
+    for hole in holes {
+        // the smallest outer ring around the hole
+        let polygon = polygons
+            .iter_mut()
+            .filter(|polygon| contains(&polygon[0], hole[0]))
+            .min_by(|a, b| signed_area(&b[0]).total_cmp(&signed_area(&a[0])))
+            .expect("Hole outside of all areas (this shouldn't happen)");
+        polygon.push(hole);
+    }
+    polygons
+}
+
+/// Positive for counterclockwise rings (with y growing upwards)
+fn signed_area(ring: &[(f64, f64)]) -> f64 {
+    ring.windows(2)
+        .map(|points| points[0].0 * points[1].1 - points[1].0 * points[0].1)
+        .sum::<f64>()
+        / 2.
+}
+
+fn contains(ring: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+    let mut inside = false;
+    for points in ring.windows(2) {
+        let ((x1, y1), (x2, y2)) = (points[0], points[1]);
+        if (y1 > y) != (y2 > y) && x < x1 + (y - y1) / (y2 - y1) * (x2 - x1) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RainRadarValuesExt;
+
+    #[test]
+    fn test_polygons() {
+        // a square with a hole, a single value inside the hole and two values touching diagonally
+        let mut grid = vec![0.; 30 * 20];
+        for y in 2..12 {
+            for x in 2..12 {
+                grid[y * 30 + x] = 100.;
+            }
+        }
+        for y in 4..10 {
+            for x in 4..10 {
+                grid[y * 30 + x] = 0.;
+            }
+        }
+        grid[6 * 30 + 6] = 100.;
+        grid[15 * 30 + 20] = 90.;
+        grid[16 * 30 + 21] = 90.;
+
+        let mut areas = polygons(&grid, 30, 20, 50.);
+        areas.sort_by(|a, b| signed_area(&a[0]).total_cmp(&signed_area(&b[0])));
+        // the diagonal values are separate (the center of the saddle is below the threshold)
+        assert_eq!(areas.len(), 4);
+        assert_eq!(
+            areas
+                .iter()
+                .map(|polygon| polygon.len())
+                .collect::<Vec<_>>(),
+            [2, 1, 1, 1]
+        );
+        let square = &areas[0];
+        // halfway between the centers, the corners of the area and of the hole are cut
+        assert_eq!(signed_area(&square[0]), -(100. - 4. * 0.125));
+        assert_eq!(signed_area(&square[1]), 36. - 4. * 0.125);
+        assert!(square[0]
+            .iter()
+            .all(|&(x, y)| (1.5..=11.5).contains(&x) && (1.5..=11.5).contains(&y)));
+        assert_eq!(signed_area(&areas[1][0]), -0.5);
+        // the contour crosses 4/9 of the way from a value of 90 to 0
+        assert!(areas[2..]
+            .iter()
+            .all(|polygon| (signed_area(&polygon[0]) + 2. * (4f64 / 9.).powi(2)).abs() < 1e-9));
+        for polygon in &areas {
+            for ring in polygon {
+                assert_eq!(ring.first(), ring.last());
+            }
+        }
+
+        // with a lower threshold, the diagonal values are connected through the saddle
+        assert_eq!(polygons(&grid, 30, 20, 40.).len(), 3);
+        // areas at the edge of the grid are closed
+        assert_eq!(polygons(&[100.; 4], 2, 2, 50.).len(), 1);
+    }
+
+    #[test]
+    fn test_geojson() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(28, 420, 300, 2);
+        let cropped = crate::Crop::new(&test_values, 400..420, 280..300);
+        let square = (&cropped)
+            .map_values(|_| Some(100))
+            .mask(|x, y| (5..15).contains(&x) && (5..15).contains(&y))
+            .fill_missing(0);
+
+        let time = cropped.time_information().last_time();
+        let contour = Contour::from_rain_radar_values(&square, time, 50);
+        assert_eq!(contour.polygons.len(), 1);
+        assert_eq!(contour.polygons[0].len(), 1);
+        // counterclockwise in longitude and latitude, between the centers of the cells
+        assert!(signed_area(&contour.polygons[0][0]) > 0.);
+        for &(longitude, latitude) in &contour.polygons[0][0] {
+            let stereographic: StereographicCoordinates = GeographicCoordinates {
+                latitude,
+                longitude,
+            }
+            .into();
+            assert!(
+                (404.5..=415.5).contains(&stereographic.x),
+                "{stereographic:?}"
+            );
+            assert!(
+                (284.5..=295.5).contains(&stereographic.y),
+                "{stereographic:?}"
+            );
+        }
+
+        let collection = geojson(&square, &[50, 200])?;
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), 2 * 2);
+        assert_eq!(features[2]["properties"]["time"], "2022-05-01T12:10:00Z");
+        assert_eq!(features[2]["properties"]["threshold"], 50);
+        assert_eq!(features[2]["properties"]["threshold_mm_per_hour"], 6.);
+        assert_eq!(features[2]["geometry"]["type"], "MultiPolygon");
+        assert_eq!(
+            features[2]["geometry"]["coordinates"][0][0]
+                .as_array()
+                .unwrap()
+                .len(),
+            contour.polygons[0][0].len()
+        );
+        // nothing reaches 200
+        assert_eq!(
+            features[3]["geometry"]["coordinates"],
+            serde_json::json!([])
+        );
+
+        // all outlines of the noisy test values are closed
+        let noisy = Contour::from_rain_radar_values(&test_values, time, 100);
+        assert!(!noisy.polygons.is_empty());
+        assert!(noisy
+            .polygons
+            .iter()
+            .flatten()
+            .all(|ring| ring.len() >= 4 && ring.first() == ring.last()));
+        assert!(geojson(&test_values, &[0]).is_err());
+        Ok(())
+    }
+}
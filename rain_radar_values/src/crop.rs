@@ -0,0 +1,165 @@
+use crate::{GridInformation, RainRadarValues, TimeInformation};
+
+/// A rectangular part of other rain radar values. Coordinates start at 0 at the top left corner
+/// of the crop, its position in the full grid is available via
+/// [`RainRadarValues::grid_offset`] (and kept when encoding it as
+/// [`crate::CompressedRainRadarValues`]).
+pub struct Crop<'s, T: RainRadarValues> {
+    source: &'s T,
+    x: std::ops::Range<usize>,
+    y: std::ops::Range<usize>,
+}
+
+impl<'s, T: RainRadarValues> Crop<'s, T> {
+    /// `x` and `y` are coordinates of `source`
+    pub fn new(source: &'s T, x: std::ops::Range<usize>, y: std::ops::Range<usize>) -> Self {
+        let grid_information = source.grid_information();
+        assert!(!x.is_empty() && !y.is_empty(), "Crop {x:?}x{y:?} is empty");
+        assert!(
+            x.end <= grid_information.width && y.end <= grid_information.height,
+            "Crop {x:?}x{y:?} exceeds the grid of {}x{}",
+            grid_information.width,
+            grid_information.height
+        );
+        Self { source, x, y }
+    }
+}
+
+/// Coordinates of the crop translated to coordinates of the source
+#[derive(Clone)]
+pub struct ShiftedRange<R: crate::Range> {
+    inner: R,
+    offset: usize,
+    /// Exclusive limit of the coordinates in the crop
+    limit: usize,
+}
+
+impl<R: crate::Range> std::iter::Iterator for ShiftedRange<R> {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        self.inner.next().map(|coordinate| {
+            assert!(coordinate < self.limit);
+            coordinate + self.offset
+        })
+    }
+}
+
+impl<'s, T: RainRadarValues> RainRadarValues for Crop<'s, T> {
+    type Iter<'a, X: crate::Range, Y: crate::Range>
+        = T::Iter<'a, ShiftedRange<X>, ShiftedRange<Y>>
+    where
+        Self: 'a;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        self.source.for_area(
+            time,
+            ShiftedRange {
+                inner: x,
+                offset: self.x.start,
+                limit: self.x.len(),
+            },
+            ShiftedRange {
+                inner: y,
+                offset: self.y.start,
+                limit: self.y.len(),
+            },
+        )
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        self.source.time_information()
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        GridInformation {
+            width: self.x.len(),
+            height: self.y.len(),
+        }
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        let (x_offset, y_offset) = self.source.grid_offset();
+        let cell_size = self.source.cell_size();
+        (
+            x_offset + self.x.start * cell_size,
+            y_offset + self.y.start * cell_size,
+        )
+    }
+
+    fn cell_size(&self) -> usize {
+        self.source.cell_size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CompressedRainRadarValues, Compression, EncoderOptions};
+
+    #[test]
+    fn test_crop() -> anyhow::Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(11, 400, 300, 2);
+        let crop = Crop::new(&test_values, 130..290, 45..222);
+        assert_eq!(crop.grid_information().width, 160);
+        assert_eq!(crop.grid_information().height, 177);
+        assert_eq!(crop.grid_offset(), (130, 45));
+
+        for time in test_values.available_times() {
+            assert!(crop.for_area(time, 0..160, 0..177).eq(test_values.for_area(
+                time,
+                130..290,
+                45..222
+            )));
+            assert!(crop
+                .for_area(time, 10..=20, 100..101)
+                .eq(test_values.for_area(time, 140..=150, 145..146)));
+        }
+
+        // crops of crops add up their offsets
+        let inner_crop = Crop::new(&crop, 5..10, 7..8);
+        assert_eq!(inner_crop.grid_offset(), (135, 52));
+
+        let compressed = CompressedRainRadarValues::from_rain_radar_values_with_options(
+            &crop,
+            &EncoderOptions {
+                compression: Compression::Lzma(1),
+                ..Default::default()
+            },
+        );
+        let compressed = CompressedRainRadarValues::from_data(compressed.data())?;
+        assert_eq!(compressed.grid_offset(), (130, 45));
+        crate::test_values::assert_same_values(&crop, &compressed);
+        assert!(
+            compressed.data().len()
+                < CompressedRainRadarValues::from_rain_radar_values_with_options(
+                    &test_values,
+                    &EncoderOptions {
+                        compression: Compression::Lzma(1),
+                        ..Default::default()
+                    },
+                )
+                .data()
+                .len()
+        );
+        assert_eq!(
+            CompressedRainRadarValues::from_rain_radar_values(&test_values).grid_offset(),
+            (0, 0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_crop_rejects_coordinates_outside_of_it() {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(12, 100, 100, 1);
+        let crop = Crop::new(&test_values, 10..20, 10..20);
+        let time = test_values.time_information().first_time;
+        crop.for_area(time, 0..11, 0..1).for_each(drop);
+    }
+}
@@ -8,9 +8,9 @@ use std::io::Read;
 trait ReadExt: Read {
     fn integer(&mut self, length: usize) -> Result<u32> {
         let mut vec = vec![0u8; length];
-        self.read_exact(&mut *vec)
+        self.read_exact(&mut vec)
             .with_context(|| anyhow!("Failed reading integer of length {length}"))?;
-        std::str::from_utf8(&*vec)
+        std::str::from_utf8(&vec)
             .with_context(|| {
                 anyhow!("Failed converting file contents of length {length} to UTF-8")
             })?
@@ -82,10 +82,12 @@ impl DWDRainRadarValues {
                 let month = entry.integer(2).context("Failed extracting month")?;
                 let year = entry.integer(2).context("Failed extracting year")?;
 
-                let this_time = NaiveDateTime::new(
-                    NaiveDate::from_ymd(2000 + year as i32, month, day),
-                    NaiveTime::from_hms(hour, minute, 0),
-                );
+                // DWD products are always stamped in UTC
+                let this_time = NaiveDate::from_ymd_opt(2000 + year as i32, month, day)
+                    .and_then(|date| date.and_hms_opt(hour, minute, 0))
+                    .with_context(|| {
+                        anyhow!("Invalid time {year}-{month}-{day} {hour}:{minute}")
+                    })?;
 
                 match base_time {
                     Some(base_time) => {
@@ -234,16 +236,10 @@ impl RainRadarValues for DWDRainRadarValues {
         x: X,
         y: Y,
     ) -> Self::Iter<'_, X, Y> {
-        let duration = time - self.base_time;
-        let prediction_index: usize = (duration.num_minutes() / 5)
-            .try_into()
-            .expect("prediction_index is not usize");
-        assert_eq!(
-            chrono::Duration::minutes(prediction_index as i64 * 5),
-            duration,
-            "Illegal duration: Not multiple of 5 minutes"
-        );
-        assert!(prediction_index < self.predictions.len());
+        let prediction_index = self
+            .time_information()
+            .time_slot(time)
+            .unwrap_or_else(|| panic!("Illegal time {time}: Not one of the available times"));
         Iterator {
             radar_values: self,
             prediction_index,
@@ -254,9 +250,14 @@ impl RainRadarValues for DWDRainRadarValues {
     fn time_information(&self) -> super::TimeInformation {
         super::TimeInformation {
             first_time: self.base_time,
-            available_time_slots: 25,
+            available_time_slots: self.predictions.len() as u32,
+            interval: super::TimeInformation::dwd_interval(),
         }
     }
+
+    fn grid_information(&self) -> super::GridInformation {
+        super::GridInformation::dwd()
+    }
 }
 
 #[cfg(test)]
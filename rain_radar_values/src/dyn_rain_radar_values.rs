@@ -0,0 +1,151 @@
+//! Object safe counterpart of [`RainRadarValues`], so that the source of the values can be chosen at
+//! runtime, e.g. `Vec<Box<dyn DynRainRadarValues>>`
+
+use crate::{GridInformation, RainRadarValues, TimeInformation};
+
+/// Implemented for every [`RainRadarValues`]. `Box<dyn DynRainRadarValues>` implements
+/// [`RainRadarValues`] again, so it can be used with everything that accepts rain radar values.
+///
+/// The methods are prefixed with `dyn_` so that they don't clash with the ones of
+/// [`RainRadarValues`] if both traits are in scope.
+pub trait DynRainRadarValues {
+    /// Same as [`RainRadarValues::for_area`] with the coordinates given as lists (x is still the
+    /// inner loop)
+    fn dyn_for_area(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: Vec<usize>,
+        y: Vec<usize>,
+    ) -> Box<dyn Iterator<Item = Option<u16>> + '_>;
+
+    /// Reads all values in the area at once
+    fn dyn_read_area(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: std::ops::Range<usize>,
+        y: std::ops::Range<usize>,
+    ) -> Vec<Option<u16>>;
+
+    fn dyn_time_information(&self) -> TimeInformation;
+
+    fn dyn_grid_information(&self) -> GridInformation;
+
+    fn dyn_grid_offset(&self) -> (usize, usize);
+
+    fn dyn_cell_size(&self) -> usize;
+}
+
+impl<T: RainRadarValues> DynRainRadarValues for T {
+    fn dyn_for_area(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: Vec<usize>,
+        y: Vec<usize>,
+    ) -> Box<dyn Iterator<Item = Option<u16>> + '_> {
+        Box::new(self.for_area(time, x.into_iter(), y.into_iter()))
+    }
+
+    fn dyn_read_area(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: std::ops::Range<usize>,
+        y: std::ops::Range<usize>,
+    ) -> Vec<Option<u16>> {
+        self.for_area(time, x, y).collect()
+    }
+
+    fn dyn_time_information(&self) -> TimeInformation {
+        self.time_information()
+    }
+
+    fn dyn_grid_information(&self) -> GridInformation {
+        self.grid_information()
+    }
+
+    fn dyn_grid_offset(&self) -> (usize, usize) {
+        self.grid_offset()
+    }
+
+    fn dyn_cell_size(&self) -> usize {
+        self.cell_size()
+    }
+}
+
+impl<D: DynRainRadarValues + ?Sized> RainRadarValues for Box<D> {
+    type Iter<'a, X: crate::Range, Y: crate::Range>
+        = Box<dyn Iterator<Item = Option<u16>> + 'a>
+    where
+        Self: 'a;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        (**self).dyn_for_area(time, x.collect(), y.collect())
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        (**self).dyn_time_information()
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        (**self).dyn_grid_information()
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        (**self).dyn_grid_offset()
+    }
+
+    fn cell_size(&self) -> usize {
+        (**self).dyn_cell_size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{CompressedRainRadarValues, Crop, RainRadarValuesExt};
+
+    #[test]
+    fn test_dyn_rain_radar_values() -> anyhow::Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(16, 420, 300, 3);
+        let compressed = CompressedRainRadarValues::from_data(
+            CompressedRainRadarValues::from_rain_radar_values(&test_values).data(),
+        )?;
+        let time = test_values.time_information().first_time + chrono::Duration::minutes(5);
+
+        let sources: Vec<Box<dyn DynRainRadarValues>> = vec![
+            Box::new(crate::test_values::TestRainRadarValues::with_size(
+                16, 420, 300, 3,
+            )),
+            Box::new(compressed),
+            Box::new(
+                crate::test_values::TestRainRadarValues::with_size(16, 420, 300, 3).fill_missing(0),
+            ),
+        ];
+        for source in &sources[..2] {
+            crate::test_values::assert_same_values(&test_values, source);
+        }
+        // also usable by reference, e.g. by adapters
+        crate::test_values::assert_same_values(
+            &Crop::new(&test_values, 10..400, 0..300),
+            &Crop::new(&sources[1], 10..400, 0..300),
+        );
+
+        assert_eq!(
+            sources[1].dyn_read_area(time, 300..420, 10..20),
+            test_values
+                .for_area(time, 300..420, 10..20)
+                .collect::<Vec<_>>()
+        );
+        assert!(sources[2]
+            .for_area(time, 0..420, 0..300)
+            .all(|value| value.is_some()));
+        assert!(sources[0]
+            .dyn_for_area(time, vec![5, 400], vec![299, 0])
+            .eq(test_values.for_area(time, [5, 400].into_iter(), [299, 0].into_iter())));
+        Ok(())
+    }
+}
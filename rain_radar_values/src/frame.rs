@@ -0,0 +1,330 @@
+//! A single time slot of rain radar values that can be passed around on its own. With the `serde`
+//! feature, it is serialised with a run-length encoding of the values, so dry and missing areas
+//! take hardly any space.
+
+use crate::{CrossIteratorExt, GridInformation, RainRadarValues, TimeInformation};
+
+/// Values of a single time slot. Implements [`RainRadarValues`] with just this time slot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "FrameRepresentation", into = "FrameRepresentation")
+)]
+pub struct Frame {
+    time: chrono::NaiveDateTime,
+    interval: chrono::Duration,
+    width: usize,
+    height: usize,
+    grid_offset: (usize, usize),
+    cell_size: usize,
+    /// Row by row, x is the inner loop
+    values: Vec<Option<u16>>,
+}
+
+impl Frame {
+    pub fn from_rain_radar_values<T: RainRadarValues>(
+        from: &T,
+        time: chrono::NaiveDateTime,
+    ) -> Self {
+        let grid_information = from.grid_information();
+        Self {
+            time,
+            interval: from.time_information().interval,
+            width: grid_information.width,
+            height: grid_information.height,
+            grid_offset: from.grid_offset(),
+            cell_size: from.cell_size(),
+            values: from
+                .for_area(time, 0..grid_information.width, 0..grid_information.height)
+                .collect(),
+        }
+    }
+
+    pub fn time(&self) -> chrono::NaiveDateTime {
+        self.time
+    }
+
+    pub fn value(&self, x: usize, y: usize) -> Option<u16> {
+        assert!(x < self.width && y < self.height);
+        self.values[y * self.width + x]
+    }
+
+    /// Runs of equal values as (number of values, value), row by row
+    pub fn runs(&self) -> Vec<(usize, Option<u16>)> {
+        let mut runs: Vec<(usize, Option<u16>)> = Vec::new();
+        for value in &self.values {
+            match runs.last_mut() {
+                Some((length, last_value)) if last_value == value => *length += 1,
+                _ => runs.push((1, *value)),
+            }
+        }
+        runs
+    }
+}
+
+pub struct Iterator<'a, X: crate::Range, Y: crate::Range> {
+    frame: &'a Frame,
+    current_index_iter: crate::CrossProduct<X, Y>,
+}
+
+impl<'a, X: crate::Range, Y: crate::Range> std::iter::Iterator for Iterator<'a, X, Y> {
+    type Item = Option<u16>;
+
+    fn next(&mut self) -> Option<Option<u16>> {
+        self.current_index_iter
+            .next()
+            .map(|(x, y)| self.frame.value(x, y))
+    }
+}
+
+impl RainRadarValues for Frame {
+    type Iter<'a, X: crate::Range, Y: crate::Range> = Iterator<'a, X, Y>;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        assert_eq!(
+            time, self.time,
+            "Illegal time {time}: Not the time of the frame"
+        );
+        Iterator {
+            frame: self,
+            current_index_iter: x.cross_product(y),
+        }
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        TimeInformation {
+            first_time: self.time,
+            available_time_slots: 1,
+            interval: self.interval,
+        }
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        GridInformation {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        self.grid_offset
+    }
+
+    fn cell_size(&self) -> usize {
+        self.cell_size
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct FrameRepresentation {
+    time: chrono::NaiveDateTime,
+    #[serde(rename = "interval_seconds", with = "crate::serde_seconds")]
+    interval: chrono::Duration,
+    width: usize,
+    height: usize,
+    grid_offset: (usize, usize),
+    /// 1 if missing, see [`RainRadarValues::cell_size`]
+    #[serde(default = "default_cell_size")]
+    cell_size: usize,
+    /// See [`Frame::runs`]
+    runs: Vec<(usize, Option<u16>)>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Frame> for FrameRepresentation {
+    fn from(frame: Frame) -> Self {
+        Self {
+            runs: frame.runs(),
+            time: frame.time,
+            interval: frame.interval,
+            width: frame.width,
+            height: frame.height,
+            grid_offset: frame.grid_offset,
+            cell_size: frame.cell_size,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn default_cell_size() -> usize {
+    1
+}
+
+/// Largest number of values of a deserialised frame, many times the values of the DE4800 grid
+#[cfg(feature = "serde")]
+const MAX_VALUES: usize = 100_000_000;
+
+#[cfg(feature = "serde")]
+impl TryFrom<FrameRepresentation> for Frame {
+    type Error = anyhow::Error;
+
+    fn try_from(representation: FrameRepresentation) -> anyhow::Result<Self> {
+        use anyhow::Context;
+        anyhow::ensure!(representation.cell_size > 0, "Cell size is 0");
+        // checked before allocating anything
+        let number_of_values = representation
+            .width
+            .checked_mul(representation.height)
+            .filter(|&number_of_values| number_of_values <= MAX_VALUES)
+            .with_context(|| {
+                format!(
+                    "Grid size {}x{} exceeds {MAX_VALUES} values",
+                    representation.width, representation.height
+                )
+            })?;
+        let mut values = Vec::with_capacity(number_of_values);
+        for (length, value) in representation.runs {
+            anyhow::ensure!(
+                length <= number_of_values - values.len(),
+                "Runs contain more than the {number_of_values} values of the grid"
+            );
+            values.extend(std::iter::repeat_n(value, length));
+        }
+        anyhow::ensure!(
+            values.len() == number_of_values,
+            "Runs contain {} values instead of the {number_of_values} values of the grid",
+            values.len()
+        );
+        Ok(Self {
+            time: representation.time,
+            interval: representation.interval,
+            width: representation.width,
+            height: representation.height,
+            grid_offset: representation.grid_offset,
+            cell_size: representation.cell_size,
+            values,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RainRadarValuesExt;
+
+    #[test]
+    fn test_frame() {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(17, 420, 300, 3);
+        let time = test_values.time_information().last_time();
+        let frame =
+            Frame::from_rain_radar_values(&crate::Crop::new(&test_values, 20..420, 0..300), time);
+
+        assert_eq!(frame.time(), time);
+        assert_eq!(frame.grid_offset(), (20, 0));
+        assert!(frame.available_times().eq([time]));
+        assert!(frame
+            .for_area(time, 0..400, 0..300)
+            .eq(test_values.for_area(time, 20..420, 0..300)));
+        assert_eq!(
+            frame.value(399, 299),
+            test_values
+                .for_area(time, 419..420, 299..300)
+                .next()
+                .unwrap()
+        );
+
+        let runs = frame.runs();
+        assert_eq!(
+            runs.iter().map(|(length, _)| length).sum::<usize>(),
+            400 * 300
+        );
+        assert!(runs.windows(2).all(|runs| runs[0].1 != runs[1].1));
+        assert!(runs.len() < 400 * 300 / 2);
+        // a dry frame is a single run
+        let dry = (&test_values).map_values(|_| Some(0));
+        assert_eq!(
+            Frame::from_rain_radar_values(&dry, time).runs(),
+            vec![(420 * 300, Some(0))]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() -> anyhow::Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(18, 420, 300, 3);
+        let time = test_values.time_information().first_time;
+        let frame = Frame::from_rain_radar_values(&test_values, time);
+
+        let json = serde_json::to_string(&frame)?;
+        assert!(json.contains(r#""time":"2022-05-01T12:05:00""#));
+        assert!(json.contains(r#""interval_seconds":300"#));
+        assert_eq!(serde_json::from_str::<Frame>(&json)?, frame);
+        assert!(json.len() < serde_json::to_string(&frame.values)?.len());
+
+        // runs have to cover the grid exactly
+        let too_long = json.replacen("[[", "[[2,null],[", 1);
+        assert!(serde_json::from_str::<Frame>(&too_long).is_err());
+        // huge grids are rejected before allocating their values
+        let huge = serde_json::json!({
+            "time": "2022-05-01T12:05:00",
+            "interval_seconds": 300,
+            "width": 1_000_000_000usize,
+            "height": 1_000_000_000usize,
+            "grid_offset": [0, 0],
+            "runs": [[1_000_000_000_000_000u64, null]],
+        });
+        let error = serde_json::from_value::<Frame>(huge).unwrap_err();
+        assert!(error.to_string().contains("exceeds"));
+        let overflowing = serde_json::json!({
+            "time": "2022-05-01T12:05:00",
+            "interval_seconds": 300,
+            "width": usize::MAX,
+            "height": 2usize,
+            "grid_offset": [0, 0],
+            "runs": [],
+        });
+        assert!(serde_json::from_value::<Frame>(overflowing).is_err());
+        // grids larger than the DWD grid, e.g. DE4800, round-trip
+        let large = crate::test_values::TestRainRadarValues::with_size(18, 1500, 1300, 1);
+        let frame = Frame::from_rain_radar_values(&large, large.time_information().first_time);
+        let json = serde_json::to_string(&frame)?;
+        assert_eq!(serde_json::from_str::<Frame>(&json)?, frame);
+
+        let time_information = test_values.time_information();
+        let json = serde_json::to_value(time_information)?;
+        assert_eq!(json["available_time_slots"], 3);
+        assert_eq!(json["interval_seconds"], 300);
+        assert_eq!(
+            serde_json::from_value::<TimeInformation>(json)?,
+            time_information
+        );
+
+        let compressed = crate::CompressedRainRadarValues::from_rain_radar_values_with_options(
+            &test_values,
+            &crate::EncoderOptions {
+                quantisation: crate::Quantisation::Step(4),
+                ..Default::default()
+            },
+        );
+        let header = compressed.header();
+        assert_eq!(
+            serde_json::from_str::<crate::Header>(&serde_json::to_string(&header)?)?,
+            header
+        );
+
+        let point_forecast = test_values.point_forecast(410, 290);
+        assert_eq!(point_forecast.values.len(), 3);
+        assert_eq!(
+            serde_json::from_str::<crate::PointForecast>(&serde_json::to_string(&point_forecast)?)?,
+            point_forecast
+        );
+
+        #[cfg(feature = "coordinates_mapper")]
+        {
+            let geographic: coordinates_mapper::GeographicCoordinates =
+                serde_json::from_str(r#"{"latitude": 51.0, "longitude": 9.0}"#)?;
+            let stereographic: coordinates_mapper::StereographicCoordinates = geographic.into();
+            let json = serde_json::to_value(stereographic)?;
+            assert_eq!(json["x"], stereographic.x);
+            assert_eq!(json["y"], stereographic.y);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+//! Export as GeoTIFF in the polar stereographic projection of the DWD grid, so the values can be
+//! used in GIS software like QGIS. The georeferencing is derived from the constants of
+//! [`coordinates_mapper`].
+
+use crate::RainRadarValues;
+use anyhow::{ensure, Context, Result};
+use coordinates_mapper::{
+    LATITUDE_OF_TRUE_SCALE, LONGITUDE_OF_PROJECTION_ORIGIN, OFFSET_X, OFFSET_Y, RADIUS_OF_EARTH,
+};
+use std::io::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Unit {
+    /// u16 as stored by DWD (1/100 mm per time slot), missing values are [`RAW_NO_DATA`]
+    Raw,
+    /// f32, missing values are [`MILLIMETRES_PER_HOUR_NO_DATA`]
+    MillimetresPerHour,
+}
+
+pub const RAW_NO_DATA: u16 = u16::MAX;
+pub const MILLIMETRES_PER_HOUR_NO_DATA: f32 = -1.;
+
+#[derive(Debug, Clone)]
+pub struct GeoTiffOptions {
+    pub unit: Unit,
+}
+
+impl Default for GeoTiffOptions {
+    fn default() -> Self {
+        Self {
+            unit: Unit::MillimetresPerHour,
+        }
+    }
+}
+
+/// Affine transformation from pixel coordinates (of the corners) to projected coordinates in metres,
+/// in the order used by GDAL: x origin, x pixel size, 0, y origin, 0, y pixel size (negative, as y
+/// grows to the south in the grid, but to the north in the projection)
+pub fn geotransform<T: RainRadarValues + ?Sized>(values: &T) -> [f64; 6] {
+    let (offset_x, offset_y) = values.grid_offset();
+    // stereographic coordinates are in km, with the cell (x, y) between x and x + 1
+    let cell_size = values.cell_size() as f64 * 1000.;
+    [
+        (offset_x as f64 - OFFSET_X) * 1000.,
+        cell_size,
+        0.,
+        (OFFSET_Y - offset_y as f64) * 1000.,
+        0.,
+        -cell_size,
+    ]
+}
+
+/// Writes a GeoTIFF with one band per time in `times`
+pub fn write_geotiff<T: RainRadarValues + ?Sized, W: Write>(
+    values: &T,
+    times: &[chrono::NaiveDateTime],
+    options: &GeoTiffOptions,
+    mut writer: W,
+) -> Result<()> {
+    ensure!(!times.is_empty(), "GeoTIFF needs at least one time");
+    let grid_information = values.grid_information();
+    let (width, height) = (grid_information.width, grid_information.height);
+    let bands = times.len();
+    let bytes_per_value = match options.unit {
+        Unit::Raw => 2,
+        Unit::MillimetresPerHour => 4,
+    };
+    let band_size: u32 = (width * height * bytes_per_value)
+        .try_into()
+        .context("Grid is too large for TIFF")?;
+    ensure!(
+        (band_size as u64) * (bands as u64) < u32::MAX as u64 / 2,
+        "Too many time slots for a single TIFF file"
+    );
+    let bands_u16: u16 = bands.try_into().context("Too many time slots for TIFF")?;
+
+    let [origin_x, pixel_width, _, origin_y, _, pixel_height] = geotransform(values);
+    let (sample_format, no_data, unit) = match options.unit {
+        Unit::Raw => (1, RAW_NO_DATA.to_string(), "1/100 mm"),
+        Unit::MillimetresPerHour => (3, MILLIMETRES_PER_HOUR_NO_DATA.to_string(), "mm/h"),
+    };
+    let mut metadata = String::from("<GDALMetadata>");
+    for (band, time) in times.iter().enumerate() {
+        metadata += &format!(
+            "<Item name=\"DESCRIPTION\" sample=\"{band}\" role=\"description\">{}</Item>\
+             <Item name=\"UNITTYPE\" sample=\"{band}\" role=\"unittype\">{unit}</Item>",
+            time.format("%Y-%m-%dT%H:%M:%SZ")
+        );
+    }
+    metadata += "</GDALMetadata>";
+
+    // ordered by tag, the strip offsets are filled in once the size of the header is known
+    let mut entries = vec![
+        Entry::long(256, &[width as u32]),
+        Entry::long(257, &[height as u32]),
+        Entry::short(258, &vec![8 * bytes_per_value as u16; bands]),
+        // no compression
+        Entry::short(259, &[1]),
+        // black is zero
+        Entry::short(262, &[1]),
+        Entry::long(273, &vec![0; bands]),
+        Entry::short(277, &[bands_u16]),
+        Entry::long(278, &[height as u32]),
+        Entry::long(279, &vec![band_size; bands]),
+        // bands one after another
+        Entry::short(284, &[2]),
+    ];
+    if bands > 1 {
+        // unspecified meaning
+        entries.push(Entry::short(338, &vec![0; bands - 1]));
+    }
+    entries.extend([
+        Entry::short(339, &vec![sample_format; bands]),
+        Entry::double(33550, &[pixel_width, -pixel_height, 0.]),
+        Entry::double(33922, &[0., 0., 0., origin_x, origin_y, 0.]),
+        Entry::short(34735, &GEO_KEY_DIRECTORY),
+        Entry::double(34736, &geo_double_params()),
+        Entry::ascii(34737, GEO_ASCII_PARAMS),
+        Entry::ascii(42112, &metadata),
+        Entry::ascii(42113, &no_data),
+    ]);
+
+    let ifd_size = 2 + entries.len() * 12 + 4;
+    let mut overflow_size: usize = entries
+        .iter()
+        .filter(|entry| entry.data.len() > 4)
+        .map(|entry| entry.data.len().next_multiple_of(2))
+        .sum();
+    overflow_size = overflow_size.next_multiple_of(4);
+    let data_offset: u32 = (8 + ifd_size + overflow_size)
+        .try_into()
+        .context("TIFF header is too large")?;
+    let strip_offsets: Vec<u32> = (0..bands as u32)
+        .map(|band| data_offset + band * band_size)
+        .collect();
+    entries[5] = Entry::long(273, &strip_offsets);
+
+    let mut header = Vec::with_capacity(data_offset as usize);
+    // little endian, version 42, first IFD directly after the header
+    header.extend_from_slice(b"II");
+    header.extend_from_slice(&42u16.to_le_bytes());
+    header.extend_from_slice(&8u32.to_le_bytes());
+    header.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+    let mut overflow = Vec::with_capacity(overflow_size);
+    for entry in &entries {
+        header.extend_from_slice(&entry.tag.to_le_bytes());
+        header.extend_from_slice(&entry.field_type.to_le_bytes());
+        header.extend_from_slice(&entry.count.to_le_bytes());
+        if entry.data.len() > 4 {
+            let offset = (8 + ifd_size + overflow.len()) as u32;
+            header.extend_from_slice(&offset.to_le_bytes());
+            overflow.extend_from_slice(&entry.data);
+            if overflow.len() % 2 == 1 {
+                overflow.push(0);
+            }
+        } else {
+            let mut value = [0; 4];
+            value[..entry.data.len()].copy_from_slice(&entry.data);
+            header.extend_from_slice(&value);
+        }
+    }
+    // no further IFDs
+    header.extend_from_slice(&0u32.to_le_bytes());
+    overflow.resize(overflow_size, 0);
+    header.extend_from_slice(&overflow);
+    assert_eq!(header.len(), data_offset as usize);
+    writer
+        .write_all(&header)
+        .context("Failed writing GeoTIFF header")?;
+
+    let millimetres_per_hour =
+        3600. / values.time_information().interval.num_seconds() as f32 / 100.;
+    for &time in times {
+        let mut band = Vec::with_capacity(band_size as usize);
+        for value in values.for_area(time, 0..width, 0..height) {
+            match options.unit {
+                Unit::Raw => band.extend_from_slice(&value.unwrap_or(RAW_NO_DATA).to_le_bytes()),
+                Unit::MillimetresPerHour => band.extend_from_slice(
+                    &value
+                        .map(|value| value as f32 * millimetres_per_hour)
+                        .unwrap_or(MILLIMETRES_PER_HOUR_NO_DATA)
+                        .to_le_bytes(),
+                ),
+            }
+        }
+        writer
+            .write_all(&band)
+            .context("Failed writing GeoTIFF band")?;
+    }
+    Ok(())
+}
+
+/// Writes all available times to `path`, one band per time slot
+pub fn save_geotiff<T: RainRadarValues + ?Sized, P: AsRef<std::path::Path>>(
+    values: &T,
+    path: P,
+    options: &GeoTiffOptions,
+) -> Result<()> {
+    let path = path.as_ref();
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed creating {}", path.display()))?;
+    let times: Vec<_> = values.available_times().collect();
+    let mut writer = std::io::BufWriter::new(file);
+    write_geotiff(values, &times, options, &mut writer)?;
+    writer.flush().context("Failed writing GeoTIFF")
+}
+
+/// Writes every time slot to `<directory>/<time>.tif`
+pub fn save_geotiffs<T: RainRadarValues + ?Sized, P: AsRef<std::path::Path>>(
+    values: &T,
+    directory: P,
+    options: &GeoTiffOptions,
+) -> Result<()> {
+    let directory = directory.as_ref();
+    std::fs::create_dir_all(directory)
+        .with_context(|| format!("Failed creating {}", directory.display()))?;
+    for time in values.available_times() {
+        let path = directory.join(time.format("%Y%m%d%H%M%S.tif").to_string());
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("Failed creating {}", path.display()))?;
+        let mut writer = std::io::BufWriter::new(file);
+        write_geotiff(values, &[time], options, &mut writer)?;
+        writer.flush().context("Failed writing GeoTIFF")?;
+    }
+    Ok(())
+}
+
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    data: Vec<u8>,
+}
+
+impl Entry {
+    fn short(tag: u16, values: &[u16]) -> Self {
+        Self {
+            tag,
+            field_type: 3,
+            count: values.len() as u32,
+            data: values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    fn long(tag: u16, values: &[u32]) -> Self {
+        Self {
+            tag,
+            field_type: 4,
+            count: values.len() as u32,
+            data: values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    fn double(tag: u16, values: &[f64]) -> Self {
+        Self {
+            tag,
+            field_type: 12,
+            count: values.len() as u32,
+            data: values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    /// NUL terminated
+    fn ascii(tag: u16, value: &str) -> Self {
+        let mut data = value.as_bytes().to_vec();
+        data.push(0);
+        Self {
+            tag,
+            field_type: 2,
+            count: data.len() as u32,
+            data,
+        }
+    }
+}
+
+const GEO_ASCII_PARAMS: &str = "RADOLAN polar stereographic|RADOLAN sphere|";
+
+/// A user defined polar stereographic projection on a sphere. Entries are key, location of the
+/// value (0: the entry itself, 34736: the double parameters, 34737: the ASCII parameters), count
+/// and value (or index into the parameters).
+#[rustfmt::skip]
+const GEO_KEY_DIRECTORY: [u16; 4 * 20] = [
+    // version 1.1.0, number of keys
+    1, 1, 0, 19,
+    // projected
+    1024, 0, 1, 1,
+    // pixels are areas
+    1025, 0, 1, 1,
+    1026, 34737, 28, 0,
+    // user defined geographic system, datum and ellipsoid
+    2048, 0, 1, 32767,
+    2049, 34737, 15, 28,
+    2050, 0, 1, 32767,
+    // Greenwich
+    2051, 0, 1, 8901,
+    // degrees
+    2054, 0, 1, 9102,
+    2056, 0, 1, 32767,
+    // semi major and semi minor axis
+    2057, 34736, 1, 0,
+    2058, 34736, 1, 1,
+    // user defined projection
+    3072, 0, 1, 32767,
+    3074, 0, 1, 32767,
+    // polar stereographic
+    3075, 0, 1, 15,
+    // metres
+    3076, 0, 1, 9001,
+    // latitude of true scale, false easting and false northing
+    3081, 34736, 1, 2,
+    3082, 34736, 1, 3,
+    3083, 34736, 1, 4,
+    // longitude of the projection origin
+    3095, 34736, 1, 5,
+];
+
+fn geo_double_params() -> [f64; 6] {
+    [
+        RADIUS_OF_EARTH * 1000.,
+        RADIUS_OF_EARTH * 1000.,
+        LATITUDE_OF_TRUE_SCALE,
+        0.,
+        0.,
+        LONGITUDE_OF_PROJECTION_ORIGIN,
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::tags::Tag;
+
+    #[test]
+    fn test_geotransform() {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(23, 1100, 1200, 1);
+        let transform = geotransform(&test_values);
+        // the center of the cell at (469, 599) is 51° N, 9° E (see coordinates_mapper)
+        let easting = transform[0] + 469.5 * transform[1];
+        let northing = transform[3] + 599.5 * transform[5];
+        let distance_to_pole = easting.hypot(northing);
+        let longitude = LONGITUDE_OF_PROJECTION_ORIGIN + easting.atan2(-northing).to_degrees();
+        let latitude = 90.
+            - 2. * (distance_to_pole
+                / (RADIUS_OF_EARTH * 1000. * (1. + LATITUDE_OF_TRUE_SCALE.to_radians().sin())))
+            .atan()
+            .to_degrees();
+        assert!((longitude - 9.).abs() < 1e-4, "{longitude}");
+        assert!((latitude - 51.).abs() < 1e-4, "{latitude}");
+
+        // a crop starts further south east, a downsampled grid has larger cells
+        let cropped = geotransform(&crate::DownsampledRainRadarValues::from_rain_radar_values(
+            &crate::Crop::new(&test_values, 100..200, 50..60),
+            2,
+            crate::Aggregation::Max,
+        ));
+        assert_eq!(cropped[0], transform[0] + 100_000.);
+        assert_eq!(cropped[3], transform[3] - 50_000.);
+        assert_eq!((cropped[1], cropped[5]), (2000., -2000.));
+    }
+
+    #[test]
+    fn test_geotiff() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(24, 420, 300, 3);
+        let times: Vec<_> = test_values.available_times().collect();
+
+        // one band
+        let mut data = Vec::new();
+        write_geotiff(
+            &test_values,
+            &times[1..2],
+            &GeoTiffOptions::default(),
+            &mut data,
+        )?;
+        let mut decoder = Decoder::new(std::io::Cursor::new(&data))?;
+        assert_eq!(decoder.dimensions()?, (420, 300));
+        let transform = geotransform(&test_values);
+        assert_eq!(
+            decoder.get_tag_f64_vec(Tag::from_u16_exhaustive(33922))?,
+            vec![0., 0., 0., transform[0], transform[3], 0.]
+        );
+        assert_eq!(
+            decoder.get_tag_f64_vec(Tag::from_u16_exhaustive(33550))?,
+            vec![1000., 1000., 0.]
+        );
+        let geo_keys = decoder.get_tag_u16_vec(Tag::from_u16_exhaustive(34735))?;
+        assert_eq!(geo_keys.len(), 4 * (geo_keys[3] as usize + 1));
+        assert!(geo_keys[4..]
+            .chunks(4)
+            .zip(geo_keys[8..].chunks(4))
+            .all(|(a, b)| a[0] < b[0]));
+        assert_eq!(
+            decoder.get_tag_ascii_string(Tag::from_u16_exhaustive(42113))?,
+            "-1"
+        );
+        assert!(decoder
+            .get_tag_ascii_string(Tag::from_u16_exhaustive(42112))?
+            .contains("2022-05-01T12:10:00Z"));
+        let expected: Vec<f32> = test_values
+            .for_area(times[1], 0..420, 0..300)
+            .map(|value| value.map_or(-1., |value| value as f32 * 0.12))
+            .collect();
+        match decoder.read_image()? {
+            DecodingResult::F32(values) => assert_eq!(values, expected),
+            _ => panic!("Expected f32 values"),
+        }
+
+        // raw units with a band per time slot, stored one after another
+        let mut data = Vec::new();
+        write_geotiff(
+            &test_values,
+            &times,
+            &GeoTiffOptions { unit: Unit::Raw },
+            &mut data,
+        )?;
+        let mut decoder = Decoder::new(std::io::Cursor::new(&data))?;
+        assert_eq!(decoder.get_tag_u32(Tag::SamplesPerPixel)?, 3);
+        assert_eq!(decoder.get_tag_u32(Tag::PlanarConfiguration)?, 2);
+        assert_eq!(
+            decoder.get_tag_ascii_string(Tag::from_u16_exhaustive(42113))?,
+            "65535"
+        );
+        let offsets = decoder.get_tag_u32_vec(Tag::StripOffsets)?;
+        assert_eq!(data.len(), offsets[2] as usize + 420 * 300 * 2);
+        for (time, offset) in times.iter().zip(offsets) {
+            let band = data[offset as usize..][..420 * 300 * 2]
+                .chunks(2)
+                .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]));
+            assert!(band.eq(test_values
+                .for_area(*time, 0..420, 0..300)
+                .map(|value| value.unwrap_or(RAW_NO_DATA))));
+        }
+        Ok(())
+    }
+}
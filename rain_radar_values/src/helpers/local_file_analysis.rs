@@ -1,6 +1,4 @@
 use lazy_static::lazy_static;
-#[cfg(feature = "rayon")]
-use rayon::prelude::*;
 
 lazy_static! {
     static ref ALL_FILES: Vec<std::path::PathBuf> = {
@@ -21,15 +19,8 @@ lazy_static! {
     };
 }
 
-#[cfg(feature = "rayon")]
-type SelectedFilesIter = impl IntoIterator<Item = &'static std::path::PathBuf>
-    + rayon::iter::IntoParallelIterator<
-        Iter = impl IndexedParallelIterator<Item = &'static std::path::PathBuf>,
-        Item = &'static std::path::PathBuf,
-    >;
-#[cfg(not(feature = "rayon"))]
-type SelectedFilesIter = impl IntoIterator<Item = &'static std::path::PathBuf>;
-pub fn selected_files() -> SelectedFilesIter {
+/// Some randomly chosen files (both a sequential and, with rayon, a parallel iterator)
+pub fn selected_files() -> Vec<&'static std::path::PathBuf> {
     use rand::prelude::*;
     let total_number_of_files = ALL_FILES.as_slice().len();
     if total_number_of_files == 0 {
@@ -37,24 +28,17 @@ pub fn selected_files() -> SelectedFilesIter {
     }
 
     let number_of_files = 25;
-    let random_file = rand::distributions::WeightedIndex::new(
-        std::iter::repeat(1).take(total_number_of_files),
-    )
-    .expect("WeightedIndex::new failed");
+    let random_file =
+        rand::distributions::WeightedIndex::new(std::iter::repeat_n(1, total_number_of_files))
+            .expect("WeightedIndex::new failed");
 
     (0..number_of_files)
         .map(|_| &ALL_FILES.as_slice()[random_file.sample(&mut thread_rng())])
         .collect::<Vec<&std::path::PathBuf>>()
 }
 
-#[cfg(feature = "rayon")]
-type AllFilesIter = impl IntoIterator<Item = &'static std::path::PathBuf>
-    + rayon::iter::IntoParallelIterator<
-        Iter = impl IndexedParallelIterator<Item = &'static std::path::PathBuf>,
-        Item = &'static std::path::PathBuf,
-    >;
-#[cfg(not(feature = "rayon"))]
-type AllFilesIter = impl IntoIterator<Item = &'static std::path::PathBuf>;
-pub fn all_files() -> AllFilesIter {
+// only used by the bins
+#[cfg(feature = "local_file_analysis")]
+pub fn all_files() -> &'static [std::path::PathBuf] {
     &ALL_FILES[..]
 }
@@ -3,3 +3,12 @@ pub(crate) use cross_product::*;
 
 #[cfg(any(test, feature = "local_file_analysis"))]
 pub mod local_file_analysis;
+
+#[cfg(feature = "serde")]
+pub(crate) mod serde_seconds;
+
+#[cfg(feature = "render")]
+pub(crate) mod bitmap_font;
+
+#[cfg(test)]
+pub(crate) mod test_values;
@@ -0,0 +1,18 @@
+//! Serialises a `chrono::Duration` as whole seconds, use with `#[serde(with = "...")]`
+
+use serde::{Deserialize, Deserializer, Serializer};
+
+pub(crate) fn serialize<S: Serializer>(
+    duration: &chrono::Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(duration.num_seconds())
+}
+
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<chrono::Duration, D::Error> {
+    let seconds = i64::deserialize(deserializer)?;
+    chrono::Duration::try_seconds(seconds)
+        .ok_or_else(|| serde::de::Error::custom(format!("{seconds} seconds are out of range")))
+}
@@ -0,0 +1,180 @@
+use crate::{CrossIteratorExt, GridInformation, RainRadarValues, TimeInformation};
+use rand::prelude::*;
+
+/// Synthetic rain radar values for tests that should not depend on downloaded files
+pub(crate) struct TestRainRadarValues {
+    first_time: chrono::naive::NaiveDateTime,
+    interval: chrono::Duration,
+    width: usize,
+    height: usize,
+    predictions: Vec<Vec<Option<u16>>>,
+}
+
+impl TestRainRadarValues {
+    /// Generates some rain cells that drift from slot to slot, a missing area in the top left
+    /// corner and a few values that need 16 bit
+    pub(crate) fn new(seed: u64) -> Self {
+        Self::with_size(seed, 1100, 1200, 25)
+    }
+
+    pub(crate) fn with_size(seed: u64, width: usize, height: usize, time_slots: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let cells: Vec<(f64, f64, f64, f64)> = (0..12)
+            .map(|_| {
+                (
+                    rng.gen_range(0.0..width as f64),
+                    rng.gen_range(0.0..height as f64),
+                    rng.gen_range(20.0..120.0),
+                    rng.gen_range(10.0..400.0),
+                )
+            })
+            .collect();
+
+        let predictions = (0..time_slots)
+            .map(|slot| {
+                let mut values = vec![Some(0); width * height];
+                for (center_x, center_y, radius, intensity) in &cells {
+                    let center_x = center_x + slot as f64 * 3.;
+                    let center_y = center_y + slot as f64 * 2.;
+                    let min_x = (center_x - radius).max(0.) as usize;
+                    let max_x = (center_x + radius).min(width as f64 - 1.) as usize;
+                    let min_y = (center_y - radius).max(0.) as usize;
+                    let max_y = (center_y + radius).min(height as f64 - 1.) as usize;
+                    for y in min_y..=max_y {
+                        for x in min_x..=max_x {
+                            let distance = ((x as f64 - center_x).powi(2)
+                                + (y as f64 - center_y).powi(2))
+                            .sqrt();
+                            if distance < *radius {
+                                let value = (intensity * (1. - distance / radius)) as u16;
+                                let pixel = &mut values[y * width + x];
+                                *pixel = Some(u16::max(pixel.unwrap_or(0), value));
+                            }
+                        }
+                    }
+                }
+                for y in 0..usize::min(250, height) {
+                    for x in 0..usize::min(300 - y, width) {
+                        values[y * width + x] = None;
+                    }
+                }
+                for _ in 0..20 {
+                    let index = rng.gen_range(0..values.len());
+                    values[index] = None;
+                }
+                values
+            })
+            .collect();
+
+        Self {
+            first_time: chrono::NaiveDate::from_ymd_opt(2022, 5, 1)
+                .unwrap()
+                .and_hms_opt(12, 5, 0)
+                .unwrap(),
+            interval: TimeInformation::dwd_interval(),
+            width,
+            height,
+            predictions,
+        }
+    }
+
+    /// Same values, but for a forecast issued at another time
+    pub(crate) fn with_first_time(self, first_time: chrono::NaiveDateTime) -> Self {
+        Self { first_time, ..self }
+    }
+
+    /// Same values, but for a product with another interval (e.g. hourly)
+    pub(crate) fn with_interval(self, interval: chrono::Duration) -> Self {
+        Self { interval, ..self }
+    }
+}
+
+pub(crate) struct Iterator<'a, X: crate::Range, Y: crate::Range> {
+    radar_values: &'a TestRainRadarValues,
+    prediction_index: usize,
+    current_index_iter: crate::CrossProduct<X, Y>,
+}
+
+impl<'a, X: crate::Range, Y: crate::Range> std::iter::Iterator for Iterator<'a, X, Y> {
+    type Item = Option<u16>;
+
+    fn next(&mut self) -> Option<Option<u16>> {
+        self.current_index_iter.next().map(|(x, y)| {
+            assert!(x < self.radar_values.width);
+            self.radar_values.predictions[self.prediction_index][y * self.radar_values.width + x]
+        })
+    }
+}
+
+impl RainRadarValues for TestRainRadarValues {
+    type Iter<'a, X: crate::Range, Y: crate::Range> = Iterator<'a, X, Y>;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        let prediction_index = self
+            .time_information()
+            .time_slot(time)
+            .unwrap_or_else(|| panic!("Illegal time {time}: Not one of the available times"));
+        Iterator {
+            radar_values: self,
+            prediction_index,
+            current_index_iter: x.cross_product(y),
+        }
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        TimeInformation {
+            first_time: self.first_time,
+            available_time_slots: self.predictions.len() as u32,
+            interval: self.interval,
+        }
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        GridInformation {
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// Asserts that both sources contain exactly the same values
+pub(crate) fn assert_same_values<T: RainRadarValues, U: RainRadarValues>(expected: &T, actual: &U) {
+    let expected_times: Vec<chrono::NaiveDateTime> = expected.available_times().collect();
+    let actual_times: Vec<chrono::NaiveDateTime> = actual.available_times().collect();
+    assert_eq!(expected_times, actual_times);
+
+    for time in expected_times {
+        assert_same_values_at(expected, actual, time);
+    }
+}
+
+/// Asserts that both sources contain exactly the same values at the given time
+pub(crate) fn assert_same_values_at<T: RainRadarValues, U: RainRadarValues>(
+    expected: &T,
+    actual: &U,
+    time: chrono::NaiveDateTime,
+) {
+    let grid_information = expected.grid_information();
+    assert_eq!(grid_information.width, actual.grid_information().width);
+    assert_eq!(grid_information.height, actual.grid_information().height);
+
+    let mut expected_values =
+        expected.for_area(time, 0..grid_information.width, 0..grid_information.height);
+    let mut actual_values =
+        actual.for_area(time, 0..grid_information.width, 0..grid_information.height);
+    for (index, (expected_value, actual_value)) in
+        (&mut expected_values).zip(&mut actual_values).enumerate()
+    {
+        assert_eq!(
+            expected_value, actual_value,
+            "{expected_value:?} != {actual_value:?} (index {index}, time {time})"
+        )
+    }
+    assert!(expected_values.next().is_none());
+    assert!(actual_values.next().is_none());
+}
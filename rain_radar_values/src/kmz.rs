@@ -0,0 +1,267 @@
+//! Export as KMZ for Google Earth: every time slot is rendered (see [`crate::render`]) to a
+//! `GroundOverlay` with a `TimeSpan`, so the time slider animates the forecast. The grid is not
+//! aligned to latitude and longitude, so the images are warped to the corners of the area
+//! (computed with [`coordinates_mapper`]) by `gx:LatLonQuad`.
+
+use crate::render::{Image, RenderOptions};
+use crate::RainRadarValues;
+use anyhow::{Context, Result};
+use coordinates_mapper::{GeographicCoordinates, StereographicCoordinates};
+use std::io::Write;
+
+/// Writes a KMZ with a ground overlay for every available time. The legend and timestamp of
+/// `options` are ignored, they would be warped with the image.
+pub fn write_kmz<T: RainRadarValues + ?Sized, W: Write>(
+    values: &T,
+    options: &RenderOptions,
+    writer: W,
+) -> Result<()> {
+    let options = RenderOptions {
+        legend: false,
+        timestamp: false,
+        ..options.clone()
+    };
+    let grid_information = values.grid_information();
+    let (x, y) = options
+        .area
+        .clone()
+        .unwrap_or((0..grid_information.width, 0..grid_information.height));
+    let (offset_x, offset_y) = values.grid_offset();
+    let cell_size = values.cell_size();
+    let corner = |x: usize, y: usize| {
+        let geographic: GeographicCoordinates = StereographicCoordinates {
+            x: (offset_x + x * cell_size) as f64,
+            y: (offset_y + y * cell_size) as f64,
+        }
+        .into();
+        format!("{:.6},{:.6}", geographic.longitude, geographic.latitude)
+    };
+    // counterclockwise, starting at the lower left corner
+    let quad = [
+        corner(x.start, y.end),
+        corner(x.end, y.end),
+        corner(x.end, y.start),
+        corner(x.start, y.start),
+    ]
+    .join(" ");
+
+    let interval = values.time_information().interval;
+    let file_name =
+        |time: chrono::NaiveDateTime| time.format("images/%Y%m%d%H%M%S.png").to_string();
+    let timestamp = |time: chrono::NaiveDateTime| time.format("%Y-%m-%dT%H:%M:%SZ");
+    let mut kml = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <kml xmlns=\"http://www.opengis.net/kml/2.2\" xmlns:gx=\"http://www.google.com/kml/ext/2.2\">\n\
+         <Document>\n<name>DWD precipitation nowcast</name>\n",
+    );
+    for time in values.available_times() {
+        kml += &format!(
+            "<GroundOverlay>\n<name>{}</name>\n\
+             <TimeSpan><begin>{}</begin><end>{}</end></TimeSpan>\n\
+             <Icon><href>{}</href></Icon>\n\
+             <gx:LatLonQuad><coordinates>{quad}</coordinates></gx:LatLonQuad>\n\
+             </GroundOverlay>\n",
+            time.format("%Y-%m-%d %H:%M UTC"),
+            timestamp(time),
+            timestamp(time + interval),
+            file_name(time),
+        );
+    }
+    kml += "</Document>\n</kml>\n";
+
+    // Google Earth reads the first KML file in the archive
+    let mut kmz = Zip::new(writer);
+    kmz.add_file("doc.kml", kml.as_bytes())?;
+    for time in values.available_times() {
+        let mut png = Vec::new();
+        Image::from_rain_radar_values(values, time, &options).write_png(&mut png)?;
+        kmz.add_file(&file_name(time), &png)?;
+    }
+    kmz.finish()
+}
+
+pub fn save_kmz<T: RainRadarValues + ?Sized, P: AsRef<std::path::Path>>(
+    values: &T,
+    options: &RenderOptions,
+    path: P,
+) -> Result<()> {
+    let path = path.as_ref();
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed creating {}", path.display()))?;
+    let mut writer = std::io::BufWriter::new(file);
+    write_kmz(values, options, &mut writer)?;
+    writer.flush().context("Failed writing KMZ")
+}
+
+// A ZIP archive with uncompressed files (the PNGs are compressed already): for every file a
+// local header followed by its content, then the central directory with a header for every file
+// and the end of central directory record. All numbers are little endian.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+/// 2.0, the first version with directories
+const VERSION: u16 = 20;
+/// 1980-01-01, the earliest date possible
+const DATE: u16 = 0x21;
+
+struct Zip<W: Write> {
+    writer: W,
+    offset: u32,
+    central_directory: Vec<u8>,
+    files: u16,
+}
+
+impl<W: Write> Zip<W> {
+    fn new(writer: W) -> Self {
+        Self {
+            writer,
+            offset: 0,
+            central_directory: Vec::new(),
+            files: 0,
+        }
+    }
+
+    fn add_file(&mut self, name: &str, content: &[u8]) -> Result<()> {
+        let size: u32 = content
+            .len()
+            .try_into()
+            .context("File is too large for ZIP")?;
+        let crc = crc32fast::hash(content);
+        // version, flags, method (stored), time, date, CRC, compressed and uncompressed size,
+        // length of the name and of the extra field
+        let mut common = Vec::new();
+        for value in [VERSION, 0, 0, 0, DATE] {
+            common.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [crc, size, size] {
+            common.extend_from_slice(&value.to_le_bytes());
+        }
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        let mut header = LOCAL_HEADER_SIGNATURE.to_le_bytes().to_vec();
+        header.extend_from_slice(&common);
+        header.extend_from_slice(name.as_bytes());
+        self.writer
+            .write_all(&header)
+            .and_then(|_| self.writer.write_all(content))
+            .context("Failed writing KMZ")?;
+
+        self.central_directory
+            .extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        // version made by
+        self.central_directory
+            .extend_from_slice(&VERSION.to_le_bytes());
+        self.central_directory.extend_from_slice(&common);
+        // comment length, disk, internal and external attributes, offset of the local header
+        for value in [0u16, 0, 0] {
+            self.central_directory
+                .extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0u32, self.offset] {
+            self.central_directory
+                .extend_from_slice(&value.to_le_bytes());
+        }
+        self.central_directory.extend_from_slice(name.as_bytes());
+
+        self.offset = self
+            .offset
+            .checked_add((header.len() + content.len()) as u32)
+            .context("KMZ is too large")?;
+        self.files = self
+            .files
+            .checked_add(1)
+            .context("Too many files for ZIP")?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<()> {
+        let mut end = END_OF_CENTRAL_DIRECTORY_SIGNATURE.to_le_bytes().to_vec();
+        // this disk, disk with the central directory, files on this disk and in total
+        for value in [0, 0, self.files, self.files] {
+            end.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [self.central_directory.len() as u32, self.offset] {
+            end.extend_from_slice(&value.to_le_bytes());
+        }
+        // comment length
+        end.extend_from_slice(&0u16.to_le_bytes());
+        self.writer
+            .write_all(&self.central_directory)
+            .and_then(|_| self.writer.write_all(&end))
+            .context("Failed writing KMZ")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_kmz() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(29, 420, 300, 3);
+        let cropped = crate::Crop::new(&test_values, 100..420, 0..300);
+        let options = RenderOptions {
+            area: Some((200..320, 100..300)),
+            legend: true,
+            ..Default::default()
+        };
+        let mut kmz = Vec::new();
+        write_kmz(&cropped, &options, &mut kmz)?;
+
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(kmz))?;
+        assert_eq!(archive.len(), 1 + 3);
+        assert_eq!(archive.by_index(0)?.name(), "doc.kml");
+        let mut kml = String::new();
+        archive.by_name("doc.kml")?.read_to_string(&mut kml)?;
+        assert_eq!(kml.matches("<GroundOverlay>").count(), 3);
+        assert!(kml.contains(
+            "<TimeSpan><begin>2022-05-01T12:10:00Z</begin><end>2022-05-01T12:15:00Z</end></TimeSpan>"
+        ));
+        assert!(kml.contains("<href>images/20220501121500.png</href>"));
+
+        // lower left corner of the area in the full grid
+        let lower_left: GeographicCoordinates =
+            StereographicCoordinates { x: 300., y: 300. }.into();
+        let quad = kml
+            .split("<gx:LatLonQuad><coordinates>")
+            .nth(1)
+            .and_then(|quad| quad.split('<').next())
+            .unwrap();
+        let corners: Vec<Vec<f64>> = quad
+            .split(' ')
+            .map(|corner| {
+                corner
+                    .split(',')
+                    .map(|value| value.parse().unwrap())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(corners.len(), 4);
+        assert!((corners[0][0] - lower_left.longitude).abs() < 1e-6);
+        assert!((corners[0][1] - lower_left.latitude).abs() < 1e-6);
+        // north is up and east is right, roughly
+        assert!(corners[3][1] > corners[0][1] && corners[1][0] > corners[0][0]);
+
+        // the images are rendered without legend
+        let time = cropped.available_times().nth(1).unwrap();
+        let mut png = Vec::new();
+        archive
+            .by_name("images/20220501121000.png")?
+            .read_to_end(&mut png)?;
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(png)).read_info()?;
+        let mut decoded = vec![0; decoder.output_buffer_size()];
+        decoder.next_frame(&mut decoded)?;
+        let expected = Image::from_rain_radar_values(
+            &cropped,
+            time,
+            &RenderOptions {
+                legend: false,
+                ..options.clone()
+            },
+        );
+        assert_eq!(decoded, expected.bytes());
+        Ok(())
+    }
+}
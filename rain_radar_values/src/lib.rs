@@ -1,16 +1,57 @@
-#![feature(generic_associated_types)]
-#![feature(type_alias_impl_trait)]
-#![feature(allocator_api)]
-
 mod rain_radar_values;
 pub use crate::rain_radar_values::*;
 
 mod dwd_rain_radar_values;
-pub use dwd_rain_radar_values::*;
+pub use dwd_rain_radar_values::DWDRainRadarValues;
 
 pub mod compressed_rain_radar_values;
 pub use compressed_rain_radar_values::*;
 
+pub mod archive;
+pub use archive::*;
+
+pub mod pyramid;
+pub use pyramid::{Aggregation, DownsampledRainRadarValues, Pyramid, PyramidLevel};
+
+pub mod time_series;
+pub use time_series::*;
+
+pub mod crop;
+pub use crop::Crop;
+
+pub mod adapters;
+pub use adapters::RainRadarValuesExt;
+
+pub mod dyn_rain_radar_values;
+pub use dyn_rain_radar_values::DynRainRadarValues;
+
+pub mod frame;
+pub use frame::Frame;
+
+#[cfg(feature = "render")]
+pub mod render;
+
+#[cfg(feature = "animation")]
+pub mod animation;
+
+#[cfg(feature = "geotiff")]
+pub mod geotiff;
+
+#[cfg(feature = "netcdf")]
+pub mod netcdf;
+
+#[cfg(feature = "text_export")]
+pub mod text_export;
+
+#[cfg(feature = "contours")]
+pub mod contours;
+
+#[cfg(feature = "kmz")]
+pub mod kmz;
+
+#[cfg(feature = "local_time")]
+pub mod local_time;
+
 mod helpers;
 pub(crate) use helpers::*;
 
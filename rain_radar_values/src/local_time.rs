@@ -0,0 +1,112 @@
+//! Conversion of the UTC time slots to German local time (CET/CEST), including the daylight saving
+//! time transitions
+
+use crate::RainRadarValues;
+use chrono::TimeZone;
+use chrono_tz::Europe::Berlin;
+
+pub type BerlinTime = chrono::DateTime<chrono_tz::Tz>;
+
+/// Local time in Germany for a UTC time as used everywhere in this crate
+pub fn to_berlin_time(time: chrono::NaiveDateTime) -> BerlinTime {
+    Berlin.from_utc_datetime(&time)
+}
+
+/// UTC time for a local time in Germany. Local times skipped when switching to summer time have
+/// no result, the ones occurring twice when switching back two.
+pub fn from_berlin_time(
+    local_time: chrono::NaiveDateTime,
+) -> chrono::LocalResult<chrono::NaiveDateTime> {
+    Berlin
+        .from_local_datetime(&local_time)
+        .map(|time| time.naive_utc())
+}
+
+/// Label of a time slot in German local time, e.g. "2022-03-27 03:05 CEST"
+pub fn berlin_label(time: chrono::NaiveDateTime) -> String {
+    to_berlin_time(time).format("%Y-%m-%d %H:%M %Z").to_string()
+}
+
+pub trait LocalTimeExt: RainRadarValues {
+    /// Same as [`RainRadarValues::available_times`], but in German local time
+    fn available_berlin_times(
+        &self,
+    ) -> std::iter::Map<crate::TimeIter, fn(chrono::NaiveDateTime) -> BerlinTime> {
+        self.available_times()
+            .map(to_berlin_time as fn(chrono::NaiveDateTime) -> BerlinTime)
+    }
+
+    /// Same as [`RainRadarValues::for_area`], but with a time in any time zone (e.g. from
+    /// [`Self::available_berlin_times`])
+    fn for_area_at<Z: TimeZone, X: crate::Range, Y: crate::Range>(
+        &self,
+        time: &chrono::DateTime<Z>,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        self.for_area(time.naive_utc(), x, y)
+    }
+}
+
+impl<T: RainRadarValues> LocalTimeExt for T {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn utc(day: u32, month: u32, hour: u32, minute: u32) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2022, month, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_dst_transitions() {
+        // switch to summer time on 2022-03-27 at 01:00 UTC
+        assert_eq!(berlin_label(utc(27, 3, 0, 55)), "2022-03-27 01:55 CET");
+        assert_eq!(berlin_label(utc(27, 3, 1, 0)), "2022-03-27 03:00 CEST");
+        // and back on 2022-10-30 at 01:00 UTC
+        assert_eq!(berlin_label(utc(30, 10, 0, 55)), "2022-10-30 02:55 CEST");
+        assert_eq!(berlin_label(utc(30, 10, 1, 0)), "2022-10-30 02:00 CET");
+
+        assert_eq!(
+            from_berlin_time(utc(27, 3, 2, 30)),
+            chrono::LocalResult::None
+        );
+        assert_eq!(
+            from_berlin_time(utc(30, 10, 2, 30)),
+            chrono::LocalResult::Ambiguous(utc(30, 10, 0, 30), utc(30, 10, 1, 30))
+        );
+        assert_eq!(
+            from_berlin_time(utc(1, 7, 14, 0)),
+            chrono::LocalResult::Single(utc(1, 7, 12, 0))
+        );
+    }
+
+    #[test]
+    fn test_available_berlin_times() {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(15, 400, 300, 25)
+            .with_first_time(utc(27, 3, 0, 0));
+        let berlin_times: Vec<BerlinTime> = test_values.available_berlin_times().collect();
+        assert_eq!(berlin_times.len(), 25);
+        // the local times jump by an hour, but the slots stay 5 minutes apart
+        assert_eq!(berlin_times[11].format("%H:%M").to_string(), "01:55");
+        assert_eq!(berlin_times[12].format("%H:%M").to_string(), "03:00");
+        assert_eq!(
+            berlin_times[12] - berlin_times[11],
+            chrono::Duration::minutes(5)
+        );
+
+        for (berlin_time, utc_time) in berlin_times.iter().zip(test_values.available_times_utc()) {
+            assert_eq!(berlin_time, &utc_time);
+            assert!(test_values
+                .for_area_at(berlin_time, 300..400, 0..300)
+                .eq(test_values.for_area_utc(utc_time, 300..400, 0..300)));
+        }
+        assert_eq!(
+            test_values.time_information().first_time_utc().naive_utc(),
+            utc(27, 3, 0, 0)
+        );
+    }
+}
@@ -0,0 +1,403 @@
+//! Export of all time slots as NetCDF (classic format) following the CF conventions, so the
+//! values can be opened with xarray or used by hydrological models. Precipitation is in mm/h,
+//! latitude and longitude of every cell are computed with [`coordinates_mapper`].
+
+use crate::RainRadarValues;
+use anyhow::{ensure, Context, Result};
+use coordinates_mapper::{
+    GeographicCoordinates, StereographicCoordinates, LATITUDE_OF_TRUE_SCALE,
+    LONGITUDE_OF_PROJECTION_ORIGIN, OFFSET_X, OFFSET_Y, RADIUS_OF_EARTH,
+};
+use std::io::Write;
+
+pub const FILL_VALUE: f32 = -1.;
+
+// Format of the header (big endian, see the NetCDF classic format specification):
+// "CDF" 0x01, number of records (always 0, there is no record dimension), dimensions, global
+// attributes, variables (each with dimension IDs, attributes, type, size and offset of its data)
+// and then the data of every variable, padded to multiples of 4 bytes
+const MAGIC: &[u8; 4] = b"CDF\x01";
+const NC_DIMENSION: u32 = 0x0A;
+const NC_VARIABLE: u32 = 0x0B;
+const NC_ATTRIBUTE: u32 = 0x0C;
+
+const NC_CHAR: u32 = 2;
+const NC_INT: u32 = 4;
+const NC_FLOAT: u32 = 5;
+const NC_DOUBLE: u32 = 6;
+
+const TIME: u32 = 0;
+const Y: u32 = 1;
+const X: u32 = 2;
+
+enum Attribute {
+    Text(String),
+    Float(f32),
+    Double(f64),
+}
+
+struct Variable {
+    name: &'static str,
+    dimensions: Vec<u32>,
+    attributes: Vec<(&'static str, Attribute)>,
+    nc_type: u32,
+    /// Number of values
+    length: usize,
+}
+
+impl Variable {
+    fn size(&self) -> usize {
+        let value_size = match self.nc_type {
+            NC_DOUBLE => 8,
+            _ => 4,
+        };
+        self.length * value_size
+    }
+}
+
+fn text(text: &str) -> Attribute {
+    Attribute::Text(text.to_string())
+}
+
+/// Writes all available times
+pub fn write_netcdf<T: RainRadarValues + ?Sized, W: Write>(
+    values: &T,
+    mut writer: W,
+) -> Result<()> {
+    let time_information = values.time_information();
+    let grid_information = values.grid_information();
+    let (width, height) = (grid_information.width, grid_information.height);
+    let times = time_information.available_time_slots as usize;
+    ensure!(times > 0, "NetCDF needs at least one time slot");
+    let (offset_x, offset_y) = values.grid_offset();
+    let cell_size = values.cell_size();
+    // stereographic coordinates of the center of the value at (x, y)
+    let stereographic = |x: usize, y: usize| StereographicCoordinates {
+        x: (offset_x + x * cell_size) as f64 + cell_size as f64 / 2.,
+        y: (offset_y + y * cell_size) as f64 + cell_size as f64 / 2.,
+    };
+
+    let dimensions = [("time", times), ("y", height), ("x", width)];
+    let global_attributes = [
+        ("Conventions", text("CF-1.8")),
+        ("title", text("DWD radar precipitation nowcast")),
+        ("source", text("Deutscher Wetterdienst, RADOLAN/RADVOR")),
+    ];
+    let variables = vec![
+        Variable {
+            name: "time",
+            dimensions: vec![TIME],
+            attributes: vec![
+                ("standard_name", text("time")),
+                ("units", text("minutes since 1970-01-01 00:00:00")),
+                ("calendar", text("standard")),
+                ("axis", text("T")),
+            ],
+            nc_type: NC_DOUBLE,
+            length: times,
+        },
+        Variable {
+            name: "y",
+            dimensions: vec![Y],
+            attributes: vec![
+                ("standard_name", text("projection_y_coordinate")),
+                ("units", text("m")),
+                ("axis", text("Y")),
+            ],
+            nc_type: NC_DOUBLE,
+            length: height,
+        },
+        Variable {
+            name: "x",
+            dimensions: vec![X],
+            attributes: vec![
+                ("standard_name", text("projection_x_coordinate")),
+                ("units", text("m")),
+                ("axis", text("X")),
+            ],
+            nc_type: NC_DOUBLE,
+            length: width,
+        },
+        Variable {
+            name: "lat",
+            dimensions: vec![Y, X],
+            attributes: vec![
+                ("standard_name", text("latitude")),
+                ("units", text("degrees_north")),
+            ],
+            nc_type: NC_FLOAT,
+            length: width * height,
+        },
+        Variable {
+            name: "lon",
+            dimensions: vec![Y, X],
+            attributes: vec![
+                ("standard_name", text("longitude")),
+                ("units", text("degrees_east")),
+            ],
+            nc_type: NC_FLOAT,
+            length: width * height,
+        },
+        Variable {
+            name: "polar_stereographic",
+            dimensions: Vec::new(),
+            attributes: vec![
+                ("grid_mapping_name", text("polar_stereographic")),
+                (
+                    "straight_vertical_longitude_from_pole",
+                    Attribute::Double(LONGITUDE_OF_PROJECTION_ORIGIN),
+                ),
+                ("latitude_of_projection_origin", Attribute::Double(90.)),
+                (
+                    "standard_parallel",
+                    Attribute::Double(LATITUDE_OF_TRUE_SCALE),
+                ),
+                ("false_easting", Attribute::Double(0.)),
+                ("false_northing", Attribute::Double(0.)),
+                ("earth_radius", Attribute::Double(RADIUS_OF_EARTH * 1000.)),
+            ],
+            nc_type: NC_INT,
+            length: 1,
+        },
+        Variable {
+            name: "precipitation",
+            dimensions: vec![TIME, Y, X],
+            attributes: vec![
+                ("long_name", text("precipitation rate")),
+                ("units", text("mm h-1")),
+                ("_FillValue", Attribute::Float(FILL_VALUE)),
+                ("coordinates", text("lat lon")),
+                ("grid_mapping", text("polar_stereographic")),
+            ],
+            nc_type: NC_FLOAT,
+            length: times * width * height,
+        },
+    ];
+
+    // the size of the header doesn't depend on the offsets of the data, so it is written once
+    // with dummy offsets to measure it
+    let header_size = header(&dimensions, &global_attributes, &variables, &[0; 7]).len();
+    let mut offsets = [0; 7];
+    let mut offset = header_size;
+    for (variable, variable_offset) in variables.iter().zip(&mut offsets) {
+        *variable_offset = offset;
+        offset += variable.size().next_multiple_of(4);
+    }
+    ensure!(
+        i32::try_from(offsets[6]).is_ok(),
+        "Too many values for the NetCDF classic format"
+    );
+    writer
+        .write_all(&header(
+            &dimensions,
+            &global_attributes,
+            &variables,
+            &offsets,
+        ))
+        .context("Failed writing NetCDF header")?;
+
+    let mut data = Vec::new();
+    for time in values.available_times() {
+        data.extend_from_slice(&((time.and_utc().timestamp() / 60) as f64).to_be_bytes());
+    }
+    for y in 0..height {
+        data.extend_from_slice(&((OFFSET_Y - stereographic(0, y).y) * 1000.).to_be_bytes());
+    }
+    for x in 0..width {
+        data.extend_from_slice(&((stereographic(x, 0).x - OFFSET_X) * 1000.).to_be_bytes());
+    }
+    writer
+        .write_all(&data)
+        .context("Failed writing NetCDF coordinates")?;
+
+    let coordinates: Vec<GeographicCoordinates> =
+        crate::CrossIteratorExt::cross_product(0..width, 0..height)
+            .map(|(x, y)| stereographic(x, y).into())
+            .collect();
+    for coordinate in [
+        |coordinates: &GeographicCoordinates| coordinates.latitude,
+        |coordinates: &GeographicCoordinates| coordinates.longitude,
+    ] {
+        let data: Vec<u8> = coordinates
+            .iter()
+            .flat_map(|coordinates| (coordinate(coordinates) as f32).to_be_bytes())
+            .collect();
+        writer
+            .write_all(&data)
+            .context("Failed writing NetCDF coordinates")?;
+    }
+    // the value of the grid mapping variable is irrelevant
+    writer
+        .write_all(&0i32.to_be_bytes())
+        .context("Failed writing NetCDF grid mapping")?;
+
+    let millimetres_per_hour = 3600. / time_information.interval.num_seconds() as f32 / 100.;
+    for time in values.available_times() {
+        let data: Vec<u8> = values
+            .for_area(time, 0..width, 0..height)
+            .flat_map(|value| {
+                value
+                    .map(|value| value as f32 * millimetres_per_hour)
+                    .unwrap_or(FILL_VALUE)
+                    .to_be_bytes()
+            })
+            .collect();
+        writer
+            .write_all(&data)
+            .context("Failed writing NetCDF precipitation")?;
+    }
+    Ok(())
+}
+
+pub fn save_netcdf<T: RainRadarValues + ?Sized, P: AsRef<std::path::Path>>(
+    values: &T,
+    path: P,
+) -> Result<()> {
+    let path = path.as_ref();
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed creating {}", path.display()))?;
+    let mut writer = std::io::BufWriter::new(file);
+    write_netcdf(values, &mut writer)?;
+    writer.flush().context("Failed writing NetCDF")
+}
+
+fn header(
+    dimensions: &[(&str, usize)],
+    global_attributes: &[(&str, Attribute)],
+    variables: &[Variable],
+    offsets: &[usize],
+) -> Vec<u8> {
+    let mut header = Vec::new();
+    let push_u32 =
+        |header: &mut Vec<u8>, value: u32| header.extend_from_slice(&value.to_be_bytes());
+    let push_name = |header: &mut Vec<u8>, name: &str| {
+        push_u32(header, name.len() as u32);
+        header.extend_from_slice(name.as_bytes());
+        header.resize(header.len().next_multiple_of(4), 0);
+    };
+    let push_attributes = |header: &mut Vec<u8>, attributes: &[(&str, Attribute)]| {
+        push_u32(header, NC_ATTRIBUTE);
+        push_u32(header, attributes.len() as u32);
+        for (name, value) in attributes {
+            push_name(header, name);
+            match value {
+                Attribute::Text(text) => {
+                    push_u32(header, NC_CHAR);
+                    push_name(header, text);
+                }
+                Attribute::Float(value) => {
+                    push_u32(header, NC_FLOAT);
+                    push_u32(header, 1);
+                    header.extend_from_slice(&value.to_be_bytes());
+                }
+                Attribute::Double(value) => {
+                    push_u32(header, NC_DOUBLE);
+                    push_u32(header, 1);
+                    header.extend_from_slice(&value.to_be_bytes());
+                }
+            }
+        }
+    };
+
+    header.extend_from_slice(MAGIC);
+    push_u32(&mut header, 0);
+    push_u32(&mut header, NC_DIMENSION);
+    push_u32(&mut header, dimensions.len() as u32);
+    for (name, length) in dimensions {
+        push_name(&mut header, name);
+        push_u32(&mut header, *length as u32);
+    }
+    push_attributes(&mut header, global_attributes);
+    push_u32(&mut header, NC_VARIABLE);
+    push_u32(&mut header, variables.len() as u32);
+    for (variable, offset) in variables.iter().zip(offsets) {
+        push_name(&mut header, variable.name);
+        push_u32(&mut header, variable.dimensions.len() as u32);
+        for dimension in &variable.dimensions {
+            push_u32(&mut header, *dimension);
+        }
+        push_attributes(&mut header, &variable.attributes);
+        push_u32(&mut header, variable.nc_type);
+        // the size is clamped for large variables, readers calculate it from the dimensions
+        push_u32(
+            &mut header,
+            u32::try_from(variable.size().next_multiple_of(4)).unwrap_or(u32::MAX),
+        );
+        push_u32(&mut header, *offset as u32);
+    }
+    header
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_netcdf() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(25, 420, 300, 3);
+        let cropped = crate::Crop::new(&test_values, 400..420, 280..300);
+        let path = std::env::temp_dir().join(format!("test_netcdf_{}.nc", std::process::id()));
+        save_netcdf(&cropped, &path)?;
+        let mut reader = netcdf3::FileReader::open(&path).unwrap();
+        std::fs::remove_file(&path)?;
+
+        let data_set = reader.data_set();
+        assert_eq!(
+            data_set.get_global_attr_as_string("Conventions").as_deref(),
+            Some("CF-1.8")
+        );
+        assert_eq!(data_set.dim_size("time"), Some(3));
+        assert_eq!(data_set.dim_size("y"), Some(20));
+        assert_eq!(data_set.dim_size("x"), Some(20));
+        let precipitation = data_set.get_var("precipitation").unwrap();
+        assert_eq!(precipitation.dim_names(), ["time", "y", "x"]);
+        assert_eq!(precipitation.get_attr_f32("_FillValue"), Some(&[-1.][..]));
+        assert_eq!(
+            precipitation.get_attr_as_string("grid_mapping").as_deref(),
+            Some("polar_stereographic")
+        );
+        assert_eq!(
+            data_set
+                .get_var("polar_stereographic")
+                .unwrap()
+                .get_attr_f64("standard_parallel"),
+            Some(&[60.][..])
+        );
+
+        let times = reader.read_var_f64("time").unwrap();
+        assert!(times
+            .iter()
+            .copied()
+            .eq(cropped
+                .available_times()
+                .map(|time| (time.and_utc().timestamp() / 60) as f64)));
+        let expected: Vec<f32> = cropped
+            .available_times()
+            .flat_map(|time| cropped.for_area(time, 0..20, 0..20))
+            .map(|value| value.map_or(FILL_VALUE, |value| value as f32 * 0.12))
+            .collect();
+        assert_eq!(reader.read_var_f32("precipitation").unwrap(), expected);
+
+        // projected coordinates of the cell centers, y grows to the south in the grid
+        let x = reader.read_var_f64("x").unwrap();
+        let y = reader.read_var_f64("y").unwrap();
+        assert!((x[0] - (400.5 - OFFSET_X) * 1000.).abs() < 1e-6);
+        assert!((y[0] - (OFFSET_Y - 280.5) * 1000.).abs() < 1e-6);
+        assert!(x.windows(2).all(|x| x[1] - x[0] == 1000.));
+        assert!(y.windows(2).all(|y| y[1] - y[0] == -1000.));
+
+        let latitudes = reader.read_var_f32("lat").unwrap();
+        let longitudes = reader.read_var_f32("lon").unwrap();
+        let geographic: GeographicCoordinates = StereographicCoordinates {
+            x: 400.5 + 3.,
+            y: 280.5 + 2.,
+        }
+        .into();
+        assert!((latitudes[2 * 20 + 3] - geographic.latitude as f32).abs() < 1e-4);
+        assert!((longitudes[2 * 20 + 3] - geographic.longitude as f32).abs() < 1e-4);
+        // further south and east
+        assert!(latitudes[0] > latitudes[20 * 20 - 1]);
+        assert!(longitudes[0] < longitudes[20 * 20 - 1]);
+        Ok(())
+    }
+}
@@ -0,0 +1,444 @@
+use crate::{
+    CompressedRainRadarValues, CrossIteratorExt, EncoderOptions, GridInformation, RainRadarValues,
+    TimeInformation,
+};
+use anyhow::{bail, ensure, Context, Result};
+
+/// How the values of the pixels combined into one are aggregated. Missing pixels are ignored, the
+/// result is only missing if all of them are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Aggregation {
+    Max,
+    /// Rounded to the nearest integer
+    Mean,
+}
+
+impl Aggregation {
+    fn id(&self) -> u8 {
+        match self {
+            Aggregation::Max => 0,
+            Aggregation::Mean => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        Ok(match id {
+            0 => Aggregation::Max,
+            1 => Aggregation::Mean,
+            id => bail!("Unknown aggregation {id}"),
+        })
+    }
+}
+
+/// Values with a lower resolution: Every value aggregates a square of `factor`x`factor` values of
+/// the source. The squares are aligned to the full DWD grid, so the values of different crops
+/// match; squares at the edges of the source cover less values if they extend beyond it.
+pub struct DownsampledRainRadarValues {
+    first_time: chrono::NaiveDateTime,
+    interval: chrono::Duration,
+    factor: usize,
+    grid_offset: (usize, usize),
+    cell_size: usize,
+    width: usize,
+    height: usize,
+    predictions: Vec<Vec<Option<u16>>>,
+}
+
+impl DownsampledRainRadarValues {
+    pub fn from_rain_radar_values<T: RainRadarValues>(
+        from: &T,
+        factor: usize,
+        aggregation: Aggregation,
+    ) -> Self {
+        assert!(factor > 0, "Downsampling factor must not be 0");
+        let source_cell_size = from.cell_size();
+        let (offset_x, offset_y) = from.grid_offset();
+        // values of the source in front of it in the first square
+        let skipped_x = (offset_x / source_cell_size) % factor;
+        let skipped_y = (offset_y / source_cell_size) % factor;
+        let grid_information = from.grid_information();
+        let width = (skipped_x + grid_information.width).div_ceil(factor);
+        let height = (skipped_y + grid_information.height).div_ceil(factor);
+        let cell_size = source_cell_size * factor;
+
+        let predictions = from
+            .available_times()
+            .map(|time| {
+                let mut values = Vec::with_capacity(width * height);
+                // sum (or max) and number of present values for every value of a row
+                let mut row: Vec<(u64, u32)> = vec![(0, 0); width];
+                // a single pass over the whole grid, so blocks of compressed sources are decoded
+                // only once
+                for ((x, y), value) in (0..grid_information.width)
+                    .cross_product(0..grid_information.height)
+                    .zip(from.for_area(time, 0..grid_information.width, 0..grid_information.height))
+                {
+                    if let Some(value) = value {
+                        let (aggregate, count) = &mut row[(skipped_x + x) / factor];
+                        *aggregate = match aggregation {
+                            Aggregation::Max => u64::max(*aggregate, value as u64),
+                            Aggregation::Mean => *aggregate + value as u64,
+                        };
+                        *count += 1;
+                    }
+                    let is_row_complete = x == grid_information.width - 1
+                        && ((skipped_y + y) % factor == factor - 1
+                            || y == grid_information.height - 1);
+                    if is_row_complete {
+                        values.extend(row.iter().map(|(aggregate, count)| {
+                            match (aggregation, count) {
+                                (_, 0) => None,
+                                (Aggregation::Max, _) => Some(*aggregate as u16),
+                                (Aggregation::Mean, count) => {
+                                    Some(((*aggregate + *count as u64 / 2) / *count as u64) as u16)
+                                }
+                            }
+                        }));
+                        row.fill((0, 0));
+                    }
+                }
+                values
+            })
+            .collect();
+
+        Self {
+            first_time: from.time_information().first_time,
+            interval: from.time_information().interval,
+            factor,
+            grid_offset: (
+                offset_x - skipped_x * source_cell_size,
+                offset_y - skipped_y * source_cell_size,
+            ),
+            cell_size,
+            width,
+            height,
+            predictions,
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+}
+
+pub struct Iterator<'a, X: crate::Range, Y: crate::Range> {
+    radar_values: &'a DownsampledRainRadarValues,
+    prediction_index: usize,
+    current_index_iter: crate::CrossProduct<X, Y>,
+}
+
+impl<'a, X: crate::Range, Y: crate::Range> std::iter::Iterator for Iterator<'a, X, Y> {
+    type Item = Option<u16>;
+
+    fn next(&mut self) -> Option<Option<u16>> {
+        self.current_index_iter.next().map(|(x, y)| {
+            assert!(x < self.radar_values.width);
+            self.radar_values.predictions[self.prediction_index][y * self.radar_values.width + x]
+        })
+    }
+}
+
+impl RainRadarValues for DownsampledRainRadarValues {
+    type Iter<'a, X: crate::Range, Y: crate::Range> = Iterator<'a, X, Y>;
+
+    fn for_area<X: crate::Range, Y: crate::Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        let prediction_index = self
+            .time_information()
+            .time_slot(time)
+            .unwrap_or_else(|| panic!("Illegal time {time}: Not one of the available times"));
+        Iterator {
+            radar_values: self,
+            prediction_index,
+            current_index_iter: x.cross_product(y),
+        }
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        TimeInformation {
+            first_time: self.first_time,
+            available_time_slots: self.predictions.len() as u32,
+            interval: self.interval,
+        }
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        GridInformation {
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        self.grid_offset
+    }
+
+    fn cell_size(&self) -> usize {
+        self.cell_size
+    }
+}
+
+/// A downsampled level of a [`Pyramid`]
+pub struct PyramidLevel {
+    factor: usize,
+    values: CompressedRainRadarValues,
+}
+
+impl PyramidLevel {
+    /// Number of pixels of the full resolution in x and y direction that are combined into one
+    pub fn factor(&self) -> usize {
+        self.factor
+    }
+
+    pub fn values(&self) -> &CompressedRainRadarValues {
+        &self.values
+    }
+}
+
+/// Downsampled levels of rain radar values with the factors 2, 4, 8, …, stored alongside the full
+/// resolution values
+pub struct Pyramid {
+    aggregation: Aggregation,
+    /// ordered by factor
+    levels: Vec<PyramidLevel>,
+}
+
+// Format of the data of a pyramid:
+// bytes 0 - 7: magic bytes "DWDRRPYR"
+// byte 8: aggregation: 0 => max, 1 => mean
+// byte 9: number of levels
+// bytes 10 - 15: reserved, always 0
+// byte 16 and onwards: for every level (ordered by factor): u32 factor, u32 reserved (always 0),
+//   u64 length of the level data and the level as returned by `CompressedRainRadarValues::data`
+const MAGIC: &[u8; 8] = b"DWDRRPYR";
+const HEADER_SIZE: usize = 16;
+const LEVEL_HEADER_SIZE: usize = 16;
+
+impl Pyramid {
+    /// Generates `levels` levels with the factors 2, 4, …, 2^`levels`. Every level is downsampled
+    /// from the full resolution, so means are exact.
+    pub fn from_rain_radar_values<T: RainRadarValues>(
+        from: &T,
+        aggregation: Aggregation,
+        levels: usize,
+        options: &EncoderOptions,
+    ) -> Self {
+        assert!(
+            levels > 0 && levels < 32,
+            "Unsupported number of levels {levels}"
+        );
+        let levels = (1..=levels)
+            .map(|level| {
+                let factor = 1 << level;
+                let downsampled =
+                    DownsampledRainRadarValues::from_rain_radar_values(from, factor, aggregation);
+                PyramidLevel {
+                    factor,
+                    values: CompressedRainRadarValues::from_rain_radar_values_with_options(
+                        &downsampled,
+                        options,
+                    ),
+                }
+            })
+            .collect();
+        Self {
+            aggregation,
+            levels,
+        }
+    }
+
+    pub fn aggregation(&self) -> Aggregation {
+        self.aggregation
+    }
+
+    pub fn levels(&self) -> &[PyramidLevel] {
+        &self.levels
+    }
+
+    /// The coarsest level that still has at least `width`x`height` pixels, so rendering into an
+    /// output of that size does not lose any resolution. None if only the full resolution is
+    /// detailed enough.
+    pub fn level_for_resolution(&self, width: usize, height: usize) -> Option<&PyramidLevel> {
+        self.levels.iter().rev().find(|level| {
+            let grid_information = level.values.grid_information();
+            grid_information.width >= width && grid_information.height >= height
+        })
+    }
+
+    pub fn data(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(
+            HEADER_SIZE
+                + self
+                    .levels
+                    .iter()
+                    .map(|level| LEVEL_HEADER_SIZE + level.values.data().len())
+                    .sum::<usize>(),
+        );
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&[self.aggregation.id(), self.levels.len() as u8]);
+        data.extend_from_slice(&[0; 6]);
+        for level in &self.levels {
+            data.extend_from_slice(&(level.factor as u32).to_le_bytes());
+            data.extend_from_slice(&[0; 4]);
+            data.extend_from_slice(&(level.values.data().len() as u64).to_le_bytes());
+            data.extend_from_slice(level.values.data());
+        }
+        data
+    }
+
+    pub fn from_data(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() >= HEADER_SIZE,
+            "Data is too short ({} bytes) to contain the header",
+            bytes.len()
+        );
+        ensure!(
+            bytes[0..8] == *MAGIC,
+            "Not a pyramid (found magic bytes {:?})",
+            &bytes[0..8]
+        );
+        let aggregation = Aggregation::from_id(bytes[8])?;
+
+        let mut position = HEADER_SIZE;
+        let mut levels = Vec::with_capacity(bytes[9] as usize);
+        for index in 0..bytes[9] {
+            ensure!(
+                bytes.len() - position >= LEVEL_HEADER_SIZE,
+                "Data is too short to contain the header of level {index}"
+            );
+            let factor =
+                u32::from_le_bytes(bytes[position..(position + 4)].try_into().unwrap()) as usize;
+            let length =
+                u64::from_le_bytes(bytes[(position + 8)..(position + 16)].try_into().unwrap())
+                    as usize;
+            position += LEVEL_HEADER_SIZE;
+            ensure!(
+                bytes.len() - position >= length,
+                "Data is too short to contain level {index}"
+            );
+            ensure!(
+                levels
+                    .last()
+                    .map_or(factor > 1, |previous: &PyramidLevel| factor
+                        > previous.factor),
+                "Levels are not ordered by factor"
+            );
+            levels.push(PyramidLevel {
+                factor,
+                values: CompressedRainRadarValues::from_data(&bytes[position..(position + length)])
+                    .with_context(|| format!("Invalid level {index}"))?,
+            });
+            position += length;
+        }
+        ensure!(
+            position == bytes.len(),
+            "Data is longer than the levels it contains"
+        );
+        Ok(Self {
+            aggregation,
+            levels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_pyramid() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(10, 300, 250, 2);
+        for aggregation in [Aggregation::Max, Aggregation::Mean] {
+            let pyramid = Pyramid::from_rain_radar_values(
+                &test_values,
+                aggregation,
+                3,
+                &EncoderOptions::default(),
+            );
+            let pyramid = Pyramid::from_data(&pyramid.data())?;
+            assert_eq!(pyramid.aggregation(), aggregation);
+
+            let sizes: Vec<(usize, usize, usize)> = pyramid
+                .levels()
+                .iter()
+                .map(|level| {
+                    let grid_information = level.values().grid_information();
+                    (
+                        level.factor(),
+                        grid_information.width,
+                        grid_information.height,
+                    )
+                })
+                .collect();
+            assert_eq!(sizes, [(2, 150, 125), (4, 75, 63), (8, 38, 32)]);
+
+            // compare against aggregating the source values directly
+            let level = &pyramid.levels()[2];
+            for time in test_values.available_times() {
+                let mut actual = level.values().for_area(time, 0..38, 0..32);
+                for (x, y) in (0..38).cross_product(0..32) {
+                    let source_values: Vec<u16> = test_values
+                        .for_area(
+                            time,
+                            (x * 8)..usize::min(x * 8 + 8, 300),
+                            (y * 8)..usize::min(y * 8 + 8, 250),
+                        )
+                        .flatten()
+                        .collect();
+                    let expected = if source_values.is_empty() {
+                        None
+                    } else {
+                        match aggregation {
+                            Aggregation::Max => source_values.iter().copied().max(),
+                            Aggregation::Mean => Some(
+                                (source_values.iter().map(|value| *value as f64).sum::<f64>()
+                                    / source_values.len() as f64)
+                                    .round() as u16,
+                            ),
+                        }
+                    };
+                    assert_eq!(actual.next(), Some(expected), "x {x}, y {y}, time {time}");
+                }
+                assert!(actual.next().is_none());
+            }
+
+            assert!(pyramid.level_for_resolution(300, 250).is_none());
+            assert!(pyramid.level_for_resolution(151, 10).is_none());
+            assert_eq!(pyramid.level_for_resolution(150, 125).unwrap().factor(), 2);
+            assert_eq!(pyramid.level_for_resolution(70, 40).unwrap().factor(), 4);
+            assert_eq!(pyramid.level_for_resolution(16, 16).unwrap().factor(), 8);
+        }
+        assert!(Pyramid::from_data(b"DWDRRPYR").is_err());
+
+        // squares are aligned to the full grid, also for crops at any offset
+        let cropped = crate::Crop::new(&test_values, 13..300, 42..250);
+        let pyramid =
+            Pyramid::from_rain_radar_values(&cropped, Aggregation::Max, 3, &Default::default());
+        // stored levels can be geolocated on their own
+        let pyramid = Pyramid::from_data(&pyramid.data())?;
+        let locations: Vec<((usize, usize), usize)> = pyramid
+            .levels()
+            .iter()
+            .map(|level| (level.values().grid_offset(), level.values().cell_size()))
+            .collect();
+        assert_eq!(locations, [((12, 42), 2), ((12, 40), 4), ((8, 40), 8)]);
+        let full =
+            DownsampledRainRadarValues::from_rain_radar_values(&test_values, 8, Aggregation::Max);
+        let level = pyramid.levels()[2].values();
+        assert_eq!(level.grid_information().width, 37);
+        for time in test_values.available_times() {
+            // squares completely within the crop
+            assert!(level
+                .for_area(time, 1..37, 1..26)
+                .eq(full.for_area(time, 2..38, 6..31)));
+        }
+        assert_eq!(level.point_forecast(1, 1).x, 16);
+        assert_eq!(level.point_forecast(1, 1).y, 48);
+        Ok(())
+    }
+}
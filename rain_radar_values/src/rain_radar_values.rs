@@ -1,26 +1,187 @@
+/// Times are UTC, like everywhere else in this crate (see the `_utc` methods of
+/// [`RainRadarValues`] for explicitly UTC times)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TimeInformation {
     pub first_time: chrono::naive::NaiveDateTime,
     pub available_time_slots: u32,
+    /// Time between two consecutive time slots (5 minutes for the DWD products), serialised as
+    /// seconds
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "interval_seconds", with = "crate::serde_seconds")
+    )]
+    pub interval: chrono::Duration,
 }
 
-type TimeIterClousure =
-    impl FnMut((usize, chrono::naive::NaiveDateTime)) -> chrono::naive::NaiveDateTime;
+impl TimeInformation {
+    /// Interval of the DWD products
+    pub fn dwd_interval() -> chrono::Duration {
+        chrono::Duration::minutes(5)
+    }
+
+    pub fn first_time_utc(&self) -> chrono::DateTime<chrono::Utc> {
+        chrono::TimeZone::from_utc_datetime(&chrono::Utc, &self.first_time)
+    }
+
+    pub fn last_time(&self) -> chrono::naive::NaiveDateTime {
+        assert!(self.available_time_slots > 0);
+        self.time(self.available_time_slots as usize - 1)
+    }
+
+    /// Time of the time slot with the given index
+    pub fn time(&self, time_slot: usize) -> chrono::naive::NaiveDateTime {
+        self.first_time + self.interval * time_slot as i32
+    }
+
+    /// Index of the time slot starting exactly at `time`, if there is one
+    pub fn time_slot(&self, time: chrono::naive::NaiveDateTime) -> Option<usize> {
+        let offset = (time - self.first_time).num_milliseconds();
+        let interval = self.interval.num_milliseconds();
+        assert!(interval > 0, "Interval {} is not positive", self.interval);
+        if offset < 0 || offset % interval != 0 {
+            return None;
+        }
+        let time_slot = (offset / interval) as usize;
+        (time_slot < self.available_time_slots as usize).then_some(time_slot)
+    }
+}
+
+/// Size of the grid, x counting from 0 to width - 1 (west to east) and y from 0 to height - 1
+/// (north to south)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GridInformation {
+    pub width: usize,
+    pub height: usize,
+}
 
+impl GridInformation {
+    /// Grid of the DWD products
+    pub fn dwd() -> Self {
+        Self {
+            width: 1100,
+            height: 1200,
+        }
+    }
+}
+
+/// Values of a single point at all available times, see [`RainRadarValues::point_forecast`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointForecast {
+    /// Coordinates in the full DWD grid (including [`RainRadarValues::grid_offset`] and
+    /// [`RainRadarValues::cell_size`])
+    pub x: usize,
+    pub y: usize,
+    pub values: Vec<PointValue>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PointValue {
+    pub time: chrono::naive::NaiveDateTime,
+    pub value: Option<u16>,
+}
+
+/// The available times of [`RainRadarValues`], ordered from the first to the last time slot
+#[derive(Clone)]
 pub struct TimeIter {
-    inner: std::iter::Map<
-        std::iter::Enumerate<std::iter::Take<std::iter::Repeat<chrono::naive::NaiveDateTime>>>,
-        TimeIterClousure,
-    >,
+    first_time: chrono::naive::NaiveDateTime,
+    interval: chrono::Duration,
+    /// Next time slot returned from the front
+    front: usize,
+    /// Time slot after the next one returned from the back
+    back: usize,
+}
+
+impl TimeIter {
+    pub fn new(time_information: &TimeInformation) -> Self {
+        Self {
+            first_time: time_information.first_time,
+            interval: time_information.interval,
+            front: 0,
+            back: time_information.available_time_slots as usize,
+        }
+    }
+
+    /// Only the remaining times within `range`
+    pub fn within<R: std::ops::RangeBounds<chrono::naive::NaiveDateTime>>(
+        mut self,
+        range: R,
+    ) -> Self {
+        while self.front < self.back && !range.contains(&self.time(self.front)) {
+            self.front += 1;
+        }
+        while self.front < self.back && !range.contains(&self.time(self.back - 1)) {
+            self.back -= 1;
+        }
+        self
+    }
+
+    fn time(&self, time_slot: usize) -> chrono::naive::NaiveDateTime {
+        self.first_time + self.interval * time_slot as i32
+    }
 }
 
 impl Iterator for TimeIter {
     type Item = chrono::naive::NaiveDateTime;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next()
+        if self.front == self.back {
+            return None;
+        }
+        self.front += 1;
+        Some(self.time(self.front - 1))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.back - self.front;
+        (len, Some(len))
+    }
+}
+
+impl DoubleEndedIterator for TimeIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        if self.front == self.back {
+            return None;
+        }
+        self.back -= 1;
+        Some(self.time(self.back))
+    }
+}
+
+impl ExactSizeIterator for TimeIter {}
+
+/// Same as [`TimeIter`], but with explicitly UTC times
+pub struct UtcTimeIter {
+    inner: TimeIter,
+}
+
+impl Iterator for UtcTimeIter {
+    type Item = chrono::DateTime<chrono::Utc>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next()
+            .map(|time| chrono::TimeZone::from_utc_datetime(&chrono::Utc, &time))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl DoubleEndedIterator for UtcTimeIter {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.inner
+            .next_back()
+            .map(|time| chrono::TimeZone::from_utc_datetime(&chrono::Utc, &time))
     }
 }
 
+impl ExactSizeIterator for UtcTimeIter {}
+
 pub trait Range: std::iter::Iterator<Item = usize> + Clone {}
 impl<T: std::iter::Iterator<Item = usize> + Clone> Range for T {}
 
@@ -38,19 +199,58 @@ pub trait RainRadarValues {
 
     fn time_information(&self) -> TimeInformation;
 
+    fn grid_information(&self) -> GridInformation;
+
+    /// Position of the value at (0, 0) in the full DWD grid, so that coordinates can still be
+    /// geolocated if only a part of the grid is available (see [`crate::Crop`])
+    fn grid_offset(&self) -> (usize, usize) {
+        (0, 0)
+    }
+
+    /// Edge length of a value in cells of the full DWD grid (1 km), more than 1 for downsampled
+    /// values (see [`crate::DownsampledRainRadarValues`])
+    fn cell_size(&self) -> usize {
+        1
+    }
+
     fn available_times(&self) -> TimeIter {
-        let time_information = self.time_information();
-        fn map_index_and_first_time(
-            index_and_first_time: (usize, chrono::NaiveDateTime),
-        ) -> chrono::NaiveDateTime {
-            let (index, first_time) = index_and_first_time;
-            first_time + chrono::Duration::minutes(index as i64 * 5)
+        TimeIter::new(&self.time_information())
+    }
+
+    /// Same as [`Self::for_area`], but with an explicitly UTC time
+    fn for_area_utc<X: Range, Y: Range>(
+        &self,
+        time: chrono::DateTime<chrono::Utc>,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        self.for_area(time.naive_utc(), x, y)
+    }
+
+    /// Same as [`Self::available_times`], but with explicitly UTC times
+    fn available_times_utc(&self) -> UtcTimeIter {
+        UtcTimeIter {
+            inner: self.available_times(),
         }
-        TimeIter {
-            inner: std::iter::repeat(time_information.first_time)
-                .take(time_information.available_time_slots as usize)
-                .enumerate()
-                .map(map_index_and_first_time),
+    }
+
+    /// Values at (x, y) at all available times
+    fn point_forecast(&self, x: usize, y: usize) -> PointForecast {
+        let (x_offset, y_offset) = self.grid_offset();
+        let cell_size = self.cell_size();
+        PointForecast {
+            x: x * cell_size + x_offset,
+            y: y * cell_size + y_offset,
+            values: self
+                .available_times()
+                .map(|time| PointValue {
+                    time,
+                    value: self
+                        .for_area(time, x..=x, y..=y)
+                        .next()
+                        .expect("Couldn't get pixel (this shouldn't happen)"),
+                })
+                .collect(),
         }
     }
 
@@ -62,15 +262,20 @@ pub trait RainRadarValues {
             std::fs::create_dir_all(&path).expect("Failed creating directory");
         }
 
+        let grid_information = self.grid_information();
+
         for time in self.available_times() {
             let mut path = path.clone();
             let file_name = time.format("%Y%m%d%H%M%S.bmp");
             path.push(file_name.to_string());
 
-            let mut image = bmp::Image::new(1100, 1200);
+            let mut image = bmp::Image::new(
+                grid_information.width as u32,
+                grid_information.height as u32,
+            );
 
-            for x in 0..1100 {
-                for y in 0..1200 {
+            for x in 0..grid_information.width {
+                for y in 0..grid_information.height {
                     let pixel_value = self
                         .for_area(time, x..=x, y..=y)
                         .next()
@@ -92,4 +297,106 @@ pub trait RainRadarValues {
             image.save(path).expect("Failed sabing bmp");
         }
     }
+
+    /// Renders every time slot to `<directory>/<time>.png`, see [`crate::render`]
+    #[cfg(feature = "render")]
+    fn to_png<P: AsRef<std::path::Path>>(
+        &self,
+        directory: P,
+        options: &crate::render::RenderOptions,
+    ) -> anyhow::Result<()> {
+        use anyhow::Context;
+        let directory = directory.as_ref();
+        std::fs::create_dir_all(directory)
+            .with_context(|| format!("Failed creating {}", directory.display()))?;
+        for time in self.available_times() {
+            crate::render::Image::from_rain_radar_values(self, time, options)
+                .save_png(directory.join(time.format("%Y%m%d%H%M%S.png").to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: RainRadarValues> RainRadarValues for &T {
+    type Iter<'a, X: Range, Y: Range>
+        = T::Iter<'a, X, Y>
+    where
+        Self: 'a;
+
+    fn for_area<X: Range, Y: Range>(
+        &self,
+        time: chrono::naive::NaiveDateTime,
+        x: X,
+        y: Y,
+    ) -> Self::Iter<'_, X, Y> {
+        (**self).for_area(time, x, y)
+    }
+
+    fn time_information(&self) -> TimeInformation {
+        (**self).time_information()
+    }
+
+    fn grid_information(&self) -> GridInformation {
+        (**self).grid_information()
+    }
+
+    fn grid_offset(&self) -> (usize, usize) {
+        (**self).grid_offset()
+    }
+
+    fn cell_size(&self) -> usize {
+        (**self).cell_size()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_time_iter() {
+        let first_time = chrono::NaiveDate::from_ymd_opt(2022, 5, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let time_information = TimeInformation {
+            first_time,
+            available_time_slots: 24,
+            interval: chrono::Duration::hours(1),
+        };
+        let hour = |hours: i64| first_time + chrono::Duration::hours(hours);
+
+        let times = TimeIter::new(&time_information);
+        assert_eq!(times.len(), 24);
+        assert_eq!(times.clone().next(), Some(first_time));
+        assert_eq!(times.clone().next_back(), Some(hour(23)));
+        assert_eq!(time_information.last_time(), hour(23));
+        assert!(times.clone().rev().eq((0..24).rev().map(hour)));
+
+        let mut within = times.clone().within(hour(3)..hour(7));
+        assert_eq!(within.len(), 4);
+        assert_eq!(within.next(), Some(hour(3)));
+        assert_eq!(within.next_back(), Some(hour(6)));
+        assert_eq!(within.len(), 2);
+        assert!(within.eq([hour(4), hour(5)]));
+
+        // range bounds between time slots
+        assert!(times
+            .clone()
+            .within((hour(20) + chrono::Duration::minutes(30))..)
+            .eq([hour(21), hour(22), hour(23)]));
+        assert!(times
+            .clone()
+            .within(..=(first_time - chrono::Duration::minutes(1)))
+            .next()
+            .is_none());
+
+        assert_eq!(time_information.time_slot(hour(5)), Some(5));
+        assert_eq!(
+            time_information.time_slot(hour(5) + chrono::Duration::minutes(5)),
+            None
+        );
+        assert_eq!(time_information.time_slot(hour(24)), None);
+        assert_eq!(time_information.time_slot(hour(-1)), None);
+    }
 }
@@ -0,0 +1,448 @@
+//! Rendering of a time slot as an RGBA image, e.g. saved as PNG
+
+use crate::bitmap_font;
+use crate::RainRadarValues;
+use anyhow::{ensure, Context, Result};
+
+/// Red, green, blue and alpha
+pub type Colour = [u8; 4];
+
+pub const TRANSPARENT: Colour = [0, 0, 0, 0];
+
+/// Values from `threshold` up to the threshold of the next step get `colour`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PaletteStep {
+    pub threshold: u16,
+    pub colour: Colour,
+    /// Shown next to the colour in the legend
+    pub label: String,
+}
+
+/// Maps values to colours
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    steps: Vec<PaletteStep>,
+    /// Colour of values below the threshold of the first step
+    below: Colour,
+    no_data: Colour,
+    /// Shown above the steps in the legend, e.g. the unit
+    title: Option<String>,
+}
+
+impl Palette {
+    /// `steps` have to be ordered by their thresholds. Values below the first threshold and missing
+    /// values are transparent.
+    pub fn new(steps: Vec<PaletteStep>) -> Result<Self> {
+        ensure!(!steps.is_empty(), "Palette has no steps");
+        ensure!(
+            steps
+                .windows(2)
+                .all(|steps| steps[0].threshold < steps[1].threshold),
+            "Thresholds of the palette steps are not ascending"
+        );
+        Ok(Self {
+            steps,
+            below: TRANSPARENT,
+            no_data: TRANSPARENT,
+            title: None,
+        })
+    }
+
+    /// Shades of blue, green, yellow, red and violet like the precipitation maps of DWD. Assumes
+    /// that values are 1/100 mm per 5 minutes (as in the RV product), the legend is in mm/h.
+    pub fn dwd() -> Self {
+        let steps = [
+            (0.1, [0xB4, 0xF0, 0xFA, 0xFF]),
+            (0.5, [0x78, 0xC8, 0xFF, 0xFF]),
+            (1., [0x32, 0x8C, 0xFF, 0xFF]),
+            (2., [0x00, 0xB4, 0x3C, 0xFF]),
+            (5., [0x8C, 0xDC, 0x00, 0xFF]),
+            (10., [0xFF, 0xF0, 0x00, 0xFF]),
+            (20., [0xFF, 0xA0, 0x00, 0xFF]),
+            (50., [0xF0, 0x00, 0x00, 0xFF]),
+            (100., [0xC8, 0x00, 0xC8, 0xFF]),
+        ]
+        .into_iter()
+        .map(
+            |(millimetres_per_hour, colour): (f64, Colour)| PaletteStep {
+                // 12 values per hour, in 1/100 mm
+                threshold: (millimetres_per_hour / 12. * 100.).ceil() as u16,
+                colour,
+                label: millimetres_per_hour.to_string(),
+            },
+        )
+        .collect();
+        Self::new(steps)
+            .expect("DWD palette is invalid (this should not happen)")
+            .with_title("mm/h")
+    }
+
+    /// Colour of values below the first threshold (e.g. to make dry areas visible)
+    pub fn with_below(self, below: Colour) -> Self {
+        Self { below, ..self }
+    }
+
+    pub fn with_no_data(self, no_data: Colour) -> Self {
+        Self { no_data, ..self }
+    }
+
+    pub fn with_title<S: Into<String>>(self, title: S) -> Self {
+        Self {
+            title: Some(title.into()),
+            ..self
+        }
+    }
+
+    pub fn steps(&self) -> &[PaletteStep] {
+        &self.steps
+    }
+
+    pub fn colour(&self, value: Option<u16>) -> Colour {
+        let value = match value {
+            Some(value) => value,
+            None => return self.no_data,
+        };
+        match self.steps.partition_point(|step| step.threshold <= value) {
+            0 => self.below,
+            index => self.steps[index - 1].colour,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub palette: Palette,
+    /// Part of the grid to render (x and y), the whole grid if None
+    pub area: Option<(std::ops::Range<usize>, std::ops::Range<usize>)>,
+    /// Edge length of a value in pixels (see [`crate::DownsampledRainRadarValues`] for less than
+    /// one pixel per value)
+    pub scale: usize,
+    /// Draw the colours of the palette below the values
+    pub legend: bool,
+    /// Draw the time (UTC) below the values
+    pub timestamp: bool,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            palette: Palette::dwd(),
+            area: None,
+            scale: 1,
+            legend: false,
+            timestamp: false,
+        }
+    }
+}
+
+const TEXT_BACKGROUND: Colour = [0xFF, 0xFF, 0xFF, 0xFF];
+const TEXT_COLOUR: Colour = [0x00, 0x00, 0x00, 0xFF];
+const PADDING: usize = 2;
+const LINE_HEIGHT: usize = bitmap_font::GLYPH_HEIGHT + PADDING;
+/// Distance between the colour box of a legend entry and its label and between two entries
+const LEGEND_SPACING: usize = 3;
+
+/// An RGBA image, row by row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Colour>,
+}
+
+impl Image {
+    fn new(width: usize, height: usize, background: Colour) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![background; width * height],
+        }
+    }
+
+    /// Renders the values at `time`. The legend and the timestamp are drawn on a white area below
+    /// the values.
+    pub fn from_rain_radar_values<T: RainRadarValues + ?Sized>(
+        values: &T,
+        time: chrono::NaiveDateTime,
+        options: &RenderOptions,
+    ) -> Self {
+        assert!(options.scale > 0, "Scale must not be 0");
+        let grid_information = values.grid_information();
+        let (x_range, y_range) = options
+            .area
+            .clone()
+            .unwrap_or((0..grid_information.width, 0..grid_information.height));
+        assert!(
+            !x_range.is_empty()
+                && !y_range.is_empty()
+                && x_range.end <= grid_information.width
+                && y_range.end <= grid_information.height,
+            "Area {x_range:?}x{y_range:?} is empty or exceeds the grid"
+        );
+        let scale = options.scale;
+        let width = x_range.len() * scale;
+        let map_height = y_range.len() * scale;
+
+        let mut lines = Vec::new();
+        if options.timestamp {
+            lines.push(Line::Text(time.format("%Y-%m-%d %H:%M UTC").to_string()));
+        }
+        if options.legend {
+            if let Some(title) = &options.palette.title {
+                lines.push(Line::Text(title.clone()));
+            }
+            lines.extend(legend_lines(&options.palette, width));
+        }
+        let text_height = match lines.len() {
+            0 => 0,
+            lines => PADDING + lines * LINE_HEIGHT,
+        };
+
+        let mut image = Self::new(width, map_height + text_height, TRANSPARENT);
+        for (index, value) in values.for_area(time, x_range, y_range).enumerate() {
+            let colour = options.palette.colour(value);
+            let x = index % (width / scale) * scale;
+            let y = index / (width / scale) * scale;
+            for y in y..(y + scale) {
+                image.pixels[y * width + x..y * width + x + scale].fill(colour);
+            }
+        }
+
+        if text_height > 0 {
+            image.pixels[map_height * width..].fill(TEXT_BACKGROUND);
+        }
+        for (index, line) in lines.iter().enumerate() {
+            let y = map_height + PADDING + index * LINE_HEIGHT;
+            match line {
+                Line::Text(text) => image.draw_text(text, PADDING, y),
+                Line::Legend(entries) => {
+                    for (x, step) in entries {
+                        let step = &options.palette.steps[*step];
+                        image.fill_rectangle(
+                            *x,
+                            y,
+                            bitmap_font::GLYPH_HEIGHT,
+                            bitmap_font::GLYPH_HEIGHT,
+                            TEXT_COLOUR,
+                        );
+                        image.fill_rectangle(
+                            x + 1,
+                            y + 1,
+                            bitmap_font::GLYPH_HEIGHT - 2,
+                            bitmap_font::GLYPH_HEIGHT - 2,
+                            step.colour,
+                        );
+                        image.draw_text(
+                            &step.label,
+                            x + bitmap_font::GLYPH_HEIGHT + LEGEND_SPACING,
+                            y,
+                        );
+                    }
+                }
+            }
+        }
+        image
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Colour {
+        assert!(x < self.width && y < self.height);
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn pixels(&self) -> &[Colour] {
+        &self.pixels
+    }
+
+    /// Pixels as consecutive RGBA bytes
+    pub fn bytes(&self) -> Vec<u8> {
+        self.pixels.iter().flatten().copied().collect()
+    }
+
+    pub fn write_png<W: std::io::Write>(&self, writer: W) -> Result<()> {
+        let mut encoder = png::Encoder::new(
+            writer,
+            self.width.try_into().context("Image is too wide")?,
+            self.height.try_into().context("Image is too high")?,
+        );
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder
+            .write_header()
+            .context("Failed writing PNG header")?;
+        writer
+            .write_image_data(&self.bytes())
+            .context("Failed writing PNG data")?;
+        writer.finish().context("Failed finishing PNG")
+    }
+
+    pub fn save_png<P: AsRef<std::path::Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = std::fs::File::create(path)
+            .with_context(|| format!("Failed creating {}", path.display()))?;
+        self.write_png(std::io::BufWriter::new(file))
+    }
+
+    /// Clipped at the edges of the image
+    fn fill_rectangle(&mut self, x: usize, y: usize, width: usize, height: usize, colour: Colour) {
+        for y in y..usize::min(y + height, self.height) {
+            for x in x..usize::min(x + width, self.width) {
+                self.pixels[y * self.width + x] = colour;
+            }
+        }
+    }
+
+    /// Clipped at the edges of the image
+    fn draw_text(&mut self, text: &str, x: usize, y: usize) {
+        bitmap_font::draw_text(text, |text_x, text_y| {
+            self.fill_rectangle(x + text_x, y + text_y, 1, 1, TEXT_COLOUR)
+        });
+    }
+}
+
+enum Line {
+    Text(String),
+    /// x position and index of the palette step of the entries
+    Legend(Vec<(usize, usize)>),
+}
+
+/// Distributes the legend entries to as many lines as needed for the width of the image
+fn legend_lines(palette: &Palette, width: usize) -> Vec<Line> {
+    let mut lines = Vec::new();
+    let mut entries = Vec::new();
+    let mut x = PADDING;
+    for (index, step) in palette.steps.iter().enumerate() {
+        let entry_width =
+            bitmap_font::GLYPH_HEIGHT + LEGEND_SPACING + bitmap_font::text_width(&step.label);
+        if !entries.is_empty() && x + entry_width > width {
+            lines.push(Line::Legend(std::mem::take(&mut entries)));
+            x = PADDING;
+        }
+        entries.push((x, index));
+        x += entry_width + 2 * LEGEND_SPACING;
+    }
+    lines.push(Line::Legend(entries));
+    lines
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_palette() -> Result<()> {
+        let palette = Palette::new(vec![
+            PaletteStep {
+                threshold: 1,
+                colour: [0, 0, 0xFF, 0xFF],
+                label: "1".to_string(),
+            },
+            PaletteStep {
+                threshold: 100,
+                colour: [0xFF, 0, 0, 0xFF],
+                label: "100".to_string(),
+            },
+        ])?
+        .with_no_data([0x80, 0x80, 0x80, 0xFF]);
+        assert_eq!(palette.colour(Some(0)), TRANSPARENT);
+        assert_eq!(palette.colour(Some(1)), [0, 0, 0xFF, 0xFF]);
+        assert_eq!(palette.colour(Some(99)), [0, 0, 0xFF, 0xFF]);
+        assert_eq!(palette.colour(Some(100)), [0xFF, 0, 0, 0xFF]);
+        assert_eq!(palette.colour(Some(u16::MAX)), [0xFF, 0, 0, 0xFF]);
+        assert_eq!(palette.colour(None), [0x80, 0x80, 0x80, 0xFF]);
+
+        assert!(Palette::new(Vec::new()).is_err());
+        assert!(
+            Palette::new(vec![palette.steps()[1].clone(), palette.steps()[0].clone()]).is_err()
+        );
+
+        let dwd = Palette::dwd();
+        // 0.1 mm/h are 0.83 1/100 mm per 5 minutes
+        assert_eq!(dwd.steps()[0].threshold, 1);
+        assert_eq!(dwd.steps()[2].threshold, 9);
+        assert_eq!(dwd.steps()[2].label, "1");
+        assert_eq!(dwd.colour(Some(0)), TRANSPARENT);
+        assert_eq!(dwd.colour(None), TRANSPARENT);
+        Ok(())
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(19, 420, 300, 2);
+        let time = test_values.time_information().first_time;
+        let palette = Palette::dwd().with_no_data([0x80, 0x80, 0x80, 0xFF]);
+
+        let image = Image::from_rain_radar_values(
+            &test_values,
+            time,
+            &RenderOptions {
+                palette: palette.clone(),
+                area: Some((100..420, 50..300)),
+                scale: 2,
+                ..Default::default()
+            },
+        );
+        assert_eq!((image.width(), image.height()), (640, 500));
+        for ((x, y), value) in crate::CrossIteratorExt::cross_product(100..420, 50..300)
+            .zip(test_values.for_area(time, 100..420, 50..300))
+        {
+            for (pixel_x, pixel_y) in [(0, 0), (1, 1)] {
+                assert_eq!(
+                    image.pixel((x - 100) * 2 + pixel_x, (y - 50) * 2 + pixel_y),
+                    palette.colour(value)
+                );
+            }
+        }
+
+        let with_text = Image::from_rain_radar_values(
+            &test_values,
+            time,
+            &RenderOptions {
+                palette: palette.clone(),
+                area: Some((100..420, 50..300)),
+                scale: 2,
+                legend: true,
+                timestamp: true,
+            },
+        );
+        // timestamp, title and all steps in one line
+        assert_eq!(with_text.height(), 500 + PADDING + 3 * LINE_HEIGHT);
+        assert_eq!(with_text.pixels()[..640 * 500], image.pixels()[..]);
+        let text_area = &with_text.pixels()[640 * 500..];
+        assert!(text_area.contains(&TEXT_COLOUR));
+        for step in palette.steps() {
+            assert!(text_area.contains(&step.colour));
+        }
+
+        // a narrow image needs more lines for the legend
+        let narrow = Image::from_rain_radar_values(
+            &test_values,
+            time,
+            &RenderOptions {
+                area: Some((400..420, 0..10)),
+                legend: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(narrow.height(), 10 + PADDING + 10 * LINE_HEIGHT);
+
+        let mut png = Vec::new();
+        with_text.write_png(&mut png)?;
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(png)).read_info()?;
+        let mut decoded = vec![0; decoder.output_buffer_size()];
+        let info = decoder.next_frame(&mut decoded)?;
+        assert_eq!(
+            (info.width, info.height, info.color_type),
+            (640, with_text.height() as u32, png::ColorType::Rgba)
+        );
+        assert_eq!(decoded, with_text.bytes());
+        Ok(())
+    }
+}
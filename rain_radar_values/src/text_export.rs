@@ -0,0 +1,309 @@
+//! Export as ESRI ASCII grid (`.asc`) and CSV for tools that can't read binary formats. Values
+//! are in mm per time slot (or accumulated over several time slots), the cells are as large as
+//! [`RainRadarValues::cell_size`].
+
+use crate::RainRadarValues;
+use anyhow::{ensure, Context, Result};
+use coordinates_mapper::{GeographicCoordinates, StereographicCoordinates, OFFSET_X, OFFSET_Y};
+use std::io::Write;
+
+pub const NO_DATA: i32 = -1;
+
+/// Where to export values for [`write_csv`], in coordinates of the values (without
+/// [`RainRadarValues::grid_offset`])
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Locations {
+    Area(std::ops::Range<usize>, std::ops::Range<usize>),
+    Points(Vec<(usize, usize)>),
+}
+
+/// Writes the values at `time` as ESRI ASCII grid
+pub fn write_asc<T: RainRadarValues + ?Sized, W: Write>(
+    values: &T,
+    time: chrono::NaiveDateTime,
+    writer: W,
+) -> Result<()> {
+    let grid_information = values.grid_information();
+    let millimetres = values
+        .for_area(time, 0..grid_information.width, 0..grid_information.height)
+        .map(|value| value.map(|value| value as u32));
+    write_asc_values(values, millimetres, writer)
+}
+
+/// Writes the sum of the values at the times within `times` as ESRI ASCII grid. A sum is missing
+/// if the value is missing at any of the times.
+pub fn write_accumulation_asc<
+    T: RainRadarValues + ?Sized,
+    R: std::ops::RangeBounds<chrono::NaiveDateTime>,
+    W: Write,
+>(
+    values: &T,
+    times: R,
+    writer: W,
+) -> Result<()> {
+    let grid_information = values.grid_information();
+    let mut sums = vec![Some(0u32); grid_information.width * grid_information.height];
+    let mut accumulated_times = 0;
+    for time in values.available_times().within(times) {
+        accumulated_times += 1;
+        for (sum, value) in sums.iter_mut().zip(values.for_area(
+            time,
+            0..grid_information.width,
+            0..grid_information.height,
+        )) {
+            *sum = sum.zip(value).map(|(sum, value)| sum + value as u32);
+        }
+    }
+    ensure!(accumulated_times > 0, "No time slots to accumulate");
+    write_asc_values(values, sums.into_iter(), writer)
+}
+
+/// `values` in 1/100 mm, row by row
+fn write_asc_values<T: RainRadarValues + ?Sized, W: Write>(
+    grid: &T,
+    values: impl std::iter::Iterator<Item = Option<u32>>,
+    mut writer: W,
+) -> Result<()> {
+    let grid_information = grid.grid_information();
+    let (offset_x, offset_y) = grid.grid_offset();
+    let cell_size = grid.cell_size();
+    // lower left corner in km, relative to the north pole
+    let x_corner = offset_x as f64 - OFFSET_X;
+    let y_corner = OFFSET_Y - (offset_y + grid_information.height * cell_size) as f64;
+    let mut asc = format!(
+        "ncols {}\nnrows {}\nxllcorner {x_corner:.6}\nyllcorner {y_corner:.6}\n\
+         cellsize {cell_size}\nNODATA_value {NO_DATA}\n",
+        grid_information.width, grid_information.height,
+    );
+    // rows from north to south, as in the grid
+    for (index, value) in values.enumerate() {
+        match value {
+            Some(value) => asc += &format!("{}.{:02}", value / 100, value % 100),
+            None => asc += &NO_DATA.to_string(),
+        }
+        asc.push(if (index + 1) % grid_information.width == 0 {
+            '\n'
+        } else {
+            ' '
+        });
+    }
+    writer
+        .write_all(asc.as_bytes())
+        .context("Failed writing ASCII grid")
+}
+
+/// Writes a CSV table with a row for every location at every available time: time (UTC), x and y
+/// (of the top left corner of the cell in the full DWD grid, see [`RainRadarValues::grid_offset`]
+/// and [`RainRadarValues::cell_size`]), latitude and longitude of the center of the cell and the
+/// value in mm (empty if missing)
+pub fn write_csv<T: RainRadarValues + ?Sized, W: Write>(
+    values: &T,
+    locations: &Locations,
+    mut writer: W,
+) -> Result<()> {
+    let grid_information = values.grid_information();
+    let (offset_x, offset_y) = values.grid_offset();
+    let cell_size = values.cell_size();
+    let points: Vec<(usize, usize)> = match locations {
+        Locations::Area(x, y) => {
+            crate::CrossIteratorExt::cross_product(x.clone(), y.clone()).collect()
+        }
+        Locations::Points(points) => points.clone(),
+    };
+    ensure!(
+        points
+            .iter()
+            .all(|&(x, y)| x < grid_information.width && y < grid_information.height),
+        "Locations exceed the grid"
+    );
+    let coordinates: Vec<(usize, usize, GeographicCoordinates)> = points
+        .iter()
+        .map(|&(x, y)| {
+            let (x, y) = (offset_x + x * cell_size, offset_y + y * cell_size);
+            let geographic = StereographicCoordinates {
+                x: x as f64 + cell_size as f64 / 2.,
+                y: y as f64 + cell_size as f64 / 2.,
+            }
+            .into();
+            (x, y, geographic)
+        })
+        .collect();
+
+    let mut csv = String::from("time,x,y,lat,lon,value\n");
+    for time in values.available_times() {
+        let time_label = time.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+        let point_values: Vec<Option<u16>> = match locations {
+            Locations::Area(x, y) => values.for_area(time, x.clone(), y.clone()).collect(),
+            Locations::Points(points) => points
+                .iter()
+                .map(|&(x, y)| {
+                    values
+                        .for_area(time, x..=x, y..=y)
+                        .next()
+                        .expect("Couldn't get value (this shouldn't happen)")
+                })
+                .collect(),
+        };
+        for ((x, y, geographic), value) in coordinates.iter().zip(point_values) {
+            csv += &format!(
+                "{time_label},{x},{y},{:.5},{:.5},",
+                geographic.latitude, geographic.longitude
+            );
+            if let Some(value) = value {
+                csv += &format!("{}.{:02}", value / 100, value % 100);
+            }
+            csv.push('\n');
+        }
+        writer
+            .write_all(csv.as_bytes())
+            .context("Failed writing CSV")?;
+        csv.clear();
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_asc() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(26, 420, 300, 3);
+        let cropped = crate::Crop::new(&test_values, 300..420, 10..300);
+        let times: Vec<_> = cropped.available_times().collect();
+
+        let mut asc = Vec::new();
+        write_asc(&cropped, times[1], &mut asc)?;
+        let asc = String::from_utf8(asc)?;
+        let lines: Vec<&str> = asc.lines().collect();
+        assert_eq!(lines[0], "ncols 120");
+        assert_eq!(lines[1], "nrows 290");
+        assert_eq!(lines[2], format!("xllcorner {:.6}", 300. - OFFSET_X));
+        assert_eq!(lines[3], format!("yllcorner {:.6}", OFFSET_Y - 300.));
+        assert_eq!(lines[4], "cellsize 1");
+        assert_eq!(lines[5], "NODATA_value -1");
+        assert_eq!(lines.len(), 6 + 290);
+        let values: Vec<f64> = lines[6..]
+            .iter()
+            .flat_map(|line| line.split(' '))
+            .map(|value| value.parse())
+            .collect::<Result<_, _>>()?;
+        assert_eq!(values.len(), 120 * 290);
+        for (value, expected) in values
+            .iter()
+            .zip(cropped.for_area(times[1], 0..120, 0..290))
+        {
+            match expected {
+                Some(expected) => assert!((value - expected as f64 / 100.).abs() < 1e-9),
+                None => assert_eq!(*value, -1.),
+            }
+        }
+
+        let mut accumulation = Vec::new();
+        write_accumulation_asc(&cropped, times[1]..=times[2], &mut accumulation)?;
+        let accumulation = String::from_utf8(accumulation)?;
+        assert!(accumulation.contains("cellsize 1\n"));
+        let sums: Vec<&str> = accumulation
+            .lines()
+            .skip(6)
+            .flat_map(|line| line.split(' '))
+            .collect();
+        for ((sum, first), second) in sums
+            .iter()
+            .zip(cropped.for_area(times[1], 0..120, 0..290))
+            .zip(cropped.for_area(times[2], 0..120, 0..290))
+        {
+            match first.zip(second) {
+                Some((first, second)) => {
+                    let sum: f64 = sum.parse()?;
+                    assert!((sum - (first + second) as f64 / 100.).abs() < 1e-9);
+                }
+                None => assert_eq!(*sum, "-1"),
+            }
+        }
+        let after_last = times[2] + chrono::Duration::minutes(5);
+        assert!(write_accumulation_asc(&cropped, after_last.., Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_downsampled_crop() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(28, 420, 300, 1);
+        let cropped = crate::Crop::new(&test_values, 300..420, 12..300);
+        let downsampled = crate::DownsampledRainRadarValues::from_rain_radar_values(
+            &cropped,
+            4,
+            crate::Aggregation::Max,
+        );
+        let time = downsampled.available_times().next().unwrap();
+
+        let mut asc = Vec::new();
+        write_asc(&downsampled, time, &mut asc)?;
+        let asc = String::from_utf8(asc)?;
+        let lines: Vec<&str> = asc.lines().collect();
+        assert_eq!(lines[0], "ncols 30");
+        assert_eq!(lines[1], "nrows 72");
+        // same corners as the crop in full resolution
+        assert_eq!(lines[2], format!("xllcorner {:.6}", 300. - OFFSET_X));
+        assert_eq!(lines[3], format!("yllcorner {:.6}", OFFSET_Y - 300.));
+        assert_eq!(lines[4], "cellsize 4");
+
+        let mut csv = Vec::new();
+        write_csv(&downsampled, &Locations::Points(vec![(1, 2)]), &mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        let geographic: GeographicCoordinates = StereographicCoordinates { x: 306., y: 22. }.into();
+        assert!(csv.lines().nth(1).unwrap().starts_with(&format!(
+            "2022-05-01T12:05:00Z,304,20,{:.5},{:.5},",
+            geographic.latitude, geographic.longitude
+        )));
+        Ok(())
+    }
+
+    #[test]
+    fn test_csv() -> Result<()> {
+        let test_values = crate::test_values::TestRainRadarValues::with_size(27, 420, 300, 2);
+        let cropped = crate::Crop::new(&test_values, 100..420, 0..300);
+        let times: Vec<_> = cropped.available_times().collect();
+
+        let mut csv = Vec::new();
+        write_csv(&cropped, &Locations::Area(10..13, 5..7), &mut csv)?;
+        let csv = String::from_utf8(csv)?;
+        let rows: Vec<Vec<&str>> = csv.lines().map(|row| row.split(',').collect()).collect();
+        assert_eq!(rows[0], ["time", "x", "y", "lat", "lon", "value"]);
+        assert_eq!(rows.len(), 1 + 2 * 3 * 2);
+        assert_eq!(rows[1][..3], ["2022-05-01T12:05:00Z", "110", "5"]);
+        assert_eq!(rows[2][1..3], ["111", "5"]);
+        assert_eq!(rows[7][..3], ["2022-05-01T12:10:00Z", "110", "5"]);
+        let geographic: GeographicCoordinates =
+            StereographicCoordinates { x: 110.5, y: 5.5 }.into();
+        assert_eq!(rows[1][3], format!("{:.5}", geographic.latitude));
+        assert_eq!(rows[1][4], format!("{:.5}", geographic.longitude));
+        for (row, value) in rows[1..].iter().zip(
+            times
+                .iter()
+                .flat_map(|&time| cropped.for_area(time, 10..13, 5..7)),
+        ) {
+            match value {
+                Some(value) => assert_eq!(row[5].parse::<f64>()?, value as f64 / 100.),
+                None => assert_eq!(row[5], ""),
+            }
+        }
+
+        let mut points = Vec::new();
+        // the second point is in the missing triangle of the test values
+        write_csv(
+            &cropped,
+            &Locations::Points(vec![(300, 290), (0, 0)]),
+            &mut points,
+        )?;
+        let points = String::from_utf8(points)?;
+        let rows: Vec<&str> = points.lines().collect();
+        assert_eq!(rows.len(), 1 + 2 * 2);
+        assert!(rows[1].starts_with("2022-05-01T12:05:00Z,400,290,"));
+        assert!(rows[2].starts_with("2022-05-01T12:05:00Z,100,0,"));
+        assert!(rows[2].ends_with(','));
+
+        assert!(write_csv(&cropped, &Locations::Points(vec![(320, 0)]), Vec::new()).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,1057 @@
+use crate::{
+    Archive, CompressedRainRadarValues, Compression, DWDRainRadarValues, EncoderOptions,
+    RainRadarValues,
+};
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::BTreeMap;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// Format of a time series file:
+// bytes 0 - 7: magic bytes "DWDRRTSR"
+// bytes 8 - 11: u32 – format version, always 2
+// bytes 12 - 15: reserved, always 0
+// bytes 16 - 23: UNIX timestamp of the first frame
+// bytes 24 - 27: u32 – seconds between two frames
+// bytes 28 - 31: u32 – width of the grid
+// bytes 32 - 35: u32 – height of the grid
+// bytes 36 - 37: u16 – edge length of the square tiles the grid is divided into
+// bytes 38 - 39: reserved, always 0
+// bytes 40 - 43: u32 – number of frames per segment
+// bytes 44 - 47: reserved, always 0
+// byte 48 and onwards: the segments one after another, every one holding the frames
+//   `segment * frames per segment..` (all but the last one are complete). Empty segments (without
+//   any frames) take no space. Every other segment consists of:
+//   [u32; number of tiles + 1] – start of every tile (and the end of the last one), relative to the
+//     end of this table, tiles are numbered row by row
+//   the lzma compressed tiles – [[u16; frames in the segment]; pixels in the tile] – for every
+//     pixel of the tile (row by row) its values as wrapping differences to the value in the
+//     previous frame (to 0 for the first one), u16::MAX if missing (also if there is no frame)
+// after the segments: the index – for every segment
+//   bytes 0 - 7: u64 – position of the segment in the file
+//   bytes 8 - 15: u64 – length of the segment
+//   bytes 16 - 19: u32 – number of frames in the segment
+//   bytes 20 - 23: reserved, always 0
+// last 16 bytes: u64 – position of the index, followed by the magic bytes "DWDRRTSI"
+const MAGIC: &[u8; 8] = b"DWDRRTSR";
+const VERSION: u32 = 2;
+const HEADER_SIZE: u64 = 48;
+const INDEX_MAGIC: &[u8; 8] = b"DWDRRTSI";
+const INDEX_ENTRY_SIZE: usize = 24;
+const INDEX_TRAILER_SIZE: u64 = 16;
+
+/// lzma preset for the tiles, higher presets are much slower without saving much
+const TILE_PRESET: u32 = 1;
+/// Rows of the grid that are transposed at once when writing a segment
+const BAND_ROWS: usize = 100;
+
+/// Layout of a time series, only used when it is created
+#[derive(Clone, Copy, Debug)]
+pub struct TimeSeriesOptions {
+    /// Edge length of the square tiles, reading the history of a pixel decompresses its whole tile
+    /// in every segment
+    pub tile_size: usize,
+    /// Frames that are compressed together. While building, the frames of one segment are kept in
+    /// memory (compressed) and transposed together.
+    pub frames_per_segment: usize,
+}
+
+impl Default for TimeSeriesOptions {
+    fn default() -> Self {
+        Self {
+            tile_size: 10,
+            // two days of 5 minute frames
+            frames_per_segment: 576,
+        }
+    }
+}
+
+/// A frame that was not added to a time series
+#[derive(Debug)]
+pub struct SkippedFrame {
+    /// Time of the archive entry or path of the downloaded file
+    pub source: String,
+    pub error: anyhow::Error,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Segment {
+    position: u64,
+    length: u64,
+    frames: usize,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    first_time: chrono::NaiveDateTime,
+    interval: chrono::Duration,
+    width: usize,
+    height: usize,
+    tile_size: usize,
+    frames_per_segment: usize,
+}
+
+impl Layout {
+    fn tiles_x(&self) -> usize {
+        self.width.div_ceil(self.tile_size)
+    }
+
+    fn tiles(&self) -> usize {
+        self.tiles_x() * self.height.div_ceil(self.tile_size)
+    }
+
+    /// Columns and rows of a tile
+    fn tile_area(&self, tile: usize) -> (std::ops::Range<usize>, std::ops::Range<usize>) {
+        let x = (tile % self.tiles_x()) * self.tile_size;
+        let y = (tile / self.tiles_x()) * self.tile_size;
+        (
+            x..usize::min(x + self.tile_size, self.width),
+            y..usize::min(y + self.tile_size, self.height),
+        )
+    }
+
+    fn header(&self) -> Result<Vec<u8>> {
+        let mut header = Vec::with_capacity(HEADER_SIZE as usize);
+        header.extend_from_slice(MAGIC);
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        header.extend_from_slice(&self.first_time.and_utc().timestamp().to_le_bytes());
+        for value in [
+            self.interval.num_seconds() as usize,
+            self.width,
+            self.height,
+        ] {
+            let value: u32 = value.try_into().context("Time series is too large")?;
+            header.extend_from_slice(&value.to_le_bytes());
+        }
+        header.extend_from_slice(&(self.tile_size as u16).to_le_bytes());
+        header.extend_from_slice(&[0; 2]);
+        header.extend_from_slice(&(self.frames_per_segment as u32).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+        Ok(header)
+    }
+
+    fn from_header(header: &[u8; HEADER_SIZE as usize]) -> Result<Self> {
+        ensure!(
+            header[0..8] == *MAGIC,
+            "Not a time series file (found magic bytes {:?})",
+            &header[0..8]
+        );
+        let read_u32 = |position: usize| {
+            u32::from_le_bytes(header[position..(position + 4)].try_into().unwrap())
+        };
+        let version = read_u32(8);
+        ensure!(
+            version == VERSION,
+            "Time series format version {version} not supported (expected version {VERSION})"
+        );
+        let result = Self {
+            first_time: chrono::DateTime::from_timestamp(
+                i64::from_le_bytes(header[16..24].try_into().unwrap()),
+                0,
+            )
+            .context("Invalid first time in time series header")?
+            .naive_utc(),
+            interval: chrono::Duration::seconds(read_u32(24) as i64),
+            width: read_u32(28) as usize,
+            height: read_u32(32) as usize,
+            tile_size: u16::from_le_bytes([header[36], header[37]]) as usize,
+            frames_per_segment: read_u32(40) as usize,
+        };
+        ensure!(
+            result.interval > chrono::Duration::zero(),
+            "Interval between frames is 0"
+        );
+        ensure!(
+            result.tile_size > 0 && result.frames_per_segment > 0,
+            "Tile size and frames per segment must not be 0"
+        );
+        Ok(result)
+    }
+}
+
+/// Index of the frame at `time`, if it fits the first time and the interval
+fn frame_at(
+    first_time: chrono::NaiveDateTime,
+    interval: chrono::Duration,
+    time: chrono::NaiveDateTime,
+) -> Result<usize> {
+    let offset = time - first_time;
+    ensure!(
+        offset >= chrono::Duration::zero()
+            && offset.num_seconds() % interval.num_seconds() == 0
+            && offset.num_milliseconds() % 1000 == 0,
+        "Frame at {time} does not fit the first time {first_time} and interval {interval}"
+    );
+    Ok((offset.num_seconds() / interval.num_seconds()) as usize)
+}
+
+/// Decompresses a tile and returns the values of its pixels in all `frames` (pixel by pixel)
+fn decode_tile(compressed: &[u8], pixels: usize, frames: usize) -> Result<Vec<u16>> {
+    let bytes = lzma::decompress(compressed).context("Could not decompress tile")?;
+    ensure!(
+        bytes.len() == pixels * frames * 2,
+        "Tile has {} bytes instead of {}",
+        bytes.len(),
+        pixels * frames * 2
+    );
+    let mut values = Vec::with_capacity(pixels * frames);
+    for series in bytes.chunks_exact(frames * 2) {
+        let mut value = 0u16;
+        for delta in series.chunks_exact(2) {
+            value = value.wrapping_add(u16::from_le_bytes([delta[0], delta[1]]));
+            values.push(value);
+        }
+    }
+    Ok(values)
+}
+
+/// Compresses the values of the pixels of a tile (pixel by pixel)
+fn encode_tile(values: impl Iterator<Item = u16>, frames: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    let mut previous = 0u16;
+    for (index, value) in values.enumerate() {
+        if index % frames == 0 {
+            previous = 0;
+        }
+        bytes.extend_from_slice(&value.wrapping_sub(previous).to_le_bytes());
+        previous = value;
+    }
+    lzma::compress(&bytes, TILE_PRESET).expect("lzma compression failed")
+}
+
+/// Reads the index at the end of a time series file and checks that its segments are complete
+fn read_index(file: &mut std::fs::File, layout: &Layout) -> Result<Vec<Segment>> {
+    let length = file
+        .metadata()
+        .context("Could not read time series metadata")?
+        .len();
+    ensure!(
+        length >= HEADER_SIZE + INDEX_TRAILER_SIZE,
+        "Time series is too short for its index"
+    );
+    let mut trailer = [0u8; INDEX_TRAILER_SIZE as usize];
+    file.seek(SeekFrom::Start(length - INDEX_TRAILER_SIZE))
+        .and_then(|_| file.read_exact(&mut trailer))
+        .context("Failed reading time series index trailer")?;
+    ensure!(
+        trailer[8..16] == *INDEX_MAGIC,
+        "Time series index is missing (the file was not finished)"
+    );
+    let index_position = u64::from_le_bytes(trailer[0..8].try_into().unwrap());
+    let index_length = (length - INDEX_TRAILER_SIZE)
+        .checked_sub(index_position)
+        .filter(|length| index_position >= HEADER_SIZE && length % INDEX_ENTRY_SIZE as u64 == 0)
+        .context("Invalid time series index position")?;
+    let mut index = vec![0u8; index_length as usize];
+    file.seek(SeekFrom::Start(index_position))
+        .and_then(|_| file.read_exact(&mut index))
+        .context("Failed reading time series index")?;
+
+    let segments: Vec<Segment> = index
+        .chunks_exact(INDEX_ENTRY_SIZE)
+        .map(|entry| Segment {
+            position: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+            length: u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+            frames: u32::from_le_bytes(entry[16..20].try_into().unwrap()) as usize,
+        })
+        .collect();
+    let mut end = HEADER_SIZE;
+    for (number, segment) in segments.iter().enumerate() {
+        ensure!(
+            segment.position == end,
+            "Segment {number} starts at {} instead of {end}",
+            segment.position
+        );
+        ensure!(
+            segment.length == 0 || segment.length >= (layout.tiles() as u64 + 1) * 4,
+            "Segment {number} is too short for its tile table"
+        );
+        let expected_frames = if number + 1 == segments.len() {
+            1..=layout.frames_per_segment
+        } else {
+            layout.frames_per_segment..=layout.frames_per_segment
+        };
+        ensure!(
+            expected_frames.contains(&segment.frames),
+            "Segment {number} has {} frames",
+            segment.frames
+        );
+        end += segment.length;
+    }
+    ensure!(
+        end == index_position,
+        "Segments end at {end}, but the index starts at {index_position}"
+    );
+    Ok(segments)
+}
+
+/// Values of every pixel over a long time. The grid is divided into tiles and the time into
+/// segments, every tile of a segment is compressed on its own, so the history of a location only
+/// needs one tile per segment. Only the first time slot (lead time 0) of every forecast is used.
+pub struct TimeSeries {
+    file: std::fs::File,
+    layout: Layout,
+    segments: Vec<Segment>,
+}
+
+impl TimeSeries {
+    pub fn open<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let mut file = std::fs::File::open(path).context("Could not open time series file")?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)
+            .context("Failed reading time series header")?;
+        let layout = Layout::from_header(&header)?;
+        let segments = read_index(&mut file, &layout)?;
+        Ok(Self {
+            file,
+            layout,
+            segments,
+        })
+    }
+
+    /// Builds a time series from the forecasts of an archive (including gaps between them),
+    /// forecasts that can't be loaded or don't fit the interval are skipped
+    pub fn from_archive<P: AsRef<std::path::Path>>(
+        archive: &mut Archive,
+        path: P,
+        interval: chrono::Duration,
+    ) -> Result<(Self, Vec<SkippedFrame>)> {
+        let first_time = match archive.entries().next() {
+            Some(entry) => entry.base_time,
+            None => bail!("Archive does not contain any forecasts"),
+        };
+        let mut builder = TimeSeriesBuilder::create(path, first_time, interval)?;
+        let skipped = builder.add_archive(archive)?;
+        Ok((builder.finish()?, skipped))
+    }
+
+    /// Builds a time series from the files of the downloader (`<directory>/%Y%m%d/%H%M%S.tar.bz2`),
+    /// files that can't be read or don't fit the interval are skipped
+    pub fn from_downloads<P: AsRef<std::path::Path>, Q: AsRef<std::path::Path>>(
+        directory: P,
+        path: Q,
+        interval: chrono::Duration,
+    ) -> Result<(Self, Vec<SkippedFrame>)> {
+        let first_time = match downloads(directory.as_ref())?.first() {
+            Some((time, _)) => *time,
+            None => bail!("Directory does not contain any downloaded forecasts"),
+        };
+        let mut builder = TimeSeriesBuilder::create(path, first_time, interval)?;
+        let skipped = builder.add_downloads(directory)?;
+        Ok((builder.finish()?, skipped))
+    }
+
+    pub fn first_time(&self) -> chrono::NaiveDateTime {
+        self.layout.first_time
+    }
+
+    pub fn interval(&self) -> chrono::Duration {
+        self.layout.interval
+    }
+
+    pub fn number_of_frames(&self) -> usize {
+        self.segments.iter().map(|segment| segment.frames).sum()
+    }
+
+    /// Values of a pixel in all frames in `from..to`
+    pub fn history(
+        &mut self,
+        x: usize,
+        y: usize,
+        from: chrono::NaiveDateTime,
+        to: chrono::NaiveDateTime,
+    ) -> Result<Vec<(chrono::NaiveDateTime, Option<u16>)>> {
+        let layout = self.layout;
+        ensure!(
+            x < layout.width && y < layout.height,
+            "Pixel {x},{y} is outside of the {}x{} grid",
+            layout.width,
+            layout.height
+        );
+        // index of the first frame at or after `time`
+        let number_of_frames = self.number_of_frames();
+        let frame_after = |time: chrono::NaiveDateTime| -> usize {
+            let seconds = (time - layout.first_time).num_seconds().max(0);
+            let interval = layout.interval.num_seconds();
+            usize::min(
+                ((seconds + interval - 1) / interval) as usize,
+                number_of_frames,
+            )
+        };
+        let frames = frame_after(from)..frame_after(to);
+        if frames.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let tile = (y / layout.tile_size) * layout.tiles_x() + x / layout.tile_size;
+        let (columns, rows) = layout.tile_area(tile);
+        let pixel = (y - rows.start) * columns.len() + (x - columns.start);
+        let table_length = (layout.tiles() as u64 + 1) * 4;
+
+        let mut result = Vec::with_capacity(frames.len());
+        let segments = (frames.start / layout.frames_per_segment)
+            ..=((frames.end - 1) / layout.frames_per_segment);
+        for number in segments {
+            let segment = self.segments[number];
+            let segment_start = number * layout.frames_per_segment;
+            let wanted = usize::max(frames.start, segment_start)
+                ..usize::min(frames.end, segment_start + segment.frames);
+            let values = if segment.length == 0 {
+                vec![u16::MAX; segment.frames]
+            } else {
+                let mut table = [0u8; 8];
+                self.file
+                    .seek(SeekFrom::Start(segment.position + tile as u64 * 4))
+                    .and_then(|_| self.file.read_exact(&mut table))
+                    .context("Failed reading tile table")?;
+                let start = u32::from_le_bytes(table[0..4].try_into().unwrap()) as u64;
+                let end = u32::from_le_bytes(table[4..8].try_into().unwrap()) as u64;
+                ensure!(
+                    start <= end && table_length + end <= segment.length,
+                    "Invalid position of tile {tile} in segment {number}"
+                );
+                let mut compressed = vec![0u8; (end - start) as usize];
+                self.file
+                    .seek(SeekFrom::Start(segment.position + table_length + start))
+                    .and_then(|_| self.file.read_exact(&mut compressed))
+                    .context("Failed reading tile")?;
+                let values = decode_tile(&compressed, columns.len() * rows.len(), segment.frames)?;
+                values[(pixel * segment.frames)..((pixel + 1) * segment.frames)].to_vec()
+            };
+            result.extend(wanted.map(|frame| {
+                let value = match values[frame - segment_start] {
+                    u16::MAX => None,
+                    value => Some(value),
+                };
+                (layout.first_time + layout.interval * frame as i32, value)
+            }));
+        }
+        Ok(result)
+    }
+}
+
+/// Times and paths of the files of the downloader (`<directory>/%Y%m%d/%H%M%S.tar.bz2`), sorted
+/// by time. Other files and directories are ignored.
+fn downloads(
+    directory: &std::path::Path,
+) -> Result<Vec<(chrono::NaiveDateTime, std::path::PathBuf)>> {
+    let mut result = Vec::new();
+    for day in std::fs::read_dir(directory).context("Could not read download directory")? {
+        let day = day.context("Could not read download directory")?;
+        let Some(date) = day
+            .file_name()
+            .to_str()
+            .and_then(|name| chrono::NaiveDate::parse_from_str(name, "%Y%m%d").ok())
+        else {
+            continue;
+        };
+        if !day.path().is_dir() {
+            continue;
+        }
+        for file in std::fs::read_dir(day.path()).context("Could not read download directory")? {
+            let file = file.context("Could not read download directory")?;
+            let Some(time) = file
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_suffix(".tar.bz2"))
+                .and_then(|name| chrono::NaiveTime::parse_from_str(name, "%H%M%S").ok())
+            else {
+                continue;
+            };
+            result.push((date.and_time(time), file.path()));
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+/// Frames of the segment that is currently being built
+struct OpenSegment {
+    number: usize,
+    /// The segment as it was stored before (when appending) and its number of frames
+    stored: Option<(Vec<u8>, usize)>,
+    /// Frames by their index in the segment
+    frames: BTreeMap<usize, CompressedRainRadarValues>,
+}
+
+/// Collects the frames of one segment at a time and writes them as a [`TimeSeries`]. Frames can
+/// be added in any order within a segment, but once a later segment was started, earlier frames
+/// are rejected.
+pub struct TimeSeriesBuilder {
+    path: std::path::PathBuf,
+    file: std::fs::File,
+    first_time: chrono::NaiveDateTime,
+    interval: chrono::Duration,
+    options: TimeSeriesOptions,
+    /// Known after the first frame
+    grid_size: Option<(usize, usize)>,
+    /// Complete segments that were written already
+    segments: Vec<Segment>,
+    open_segment: Option<OpenSegment>,
+    frames: usize,
+    /// Frames before this one were stored before the builder was opened
+    first_new_frame: usize,
+}
+
+impl TimeSeriesBuilder {
+    /// Frames have to be at `first_time` or a multiple of `interval` later
+    pub fn create<P: AsRef<std::path::Path>>(
+        path: P,
+        first_time: chrono::NaiveDateTime,
+        interval: chrono::Duration,
+    ) -> Result<Self> {
+        Self::create_with_options(path, first_time, interval, &TimeSeriesOptions::default())
+    }
+
+    pub fn create_with_options<P: AsRef<std::path::Path>>(
+        path: P,
+        first_time: chrono::NaiveDateTime,
+        interval: chrono::Duration,
+        options: &TimeSeriesOptions,
+    ) -> Result<Self> {
+        ensure!(
+            interval.num_seconds() > 0 && interval.num_seconds() <= u32::MAX as i64,
+            "Interval {interval} is not supported"
+        );
+        ensure!(
+            (1..=u16::MAX as usize).contains(&options.tile_size)
+                && (1..=u32::MAX as usize).contains(&options.frames_per_segment),
+            "Tile size {} or frames per segment {} not supported",
+            options.tile_size,
+            options.frames_per_segment
+        );
+        let path = path.as_ref().to_owned();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .context("Could not create time series file")?;
+        Ok(Self {
+            path,
+            file,
+            first_time,
+            interval,
+            options: *options,
+            grid_size: None,
+            segments: Vec::new(),
+            open_segment: None,
+            frames: 0,
+            first_new_frame: 0,
+        })
+    }
+
+    /// Opens an existing time series to add frames after its last one
+    pub fn append<P: AsRef<std::path::Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref().to_owned();
+        let mut file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .context("Could not open time series file")?;
+        let mut header = [0u8; HEADER_SIZE as usize];
+        file.read_exact(&mut header)
+            .context("Failed reading time series header")?;
+        let layout = Layout::from_header(&header)?;
+        let mut segments = read_index(&mut file, &layout)?;
+        let frames = segments.iter().map(|segment| segment.frames).sum();
+
+        // the last segment is rewritten together with the new frames if it isn't complete yet
+        let open_segment = match segments.last() {
+            Some(segment) if segment.frames < layout.frames_per_segment => {
+                let segment = segments.pop().unwrap();
+                let mut bytes = vec![0u8; segment.length as usize];
+                file.seek(SeekFrom::Start(segment.position))
+                    .and_then(|_| file.read_exact(&mut bytes))
+                    .context("Failed reading the last segment")?;
+                Some(OpenSegment {
+                    number: segments.len(),
+                    stored: Some((bytes, segment.frames)),
+                    frames: BTreeMap::new(),
+                })
+            }
+            _ => None,
+        };
+        Ok(Self {
+            path,
+            file,
+            first_time: layout.first_time,
+            interval: layout.interval,
+            options: TimeSeriesOptions {
+                tile_size: layout.tile_size,
+                frames_per_segment: layout.frames_per_segment,
+            },
+            grid_size: Some((layout.width, layout.height)),
+            segments,
+            open_segment,
+            frames,
+            first_new_frame: frames,
+        })
+    }
+
+    fn layout(&self) -> Option<Layout> {
+        let (width, height) = self.grid_size?;
+        Some(Layout {
+            first_time: self.first_time,
+            interval: self.interval,
+            width,
+            height,
+            tile_size: self.options.tile_size,
+            frames_per_segment: self.options.frames_per_segment,
+        })
+    }
+
+    fn band_rows(&self) -> usize {
+        self.options.tile_size * usize::max(1, BAND_ROWS / self.options.tile_size)
+    }
+
+    /// Checks that a frame fits into the time series and returns its index
+    fn check_frame<T: RainRadarValues>(&self, values: &T) -> Result<usize> {
+        let time = values.time_information().first_time;
+        let frame = frame_at(self.first_time, self.interval, time)?;
+        ensure!(
+            frame >= self.first_new_frame,
+            "Frame at {time} is already in the time series"
+        );
+        let segment = frame / self.options.frames_per_segment;
+        let open_segment = self
+            .open_segment
+            .as_ref()
+            .map(|open_segment| open_segment.number)
+            .unwrap_or(self.segments.len());
+        ensure!(
+            segment >= open_segment,
+            "Frame at {time} is older than the frames that were written already"
+        );
+        ensure!(
+            self.open_segment.as_ref().is_none_or(|open_segment| {
+                open_segment.number != segment
+                    || !open_segment
+                        .frames
+                        .contains_key(&(frame % self.options.frames_per_segment))
+            }),
+            "Frame at {time} was added already"
+        );
+
+        let grid_information = values.grid_information();
+        if let Some((width, height)) = self.grid_size {
+            ensure!(
+                (width, height) == (grid_information.width, grid_information.height),
+                "Frame at {time} has grid size {}x{} instead of {width}x{height}",
+                grid_information.width,
+                grid_information.height
+            );
+        }
+        Ok(frame)
+    }
+
+    /// Adds the first time slot of a forecast as the frame for its time
+    pub fn add_frame<T: RainRadarValues>(&mut self, values: &T) -> Result<()> {
+        let frame = self.check_frame(values)?;
+        let grid_information = values.grid_information();
+        self.grid_size = Some((grid_information.width, grid_information.height));
+
+        let segment = frame / self.options.frames_per_segment;
+        if self
+            .open_segment
+            .as_ref()
+            .is_some_and(|open_segment| open_segment.number < segment)
+        {
+            self.write_open_segment(self.options.frames_per_segment)?;
+        }
+        // segments without any frames
+        while self.segments.len() < segment && self.open_segment.is_none() {
+            self.segments.push(Segment {
+                position: self.segments_end(),
+                length: 0,
+                frames: self.options.frames_per_segment,
+            });
+        }
+        // keep only the first time slot, compressed in blocks of the rows that are transposed at
+        // once, so every block is only decoded once when writing the segment
+        let compressed = CompressedRainRadarValues::from_rain_radar_values_with_options(
+            values,
+            &EncoderOptions {
+                compression: Compression::Lzma(0),
+                block_size: self.band_rows(),
+                time_slots: Some(1),
+                ..EncoderOptions::default()
+            },
+        );
+        let open_segment = self.open_segment.get_or_insert_with(|| OpenSegment {
+            number: segment,
+            stored: None,
+            frames: BTreeMap::new(),
+        });
+        open_segment
+            .frames
+            .insert(frame % self.options.frames_per_segment, compressed);
+        self.frames = usize::max(self.frames, frame + 1);
+        Ok(())
+    }
+
+    /// Adds the forecasts of an archive that are newer than the frames stored before, forecasts
+    /// that can't be loaded or don't fit are skipped
+    pub fn add_archive(&mut self, archive: &mut Archive) -> Result<Vec<SkippedFrame>> {
+        let first_new_time = self.first_time + self.interval * self.first_new_frame as i32;
+        let entries: Vec<_> = archive
+            .entries()
+            .filter(|entry| entry.base_time >= first_new_time)
+            .copied()
+            .collect();
+        let mut skipped = Vec::new();
+        for entry in &entries {
+            let checked = archive
+                .load(entry)
+                .and_then(|values| self.check_frame(&values).map(|_| values));
+            match checked {
+                Ok(values) => self.add_frame(&values)?,
+                Err(error) => skipped.push(SkippedFrame {
+                    source: entry.base_time.to_string(),
+                    error,
+                }),
+            }
+        }
+        Ok(skipped)
+    }
+
+    /// Adds the files of the downloader (`<directory>/%Y%m%d/%H%M%S.tar.bz2`) that are newer than
+    /// the frames stored before, files that can't be read or don't fit are skipped
+    pub fn add_downloads<P: AsRef<std::path::Path>>(
+        &mut self,
+        directory: P,
+    ) -> Result<Vec<SkippedFrame>> {
+        let first_new_time = self.first_time + self.interval * self.first_new_frame as i32;
+        let mut skipped = Vec::new();
+        for (_, path) in downloads(directory.as_ref())?
+            .into_iter()
+            .filter(|(time, _)| *time >= first_new_time)
+        {
+            let checked = DWDRainRadarValues::from_file(&path)
+                .and_then(|values| self.check_frame(&values).map(|_| values));
+            match checked {
+                Ok(values) => self.add_frame(&values)?,
+                Err(error) => skipped.push(SkippedFrame {
+                    source: path.display().to_string(),
+                    error,
+                }),
+            }
+        }
+        Ok(skipped)
+    }
+
+    fn segments_end(&self) -> u64 {
+        self.segments
+            .last()
+            .map(|segment| segment.position + segment.length)
+            .unwrap_or(HEADER_SIZE)
+    }
+
+    /// Transposes the open segment band by band and writes it with `frames` frames
+    fn write_open_segment(&mut self, frames: usize) -> Result<()> {
+        let Some(open_segment) = self.open_segment.take() else {
+            return Ok(());
+        };
+        let layout = self.layout().context("No frames were added")?;
+        let band_rows = self.band_rows();
+        let table_length = (layout.tiles() + 1) * 4;
+        let position = self.segments_end();
+
+        let bytes = match &open_segment.stored {
+            // nothing changed
+            Some((bytes, stored_frames))
+                if open_segment.frames.is_empty() && *stored_frames == frames =>
+            {
+                bytes.clone()
+            }
+            _ => {
+                let mut starts = vec![0u32];
+                let mut tiles = Vec::new();
+                let mut band = Vec::new();
+                for first_row in (0..layout.height).step_by(band_rows) {
+                    let rows = first_row..usize::min(first_row + band_rows, layout.height);
+                    // values of the band pixel by pixel, all frames of a pixel after another
+                    band.clear();
+                    band.resize(layout.width * rows.len() * frames, u16::MAX);
+                    let band_tiles = (first_row / layout.tile_size * layout.tiles_x())
+                        ..(rows.end.div_ceil(layout.tile_size) * layout.tiles_x());
+
+                    if let Some((bytes, stored_frames)) = open_segment
+                        .stored
+                        .as_ref()
+                        .filter(|(bytes, _)| !bytes.is_empty())
+                    {
+                        for tile in band_tiles.clone() {
+                            let start = u32::from_le_bytes(
+                                bytes[(tile * 4)..(tile * 4 + 4)].try_into().unwrap(),
+                            ) as usize;
+                            let end = u32::from_le_bytes(
+                                bytes[(tile * 4 + 4)..(tile * 4 + 8)].try_into().unwrap(),
+                            ) as usize;
+                            ensure!(
+                                start <= end && table_length + end <= bytes.len(),
+                                "Invalid position of tile {tile} in the last segment"
+                            );
+                            let (columns, tile_rows) = layout.tile_area(tile);
+                            let values = decode_tile(
+                                &bytes[(table_length + start)..(table_length + end)],
+                                columns.len() * tile_rows.len(),
+                                *stored_frames,
+                            )?;
+                            let mut values = values.chunks_exact(*stored_frames);
+                            for y in tile_rows {
+                                for x in columns.clone() {
+                                    let pixel = (y - first_row) * layout.width + x;
+                                    band[(pixel * frames)..(pixel * frames + stored_frames)]
+                                        .copy_from_slice(values.next().unwrap());
+                                }
+                            }
+                        }
+                    }
+
+                    for (frame, values) in &open_segment.frames {
+                        let time = values.time_information().first_time;
+                        for (pixel, value) in values
+                            .for_area(time, 0..layout.width, rows.clone())
+                            .enumerate()
+                        {
+                            band[pixel * frames + frame] = value.unwrap_or(u16::MAX);
+                        }
+                    }
+
+                    for tile in band_tiles {
+                        let (columns, tile_rows) = layout.tile_area(tile);
+                        let band = &band;
+                        tiles.extend(encode_tile(
+                            tile_rows.flat_map(|y| {
+                                columns.clone().flat_map(move |x| {
+                                    let pixel = (y - first_row) * layout.width + x;
+                                    band[(pixel * frames)..((pixel + 1) * frames)]
+                                        .iter()
+                                        .copied()
+                                })
+                            }),
+                            frames,
+                        ));
+                        starts.push(
+                            tiles
+                                .len()
+                                .try_into()
+                                .context("Segment of the time series is too large")?,
+                        );
+                    }
+                }
+                let mut bytes = Vec::with_capacity(table_length + tiles.len());
+                for start in starts {
+                    bytes.extend_from_slice(&start.to_le_bytes());
+                }
+                bytes.extend_from_slice(&tiles);
+                bytes
+            }
+        };
+
+        // drops the old index and, when appending, the previous version of this segment
+        self.file
+            .set_len(position)
+            .and_then(|_| self.file.seek(SeekFrom::Start(position)))
+            .and_then(|_| self.file.write_all(&bytes))
+            .context("Failed writing time series segment")?;
+        self.segments.push(Segment {
+            position,
+            length: bytes.len() as u64,
+            frames,
+        });
+        Ok(())
+    }
+
+    /// Writes the last segment, the header and the index
+    pub fn finish(mut self) -> Result<TimeSeries> {
+        let layout = self.layout().context("No frames were added")?;
+        if let Some(open_segment) = &self.open_segment {
+            let frames = self.frames - open_segment.number * self.options.frames_per_segment;
+            self.write_open_segment(frames)?;
+        }
+
+        let position = self.segments_end();
+        let mut index = Vec::with_capacity(self.segments.len() * INDEX_ENTRY_SIZE + 16);
+        for segment in &self.segments {
+            index.extend_from_slice(&segment.position.to_le_bytes());
+            index.extend_from_slice(&segment.length.to_le_bytes());
+            index.extend_from_slice(&(segment.frames as u32).to_le_bytes());
+            index.extend_from_slice(&[0; 4]);
+        }
+        index.extend_from_slice(&position.to_le_bytes());
+        index.extend_from_slice(INDEX_MAGIC);
+        let header = layout.header()?;
+        self.file
+            .set_len(position)
+            .and_then(|_| self.file.seek(SeekFrom::Start(position)))
+            .and_then(|_| self.file.write_all(&index))
+            .and_then(|_| self.file.seek(SeekFrom::Start(0)))
+            .and_then(|_| self.file.write_all(&header))
+            .and_then(|_| self.file.sync_data())
+            .context("Failed writing time series index")?;
+        TimeSeries::open(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_values::TestRainRadarValues;
+
+    #[test]
+    fn test_time_series() -> Result<()> {
+        let directory = std::env::temp_dir();
+        let path = directory.join(format!(
+            "rain_radar_values_time_series_{}",
+            std::process::id()
+        ));
+        let first_time = chrono::NaiveDate::from_ymd_opt(2022, 5, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let interval = chrono::Duration::minutes(15);
+        // the frames at 12:30, 13:30 and 13:45 are missing, the segment 13:30 - 13:45 is empty
+        let frames: Vec<TestRainRadarValues> = [0, 1, 3, 4, 5, 8, 9]
+            .into_iter()
+            .map(|frame| {
+                TestRainRadarValues::with_size(frame as u64, 90, 70, 2)
+                    .with_first_time(first_time + interval * frame)
+            })
+            .collect();
+        let options = TimeSeriesOptions {
+            tile_size: 8,
+            frames_per_segment: 2,
+        };
+
+        let mut builder =
+            TimeSeriesBuilder::create_with_options(&path, first_time, interval, &options)?;
+        // order does not matter within a segment
+        builder.add_frame(&frames[1])?;
+        builder.add_frame(&frames[0])?;
+        for frame in &frames[2..4] {
+            builder.add_frame(frame)?;
+        }
+        assert!(builder
+            .add_frame(
+                &TestRainRadarValues::with_size(0, 90, 70, 2)
+                    .with_first_time(first_time + chrono::Duration::minutes(5))
+            )
+            .is_err());
+        assert!(builder
+            .add_frame(
+                &TestRainRadarValues::with_size(0, 91, 70, 2)
+                    .with_first_time(first_time + interval * 5)
+            )
+            .is_err());
+        // older segments were written already
+        assert!(builder.add_frame(&frames[0]).is_err());
+        let time_series = builder.finish()?;
+        assert_eq!(time_series.number_of_frames(), 5);
+        drop(time_series);
+
+        // the last segment (frame 4) is completed and extended
+        let mut builder = TimeSeriesBuilder::append(&path)?;
+        assert!(builder.add_frame(&frames[3]).is_err());
+        for frame in &frames[4..] {
+            builder.add_frame(frame)?;
+        }
+        let mut time_series = builder.finish()?;
+        assert_eq!(time_series.number_of_frames(), 10);
+
+        for (x, y) in [(0, 0), (45, 12), (89, 69), (60, 30)] {
+            let history = time_series.history(
+                x,
+                y,
+                first_time - chrono::Duration::hours(1),
+                first_time + chrono::Duration::hours(4),
+            )?;
+            let times: Vec<chrono::NaiveDateTime> = history.iter().map(|(time, _)| *time).collect();
+            assert_eq!(
+                times,
+                (0..10)
+                    .map(|frame| first_time + interval * frame)
+                    .collect::<Vec<_>>()
+            );
+            for missing in [2, 6, 7] {
+                assert_eq!(history[missing].1, None);
+            }
+            for frame in &frames {
+                let time = frame.time_information().first_time;
+                let expected = frame.for_area(time, x..(x + 1), y..(y + 1)).next().unwrap();
+                let index = ((time - first_time).num_minutes() / 15) as usize;
+                assert_eq!(history[index].1, expected);
+            }
+
+            let partial = time_series.history(
+                x,
+                y,
+                first_time + chrono::Duration::minutes(20),
+                first_time + chrono::Duration::minutes(100),
+            )?;
+            assert_eq!(partial, history[2..7]);
+        }
+        assert!(time_series
+            .history(90, 0, first_time, first_time + chrono::Duration::hours(1))
+            .is_err());
+        assert!(time_series
+            .history(0, 70, first_time, first_time + chrono::Duration::hours(1))
+            .is_err());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_time_series_sources() -> Result<()> {
+        let directory = std::env::temp_dir().join(format!(
+            "rain_radar_values_time_series_sources_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&directory)?;
+        let first_time = chrono::NaiveDate::from_ymd_opt(2022, 5, 1)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap();
+        let interval = chrono::Duration::minutes(15);
+
+        let mut archive = Archive::create(directory.join("archive"))?;
+        for minutes in [0, 15, 20, 45] {
+            archive.append(&CompressedRainRadarValues::from_rain_radar_values(
+                &TestRainRadarValues::with_size(minutes as u64, 30, 20, 2)
+                    .with_first_time(first_time + chrono::Duration::minutes(minutes)),
+            ))?;
+        }
+        let path = directory.join("time_series");
+        let (time_series, skipped) = TimeSeries::from_archive(&mut archive, &path, interval)?;
+        assert_eq!(time_series.number_of_frames(), 4);
+        assert_eq!(skipped.len(), 1);
+        assert_eq!(
+            skipped[0].source,
+            (first_time + chrono::Duration::minutes(20)).to_string()
+        );
+        drop(time_series);
+
+        // downloaded files that can't be read are reported, older ones and other files ignored
+        let downloads = directory.join("downloads");
+        for (day, file) in [
+            ("20220501", "114500.tar.bz2"),
+            ("20220501", "130000.tar.bz2"),
+            ("20220501", "notes.txt"),
+            ("bitmaps", "130000.tar.bz2"),
+        ] {
+            std::fs::create_dir_all(downloads.join(day))?;
+            std::fs::write(downloads.join(day).join(file), b"not a forecast")?;
+        }
+        let mut builder = TimeSeriesBuilder::append(&path)?;
+        let skipped = builder.add_downloads(&downloads)?;
+        assert_eq!(skipped.len(), 1);
+        assert!(skipped[0].source.ends_with("130000.tar.bz2"));
+        assert_eq!(builder.finish()?.number_of_frames(), 4);
+        assert!(TimeSeries::from_downloads(&downloads, directory.join("empty"), interval).is_err());
+
+        std::fs::remove_dir_all(&directory)?;
+        Ok(())
+    }
+}